@@ -0,0 +1,52 @@
+//! Compara los dos caminos para descontar ingredientes con muchos dispensers compitiendo por los contenedores.
+//! Se corre con `cargo run --release --example contention`
+use std::{sync::Arc, time::Instant};
+
+use brunograssano_tp1_2023_1c::{
+    builder::CoffeeMakerBuilder, clock::ScaledClock, container::ConsumePath,
+};
+
+const ORDERS_FILE: &str = "tests/consume_all.json";
+const CLOCK_FACTOR: u32 = 50;
+
+fn main() {
+    for consume_path in [ConsumePath::Locked, ConsumePath::LockFree] {
+        let coffee_maker = match CoffeeMakerBuilder::new()
+            .clock(Arc::new(ScaledClock::new(CLOCK_FACTOR)))
+            .consume_path(consume_path)
+            .without_stdout_statistics()
+            .orders_file(ORDERS_FILE)
+            .build()
+        {
+            Ok(coffee_maker) => coffee_maker,
+            Err(err) => {
+                println!("Could not build the coffee maker: {}", err);
+                return;
+            }
+        };
+
+        let started = Instant::now();
+        if let Err(err) = coffee_maker.run() {
+            println!("{:?}: the coffee maker failed: {}", consume_path, err);
+            return;
+        }
+        let wall_time = started.elapsed();
+
+        let processed = coffee_maker.orders_processed().unwrap_or_default();
+        let skipped = coffee_maker.orders_skipped();
+        match coffee_maker.latency_summary() {
+            Ok(summary) => println!(
+                "{:?}: wall time {:?}, processed {}, skipped {}, preparation p50={}us p99={}us max={}us, queue wait p50={}us",
+                consume_path,
+                wall_time,
+                processed,
+                skipped,
+                summary.preparation.p50,
+                summary.preparation.p99,
+                summary.preparation.max,
+                summary.queue_wait.p50
+            ),
+            Err(err) => println!("{:?}: could not read the latencies: {}", consume_path, err),
+        }
+    }
+}
@@ -0,0 +1,806 @@
+//! Motor alternativo de la cafetera basado en actores. Los contenedores, los reponedores, los dispensers,
+//! el despachante de pedidos y el recolector de estadisticas son hilos que solo se comunican por canales,
+//! sin locks ni variables condicionales compartidas.
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use log::{debug, error, info};
+
+use crate::{
+    clock::Clock,
+    constants::{MINIMUM_WAIT_TIME_REPLENISHER, STATISTICS_WAIT_IN_MS},
+    container::{Container, ContainerLevel},
+    control::{ShutdownMode, ShutdownSignal},
+    errors::CoffeeMakerError,
+    ingredient_order::IngredientOrder,
+    latency::LatencyHistograms,
+    order::{Ingredient, Order, ALL_INGREDIENTS},
+    order_source::{OrderIngredients, OrderSource},
+    scheduling::SchedulingPolicy,
+    statistics::{ContainerStatistics, StatisticsPrinter, StatisticsSnapshot},
+};
+
+/// Motor con el que la cafetera procesa los pedidos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Estado compartido con `Mutex` y variables condicionales. Es el motor por defecto
+    Shared,
+    /// Actores que se comunican por canales
+    Actors,
+    /// Tareas de un runtime asincronico, permite miles de dispensers sin un hilo por cada uno
+    Async,
+}
+
+impl FromStr for Engine {
+    type Err = CoffeeMakerError;
+
+    /// Acepta `shared`, `actors` y `async`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "shared" => Ok(Engine::Shared),
+            "actors" => Ok(Engine::Actors),
+            "async" => Ok(Engine::Async),
+            _ => Err(CoffeeMakerError::InvalidEngine(value.to_string())),
+        }
+    }
+}
+
+/// Mensajes que recibe el despachante de pedidos
+enum DispatcherMessage {
+    /// Pedido leido de una fuente
+    Order(OrderIngredients),
+    /// Una fuente se agoto
+    SourceFinished,
+    /// El dispenser indicado esta libre para tomar un pedido
+    Ready(usize),
+}
+
+/// Mensajes que recibe un contenedor
+enum ContainerMessage {
+    /// Un dispenser pide un ingrediente. Si no alcanza y el contenedor tiene reponedor, se responde luego de la recarga
+    Take {
+        quantity: u64,
+        reply: Sender<Taken>,
+    },
+    /// Lo que se uso de un pedido que se salteo
+    Waste(u64),
+    /// Un reponedor toma hasta `quantity` para llevarlo a otro contenedor. Se responde lo que se tomo y si quedo vacio
+    Withdraw {
+        quantity: u64,
+        reply: Sender<(u64, bool)>,
+    },
+    /// Termino una recarga. Si no es externa, `source_is_empty` indica si se agoto la fuente del reponedor
+    Refilled {
+        quantity: u64,
+        external: bool,
+        source_is_empty: bool,
+    },
+    Level(Sender<ContainerLevel>),
+    Stop,
+}
+
+/// Respuesta a un pedido de ingrediente
+enum Taken {
+    Served { waited: bool },
+    NotEnough,
+}
+
+/// Mensajes que recibe un reponedor
+enum ReplenisherMessage {
+    /// El contenedor destino necesita una recarga, tiene `remaining`
+    RefillNeeded {
+        remaining: u64,
+    },
+    Stop,
+}
+
+/// Mensajes que recibe el recolector de estadisticas
+enum StatisticsMessage {
+    Container(ContainerStatistics),
+    OrderServed,
+    OrderSkipped,
+    QueueWait(Duration),
+    Preparation(Duration),
+    ReplenishmentWait(Duration),
+    Finish,
+}
+
+/// Estado final de una ejecucion de un motor alternativo, se vuelca sobre la cafetera al terminar
+pub struct EngineRun {
+    pub containers: HashMap<Ingredient, Container>,
+    pub orders_processed: u64,
+    pub orders_skipped: u64,
+    pub latencies: LatencyHistograms,
+}
+
+/// Estado de la cafetera y configuracion con la que arranca un motor alternativo.
+/// Los reportes de estadisticas se entregan a los sinks del `StatisticsPrinter` de la cafetera.
+pub struct EngineSetup {
+    pub(crate) containers: HashMap<Ingredient, Container>,
+    pub(crate) container_replenishers: Vec<(Ingredient, Ingredient)>,
+    pub(crate) external_replenishers: Vec<Ingredient>,
+    pub(crate) dispensers: usize,
+    pub(crate) pending_orders: Vec<Order>,
+    pub(crate) next_id: usize,
+    pub(crate) orders_processed: u64,
+    pub(crate) ingredient_order: Arc<IngredientOrder>,
+    pub(crate) statistics_printer: Arc<StatisticsPrinter>,
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Finalizacion pedida con `ControlHandle::shutdown`. Las fuentes dejan de leerse y con `Abort`
+    /// se descartan los pedidos que no se empezaron a preparar
+    pub(crate) shutdown: Arc<ShutdownSignal>,
+}
+
+/// Arma los actores a partir de `setup`, procesa los pedidos de las fuentes y devuelve el estado final
+pub fn run(
+    setup: EngineSetup,
+    sources: Vec<Box<dyn OrderSource>>,
+) -> Result<EngineRun, CoffeeMakerError> {
+    let (statistics, statistics_inbox) = mpsc::channel();
+    let statistics_actor = StatisticsActor {
+        printer: setup.statistics_printer.clone(),
+        containers: ALL_INGREDIENTS
+            .into_iter()
+            .filter_map(|ingredient| {
+                let container = setup.containers.get(&ingredient)?;
+                Some(container_statistics(ingredient, container))
+            })
+            .collect(),
+        orders_processed: setup.orders_processed,
+        orders_skipped: 0,
+        latencies: LatencyHistograms::new(),
+        clock: setup.clock.clone(),
+    };
+    let statistics_thread = thread::spawn(move || statistics_actor.run(statistics_inbox));
+
+    let mut container_inboxes = HashMap::new();
+    let mut containers = HashMap::new();
+    for ingredient in setup.containers.keys() {
+        let (sender, inbox) = mpsc::channel();
+        containers.insert(*ingredient, sender);
+        container_inboxes.insert(*ingredient, inbox);
+    }
+
+    let mut replenishers = HashMap::new();
+    let replenished = setup
+        .container_replenishers
+        .iter()
+        .map(|(source, dest)| (*dest, Some(*source)))
+        .chain(setup.external_replenishers.iter().map(|dest| (*dest, None)));
+    for (dest, source) in replenished {
+        let (Some(dest_container), Some(container)) =
+            (containers.get(&dest), setup.containers.get(&dest))
+        else {
+            continue;
+        };
+        let source = match source.map(|source| (source, containers.get(&source))) {
+            Some((source, Some(source_container))) => Some((source, source_container.clone())),
+            Some((_, None)) => continue,
+            None => None,
+        };
+        let actor = ReplenisherActor {
+            dest,
+            dest_container: dest_container.clone(),
+            source,
+            max_storage: container.capacity(),
+            clock: setup.clock.clone(),
+        };
+        let (sender, inbox) = mpsc::channel();
+        replenishers.insert(dest, (sender, thread::spawn(move || actor.run(inbox))));
+    }
+
+    let mut container_threads = Vec::new();
+    for (ingredient, container) in setup.containers {
+        let actor = ContainerActor {
+            ingredient,
+            container,
+            replenisher: replenishers
+                .get(&ingredient)
+                .map(|(sender, _)| sender.clone()),
+            statistics: statistics.clone(),
+            waiting: VecDeque::new(),
+            refilling: false,
+        };
+        let inbox = container_inboxes
+            .remove(&ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))?;
+        container_threads.push((ingredient, thread::spawn(move || actor.run(inbox))));
+    }
+
+    let (dispatcher, dispatcher_inbox) = mpsc::channel();
+    let mut dispenser_senders = Vec::with_capacity(setup.dispensers);
+    let mut dispenser_threads = Vec::with_capacity(setup.dispensers);
+    for id in 0..setup.dispensers {
+        let (sender, inbox) = mpsc::channel();
+        let actor = DispenserActor {
+            id,
+            dispatcher: dispatcher.clone(),
+            containers: containers.clone(),
+            statistics: statistics.clone(),
+            ingredient_order: setup.ingredient_order.clone(),
+            clock: setup.clock.clone(),
+        };
+        dispenser_senders.push(sender);
+        dispenser_threads.push(thread::spawn(move || actor.run(inbox)));
+    }
+    let dispatcher_actor = Dispatcher {
+        dispensers: dispenser_senders,
+        pending: setup.pending_orders.into(),
+        idle: VecDeque::new(),
+        open_sources: sources.len(),
+        next_id: setup.next_id,
+        shutdown: setup.shutdown.clone(),
+    };
+    let dispatcher_thread = thread::spawn(move || dispatcher_actor.run(dispatcher_inbox));
+
+    let reader_threads = sources
+        .into_iter()
+        .map(|source| {
+            let dispatcher = dispatcher.clone();
+            let shutdown = setup.shutdown.clone();
+            thread::spawn(move || read_orders(source, dispatcher, &shutdown))
+        })
+        .collect::<Vec<JoinHandle<()>>>();
+    drop(dispatcher);
+
+    for reader in reader_threads {
+        if let Err(err) = reader.join() {
+            println!("[ERROR ON READER] {:?}", err);
+        }
+    }
+    for dispenser in dispenser_threads {
+        match dispenser.join() {
+            Ok(Err(err)) => println!("[ERROR ON DISPENSER] {:?}", err),
+            Err(err) => println!("[ERROR ON DISPENSER] {:?}", err),
+            Ok(Ok(())) => {}
+        }
+    }
+    if let Err(err) = dispatcher_thread.join() {
+        println!("[ERROR ON DISPATCHER] {:?}", err);
+    }
+
+    // Los reponedores se detienen antes que los contenedores, asi ninguna recarga queda sin entregar
+    for (_, (sender, thread)) in replenishers {
+        _ = sender.send(ReplenisherMessage::Stop);
+        if let Err(err) = thread.join() {
+            println!("[ERROR ON REPLENISHER] {:?}", err);
+        }
+    }
+    let mut final_containers = HashMap::new();
+    for (ingredient, thread) in container_threads {
+        _ = containers[&ingredient].send(ContainerMessage::Stop);
+        let container = thread.join().map_err(|_| CoffeeMakerError::ChannelClosed)?;
+        final_containers.insert(ingredient, container);
+    }
+
+    _ = statistics.send(StatisticsMessage::Finish);
+    let (orders_processed, orders_skipped, latencies) = statistics_thread
+        .join()
+        .map_err(|_| CoffeeMakerError::ChannelClosed)?;
+    Ok(EngineRun {
+        containers: final_containers,
+        orders_processed,
+        orders_skipped,
+        latencies,
+    })
+}
+
+/// Lleva los pedidos de una fuente al despachante hasta que se agote o se pida finalizar.
+/// Siempre avisa cuando la fuente se agota
+fn read_orders(
+    mut source: Box<dyn OrderSource>,
+    dispatcher: Sender<DispatcherMessage>,
+    shutdown: &ShutdownSignal,
+) {
+    let stopped = || shutdown.requested().is_some();
+    loop {
+        if stopped() {
+            info!("[READER] Shutdown requested, stopping {}", source.name());
+            break;
+        }
+        match source.next_order_until(&stopped) {
+            Ok(Some(order)) => {
+                if dispatcher
+                    .send(DispatcherMessage::Order(order.ingredients))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                error!("[READER] Error reading from {}: {:?}", source.name(), err);
+                break;
+            }
+        }
+    }
+    _ = dispatcher.send(DispatcherMessage::SourceFinished);
+}
+
+/// Reparte los pedidos entre los dispensers libres, en orden de llegada.
+/// Cuando se agotaron todas las fuentes y no quedan pedidos, detiene a los dispensers.
+/// Si se pide `Abort` descarta los pedidos pendientes y los que sigan llegando
+struct Dispatcher {
+    dispensers: Vec<Sender<Option<Order>>>,
+    pending: VecDeque<Order>,
+    idle: VecDeque<usize>,
+    open_sources: usize,
+    next_id: usize,
+    shutdown: Arc<ShutdownSignal>,
+}
+
+impl Dispatcher {
+    fn run(mut self, inbox: Receiver<DispatcherMessage>) {
+        let mut stopped = 0;
+        while stopped < self.dispensers.len() {
+            match inbox.recv() {
+                Ok(DispatcherMessage::Order(ingredients)) => {
+                    self.pending
+                        .push_back(Order::new(self.next_id, ingredients));
+                    self.next_id += 1;
+                }
+                Ok(DispatcherMessage::SourceFinished) => {
+                    self.open_sources = self.open_sources.saturating_sub(1)
+                }
+                Ok(DispatcherMessage::Ready(id)) => self.idle.push_back(id),
+                Err(_) => return,
+            }
+            if self.shutdown.requested() == Some(ShutdownMode::Abort) && !self.pending.is_empty() {
+                info!(
+                    "[DISPATCHER] Aborting, discarded {} orders",
+                    self.pending.len()
+                );
+                self.pending.clear();
+            }
+            while !self.pending.is_empty() {
+                let Some(id) = self.idle.pop_front() else {
+                    break;
+                };
+                _ = self.dispensers[id].send(self.pending.pop_front());
+            }
+            if self.open_sources == 0 && self.pending.is_empty() {
+                for id in self.idle.drain(..) {
+                    _ = self.dispensers[id].send(None);
+                    stopped += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Dispenser que pide cada ingrediente al actor de su contenedor y espera la respuesta
+struct DispenserActor {
+    id: usize,
+    dispatcher: Sender<DispatcherMessage>,
+    containers: HashMap<Ingredient, Sender<ContainerMessage>>,
+    statistics: Sender<StatisticsMessage>,
+    ingredient_order: Arc<IngredientOrder>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DispenserActor {
+    fn run(self, inbox: Receiver<Option<Order>>) -> Result<(), CoffeeMakerError> {
+        loop {
+            self.dispatcher
+                .send(DispatcherMessage::Ready(self.id))
+                .map_err(|_| CoffeeMakerError::ChannelClosed)?;
+            let Some(order) = inbox.recv().map_err(|_| CoffeeMakerError::ChannelClosed)? else {
+                return Ok(());
+            };
+            debug!("[DISPENSER {}] Takes order {}", self.id, order.id);
+            self.send_statistics(StatisticsMessage::QueueWait(
+                self.clock.local_now().saturating_sub(order.created_at),
+            ))?;
+            self.process_order(order)?;
+        }
+    }
+
+    fn process_order(&self, mut order: Order) -> Result<(), CoffeeMakerError> {
+        let preparation_start = self.clock.local_now();
+        self.ingredient_order
+            .sort_with(order.id, &mut order.ingredients, || self.levels())?;
+        let mut used = Vec::with_capacity(order.ingredients.len());
+        for (ingredient, quantity) in order.ingredients {
+            let container = self.container(&ingredient)?;
+            let (reply, answer) = mpsc::channel();
+            let requested_at = self.clock.local_now();
+            container
+                .send(ContainerMessage::Take { quantity, reply })
+                .map_err(|_| CoffeeMakerError::ChannelClosed)?;
+            match answer.recv().map_err(|_| CoffeeMakerError::ChannelClosed)? {
+                Taken::Served { waited } => {
+                    if waited {
+                        self.send_statistics(StatisticsMessage::ReplenishmentWait(
+                            self.clock.local_now().saturating_sub(requested_at),
+                        ))?;
+                    }
+                    self.clock.sleep(Duration::from_millis(quantity));
+                    used.push((ingredient, quantity));
+                }
+                Taken::NotEnough => {
+                    info!(
+                        "[DISPENSER {}] Skipped order {}, not enough {:?}",
+                        self.id, order.id, ingredient
+                    );
+                    for (ingredient, quantity) in used {
+                        _ = self
+                            .container(&ingredient)?
+                            .send(ContainerMessage::Waste(quantity));
+                    }
+                    return self.send_statistics(StatisticsMessage::OrderSkipped);
+                }
+            }
+        }
+        self.send_statistics(StatisticsMessage::OrderServed)?;
+        self.send_statistics(StatisticsMessage::Preparation(
+            self.clock.local_now().saturating_sub(preparation_start),
+        ))
+    }
+
+    fn levels(&self) -> Result<Vec<ContainerLevel>, CoffeeMakerError> {
+        let mut levels = Vec::with_capacity(ALL_INGREDIENTS.len());
+        for ingredient in ALL_INGREDIENTS {
+            if let Some(container) = self.containers.get(&ingredient) {
+                let (reply, answer) = mpsc::channel();
+                container
+                    .send(ContainerMessage::Level(reply))
+                    .map_err(|_| CoffeeMakerError::ChannelClosed)?;
+                levels.push(answer.recv().map_err(|_| CoffeeMakerError::ChannelClosed)?);
+            }
+        }
+        Ok(levels)
+    }
+
+    fn container(
+        &self,
+        ingredient: &Ingredient,
+    ) -> Result<&Sender<ContainerMessage>, CoffeeMakerError> {
+        self.containers
+            .get(ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(*ingredient))
+    }
+
+    fn send_statistics(&self, message: StatisticsMessage) -> Result<(), CoffeeMakerError> {
+        self.statistics
+            .send(message)
+            .map_err(|_| CoffeeMakerError::ChannelClosed)
+    }
+}
+
+/// Unico duenio del estado de un contenedor. Atiende los pedidos de ingredientes en orden de llegada;
+/// mientras se recarga, los pedidos esperan en `waiting`
+struct ContainerActor {
+    ingredient: Ingredient,
+    container: Container,
+    replenisher: Option<Sender<ReplenisherMessage>>,
+    statistics: Sender<StatisticsMessage>,
+    waiting: VecDeque<(u64, Sender<Taken>, bool)>,
+    refilling: bool,
+}
+
+impl ContainerActor {
+    fn run(mut self, inbox: Receiver<ContainerMessage>) -> Container {
+        while let Ok(message) = inbox.recv() {
+            match message {
+                ContainerMessage::Take { quantity, reply } => {
+                    self.waiting.push_back((quantity, reply, false));
+                    self.serve_waiting();
+                }
+                ContainerMessage::Waste(quantity) => self.container.wasted += quantity,
+                ContainerMessage::Withdraw { quantity, reply } => {
+                    let withdrawn = self.container.level.consume_up_to(quantity);
+                    self.container.transferred += withdrawn;
+                    _ = reply.send((withdrawn, self.container.is_empty()));
+                    self.report();
+                }
+                ContainerMessage::Refilled {
+                    quantity,
+                    external,
+                    source_is_empty,
+                } => {
+                    self.container.level.fill(quantity);
+                    if external {
+                        self.container.supplied += quantity;
+                    } else {
+                        self.container.finished = source_is_empty;
+                    }
+                    self.refilling = false;
+                    self.report();
+                    self.serve_waiting();
+                }
+                ContainerMessage::Level(reply) => {
+                    _ = reply.send(ContainerLevel {
+                        ingredient: self.ingredient,
+                        remaining: self.container.remaining(),
+                        capacity: self.container.capacity(),
+                    });
+                }
+                ContainerMessage::Stop => break,
+            }
+        }
+        for (_, reply, _) in self.waiting.drain(..) {
+            _ = reply.send(Taken::NotEnough);
+        }
+        self.container
+    }
+
+    /// Atiende los pedidos que esperan. Si el primero no alcanza, pide una recarga y el resto sigue esperando
+    fn serve_waiting(&mut self) {
+        while !self.refilling {
+            let Some((quantity, _, _)) = self.waiting.front() else {
+                return;
+            };
+            let quantity = *quantity;
+            // El actor es el unico que cambia el nivel, nadie mas lo puede consumir en el medio
+            if self.container.level.try_consume(quantity) {
+                if let Some((_, reply, waited)) = self.waiting.pop_front() {
+                    _ = reply.send(Taken::Served { waited });
+                    self.report();
+                }
+                continue;
+            }
+            let replenisher = match &self.replenisher {
+                Some(replenisher) if !self.container.finished => replenisher,
+                _ => {
+                    if let Some((_, reply, _)) = self.waiting.pop_front() {
+                        _ = reply.send(Taken::NotEnough);
+                    }
+                    continue;
+                }
+            };
+            info!(
+                "[CONTAINER] Not enough {:?} for this order, asking for a refill",
+                self.ingredient
+            );
+            let request = ReplenisherMessage::RefillNeeded {
+                remaining: self.container.remaining(),
+            };
+            if replenisher.send(request).is_err() {
+                self.replenisher = None;
+                continue;
+            }
+            self.refilling = true;
+            if let Some((_, _, waited)) = self.waiting.front_mut() {
+                *waited = true;
+            }
+        }
+    }
+
+    fn report(&self) {
+        _ = self
+            .statistics
+            .send(StatisticsMessage::Container(container_statistics(
+                self.ingredient,
+                &self.container,
+            )));
+    }
+}
+
+/// Recarga un contenedor cuando este se lo pide, desde otro contenedor o desde una fuente externa
+struct ReplenisherActor {
+    dest: Ingredient,
+    dest_container: Sender<ContainerMessage>,
+    source: Option<(Ingredient, Sender<ContainerMessage>)>,
+    max_storage: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl ReplenisherActor {
+    fn run(self, inbox: Receiver<ReplenisherMessage>) {
+        while let Ok(ReplenisherMessage::RefillNeeded { remaining }) = inbox.recv() {
+            let wanted = self.max_storage.saturating_sub(remaining);
+            let (quantity, source_is_empty) = match &self.source {
+                Some((_, source)) => {
+                    let (reply, answer) = mpsc::channel();
+                    let request = ContainerMessage::Withdraw {
+                        quantity: wanted,
+                        reply,
+                    };
+                    if source.send(request).is_err() {
+                        return;
+                    }
+                    answer.recv().unwrap_or((0, true))
+                }
+                None => (wanted, false),
+            };
+            self.clock.sleep(Duration::from_millis(
+                MINIMUM_WAIT_TIME_REPLENISHER + quantity,
+            ));
+            match &self.source {
+                Some((source, _)) => info!(
+                    "[REPLENISHER] Replenished {:?} with {} of {:?}",
+                    self.dest, quantity, source
+                ),
+                None => info!(
+                    "[REPLENISHER] Replenished {:?} with {} from external source",
+                    self.dest, quantity
+                ),
+            }
+            let refilled = ContainerMessage::Refilled {
+                quantity,
+                external: self.source.is_none(),
+                source_is_empty,
+            };
+            if self.dest_container.send(refilled).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Arma las estadisticas a partir de los mensajes de los demas actores y las reporta periodicamente
+struct StatisticsActor {
+    printer: Arc<StatisticsPrinter>,
+    containers: Vec<ContainerStatistics>,
+    orders_processed: u64,
+    orders_skipped: u64,
+    latencies: LatencyHistograms,
+    clock: Arc<dyn Clock>,
+}
+
+impl StatisticsActor {
+    fn run(mut self, inbox: Receiver<StatisticsMessage>) -> (u64, u64, LatencyHistograms) {
+        let mut tick = 0;
+        loop {
+            let finished = self.receive(&inbox);
+            self.printer.report(&self.snapshot(tick), finished);
+            if finished {
+                return (self.orders_processed, self.orders_skipped, self.latencies);
+            }
+            tick += 1;
+            self.clock
+                .wait(Duration::from_millis(STATISTICS_WAIT_IN_MS));
+        }
+    }
+
+    /// Procesa los mensajes recibidos hasta el momento. Devuelve si se le indico que finalice
+    fn receive(&mut self, inbox: &Receiver<StatisticsMessage>) -> bool {
+        loop {
+            match inbox.try_recv() {
+                Ok(StatisticsMessage::Container(statistics)) => {
+                    if let Some(container) = self
+                        .containers
+                        .iter_mut()
+                        .find(|container| container.ingredient == statistics.ingredient)
+                    {
+                        *container = statistics;
+                    }
+                }
+                Ok(StatisticsMessage::OrderServed) => self.orders_processed += 1,
+                Ok(StatisticsMessage::OrderSkipped) => self.orders_skipped += 1,
+                Ok(StatisticsMessage::QueueWait(duration)) => {
+                    self.latencies.record_queue_wait(duration)
+                }
+                Ok(StatisticsMessage::Preparation(duration)) => {
+                    self.latencies.record_preparation(duration)
+                }
+                Ok(StatisticsMessage::ReplenishmentWait(duration)) => {
+                    self.latencies.record_replenishment_wait(duration)
+                }
+                Ok(StatisticsMessage::Finish) => return true,
+                Err(mpsc::TryRecvError::Empty) => return false,
+                Err(mpsc::TryRecvError::Disconnected) => return true,
+            }
+        }
+    }
+
+    fn snapshot(&self, tick: u64) -> StatisticsSnapshot {
+        StatisticsSnapshot {
+            tick,
+            elapsed_ms: self.clock.now().as_millis() as u64,
+            orders_processed: self.orders_processed,
+            containers: self.containers.clone(),
+            paused_dispensers: Vec::new(),
+            latencies: self.latencies.summary(),
+            scheduling: SchedulingPolicy::Fifo,
+            incidents: Vec::new(),
+        }
+    }
+}
+
+fn container_statistics(ingredient: Ingredient, container: &Container) -> ContainerStatistics {
+    ContainerStatistics {
+        ingredient,
+        remaining: container.remaining(),
+        consumed: container.consumed(),
+        capacity: container.capacity(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_the_engine() {
+        assert_eq!(Ok(Engine::Shared), "shared".parse::<Engine>());
+        assert_eq!(Ok(Engine::Actors), "actors".parse::<Engine>());
+        assert_eq!(Ok(Engine::Async), "async".parse::<Engine>());
+        assert_eq!(
+            Err(CoffeeMakerError::InvalidEngine(String::from("threads"))),
+            "threads".parse::<Engine>()
+        );
+    }
+
+    #[test]
+    fn should_dispatch_the_orders_in_order_and_stop_the_dispensers_when_the_sources_finish() {
+        let (sender, inbox) = mpsc::channel();
+        let (dispenser, orders) = mpsc::channel();
+        let dispatcher = Dispatcher {
+            dispensers: vec![dispenser],
+            pending: VecDeque::new(),
+            idle: VecDeque::new(),
+            open_sources: 1,
+            next_id: 5,
+            shutdown: Arc::new(ShutdownSignal::default()),
+        };
+        let handle = thread::spawn(move || dispatcher.run(inbox));
+
+        for quantity in [10, 20] {
+            sender
+                .send(DispatcherMessage::Order(vec![(
+                    Ingredient::Cacao,
+                    quantity,
+                )]))
+                .expect("Error in test");
+        }
+        sender
+            .send(DispatcherMessage::SourceFinished)
+            .expect("Error in test");
+        for expected_id in [5, 6] {
+            sender
+                .send(DispatcherMessage::Ready(0))
+                .expect("Error in test");
+            let order = orders
+                .recv()
+                .expect("Error in test")
+                .expect("Error in test");
+            assert_eq!(expected_id, order.id);
+        }
+        sender
+            .send(DispatcherMessage::Ready(0))
+            .expect("Error in test");
+        assert!(orders.recv().expect("Error in test").is_none());
+        handle.join().expect("Error in test");
+    }
+
+    #[test]
+    fn should_discard_the_pending_orders_when_aborted() {
+        let (sender, inbox) = mpsc::channel();
+        let (dispenser, orders) = mpsc::channel();
+        let shutdown = Arc::new(ShutdownSignal::default());
+        let dispatcher = Dispatcher {
+            dispensers: vec![dispenser],
+            pending: VecDeque::from([
+                Order::new(0, vec![(Ingredient::Cacao, 10)]),
+                Order::new(1, vec![(Ingredient::Cacao, 20)]),
+            ]),
+            idle: VecDeque::new(),
+            open_sources: 1,
+            next_id: 2,
+            shutdown: shutdown.clone(),
+        };
+        let handle = thread::spawn(move || dispatcher.run(inbox));
+
+        shutdown.request(ShutdownMode::Abort);
+        sender
+            .send(DispatcherMessage::Ready(0))
+            .expect("Error in test");
+        sender
+            .send(DispatcherMessage::Order(vec![(Ingredient::Cacao, 30)]))
+            .expect("Error in test");
+        sender
+            .send(DispatcherMessage::SourceFinished)
+            .expect("Error in test");
+        assert!(orders.recv().expect("Error in test").is_none());
+        handle.join().expect("Error in test");
+    }
+}
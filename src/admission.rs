@@ -0,0 +1,286 @@
+//! Control de admision de los pedidos. Rechaza los pedidos que no se van a poder preparar nunca,
+//! antes de que un dispenser consuma alguno de sus ingredientes
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::{
+    container::Resources, errors::CoffeeMakerError, order::Ingredient, poison::LockOrRecover,
+};
+
+/// Motivo por el que se rechazo un pedido
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rejection {
+    pub ingredient: Ingredient,
+    /// Cantidad que pide el pedido
+    pub required: u64,
+    /// Lo maximo que se puede llegar a obtener del ingrediente para un pedido
+    pub obtainable: u64,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "needs {} of {:?} but at most {} can be obtained",
+            self.required, self.ingredient, self.obtainable
+        )
+    }
+}
+
+/// De donde se repone un contenedor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Supply {
+    /// De otro contenedor, que se puede agotar
+    Container(Ingredient),
+    /// De una fuente que no se agota
+    External,
+}
+
+/// Forma en la que se repone cada contenedor que tiene reponedor
+pub(crate) fn supplies(
+    container_replenishers: &[(Ingredient, Ingredient)],
+    external_replenishers: &[Ingredient],
+) -> HashMap<Ingredient, Supply> {
+    container_replenishers
+        .iter()
+        .map(|(source, dest)| (*dest, Supply::Container(*source)))
+        .chain(
+            external_replenishers
+                .iter()
+                .map(|ingredient| (*ingredient, Supply::External)),
+        )
+        .collect()
+}
+
+/// Revisa que alcance el stock que se puede llegar a obtener de cada ingrediente del pedido: lo que queda en el
+/// contenedor mas lo que le puede pasar su reponedor, sin superar la capacidad del contenedor.
+/// El stock solo baja mientras la cafetera funciona (salvo que un operador recargue un contenedor),
+/// por lo que un pedido rechazado no se iba a poder preparar. Deshabilitado acepta todos los pedidos
+pub struct AdmissionControl {
+    enabled: bool,
+    resources: Arc<Resources>,
+    supplies: HashMap<Ingredient, Supply>,
+}
+
+impl AdmissionControl {
+    pub fn new(
+        resources: Arc<Resources>,
+        container_replenishers: &[(Ingredient, Ingredient)],
+        external_replenishers: &[Ingredient],
+    ) -> AdmissionControl {
+        AdmissionControl {
+            enabled: true,
+            resources,
+            supplies: supplies(container_replenishers, external_replenishers),
+        }
+    }
+
+    pub fn disabled() -> AdmissionControl {
+        AdmissionControl {
+            enabled: false,
+            resources: Arc::new(HashMap::new()),
+            supplies: HashMap::new(),
+        }
+    }
+
+    /// Devuelve el motivo por el que se rechaza el pedido, o `None` si se puede llegar a preparar
+    pub fn check(
+        &self,
+        ingredients: &[(Ingredient, u64)],
+    ) -> Result<Option<Rejection>, CoffeeMakerError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        for (ingredient, required) in ingredients {
+            let obtainable = self.obtainable(*ingredient)?;
+            if *required > obtainable {
+                return Ok(Some(Rejection {
+                    ingredient: *ingredient,
+                    required: *required,
+                    obtainable,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Lo maximo que se puede llegar a obtener del ingrediente para un pedido.
+    /// Se mantiene tomado el destino mientras se lee la fuente, en el mismo orden que los toma el reponedor,
+    /// asi no se cuela una transferencia entre las dos lecturas
+    pub fn obtainable(&self, ingredient: Ingredient) -> Result<u64, CoffeeMakerError> {
+        let container = self
+            .resources
+            .get(&ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))?
+            .lock_or_recover()?;
+        let remaining = container.remaining();
+        let capacity = container.level.capacity();
+        if !container.replenishable || container.finished {
+            return Ok(remaining);
+        }
+        match self.supplies.get(&ingredient) {
+            None => Ok(remaining),
+            Some(Supply::External) => Ok(capacity),
+            Some(Supply::Container(source)) => {
+                let source_remaining = self
+                    .resources
+                    .get(source)
+                    .ok_or(CoffeeMakerError::IngredientNotInMap(*source))?
+                    .lock_or_recover()?
+                    .remaining();
+                Ok((remaining + source_remaining).min(capacity))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        thread,
+    };
+
+    use crate::container::Container;
+
+    use super::*;
+
+    fn admission_control(
+        levels: &[(Ingredient, u64, u64)],
+        container_replenishers: &[(Ingredient, Ingredient)],
+        external_replenishers: &[Ingredient],
+    ) -> AdmissionControl {
+        let resources: Resources = levels
+            .iter()
+            .map(|(ingredient, capacity, remaining)| {
+                let mut container = Container::new(*capacity);
+                container.level.set_remaining(*remaining);
+                container.replenishable = container_replenishers
+                    .iter()
+                    .any(|(_, dest)| dest == ingredient)
+                    || external_replenishers.contains(ingredient);
+                (*ingredient, Arc::new(Mutex::new(container)))
+            })
+            .collect();
+        AdmissionControl::new(
+            Arc::new(resources),
+            container_replenishers,
+            external_replenishers,
+        )
+    }
+
+    #[test]
+    fn should_count_the_stock_of_the_source_container() {
+        let admission = admission_control(
+            &[
+                (Ingredient::GroundCoffee, 100, 10),
+                (Ingredient::GrainsToGrind, 100, 30),
+            ],
+            &[(Ingredient::GrainsToGrind, Ingredient::GroundCoffee)],
+            &[],
+        );
+
+        assert_eq!(
+            40,
+            admission
+                .obtainable(Ingredient::GroundCoffee)
+                .expect("Error in test")
+        );
+        assert_eq!(
+            None,
+            admission
+                .check(&[(Ingredient::GroundCoffee, 40)])
+                .expect("Error in test")
+        );
+        assert_eq!(
+            Some(Rejection {
+                ingredient: Ingredient::GroundCoffee,
+                required: 41,
+                obtainable: 40,
+            }),
+            admission
+                .check(&[(Ingredient::GroundCoffee, 41)])
+                .expect("Error in test")
+        );
+    }
+
+    #[test]
+    fn should_reject_orders_above_what_is_left_of_an_ingredient_without_replenisher() {
+        let admission = admission_control(
+            &[(Ingredient::Cacao, 100, 0), (Ingredient::HotWater, 100, 0)],
+            &[],
+            &[Ingredient::HotWater],
+        );
+
+        let rejection = admission
+            .check(&[(Ingredient::HotWater, 100), (Ingredient::Cacao, 10)])
+            .expect("Error in test")
+            .expect("Error in test");
+        assert_eq!(Ingredient::Cacao, rejection.ingredient);
+        assert_eq!(
+            "needs 10 of Cacao but at most 0 can be obtained",
+            rejection.to_string()
+        );
+    }
+
+    #[test]
+    fn should_not_miss_the_stock_moved_by_a_concurrent_transfer() {
+        let mut dest = Container::new(1000);
+        dest.level.set_remaining(0);
+        dest.replenishable = true;
+        let source = Container::new(1000);
+        source.level.set_remaining(500);
+        let dest_lock = Arc::new(Mutex::new(dest));
+        let source_lock = Arc::new(Mutex::new(source));
+        let resources: Resources = HashMap::from([
+            (Ingredient::GroundCoffee, dest_lock.clone()),
+            (Ingredient::GrainsToGrind, source_lock.clone()),
+        ]);
+        let admission = AdmissionControl::new(
+            Arc::new(resources),
+            &[(Ingredient::GrainsToGrind, Ingredient::GroundCoffee)],
+            &[],
+        );
+
+        // Mueve todo el stock de un contenedor al otro tomando los locks en el orden del reponedor
+        let finished = Arc::new(AtomicBool::new(false));
+        let transfers_finished = finished.clone();
+        let transfers = thread::spawn(move || {
+            while !transfers_finished.load(Ordering::Relaxed) {
+                let dest = dest_lock.lock().expect("Error in test");
+                let source = source_lock.lock().expect("Error in test");
+                if source.remaining() > 0 {
+                    let quantity = source.level.consume_up_to(source.remaining());
+                    dest.level.fill(quantity);
+                } else {
+                    let quantity = dest.level.consume_up_to(dest.remaining());
+                    source.level.fill(quantity);
+                }
+            }
+        });
+        for _ in 0..20000 {
+            assert_eq!(
+                500,
+                admission
+                    .obtainable(Ingredient::GroundCoffee)
+                    .expect("Error in test")
+            );
+        }
+        finished.store(true, Ordering::Relaxed);
+        transfers.join().expect("Error in test");
+    }
+
+    #[test]
+    fn should_accept_every_order_when_disabled() {
+        let admission = AdmissionControl::disabled();
+
+        assert_eq!(
+            None,
+            admission
+                .check(&[(Ingredient::Cacao, 10)])
+                .expect("Error in test")
+        );
+    }
+}
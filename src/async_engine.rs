@@ -0,0 +1,456 @@
+//! Motor alternativo de la cafetera sobre un runtime asincronico (tokio). Los dispensers, los reponedores y la
+//! recoleccion de estadisticas son tareas en lugar de hilos, y la espera por el nivel de un contenedor
+//! no bloquea el hilo, por lo que se pueden simular cientos de dispensers con unos pocos hilos.
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use log::{debug, error, info};
+use tokio::{
+    runtime,
+    sync::{mpsc, Mutex as AsyncMutex, MutexGuard, Notify},
+    task::{self, JoinHandle},
+    time,
+};
+
+use crate::{
+    actors::{EngineRun, EngineSetup},
+    clock::Clock,
+    constants::{
+        MAX_OF_INGREDIENT_IN_AN_ORDER, MINIMUM_WAIT_TIME_REPLENISHER, STATISTICS_WAIT_IN_MS,
+        VIRTUAL_CLOCK_WAIT_IN_MS,
+    },
+    container::{Container, ContainerLevel},
+    control::{ShutdownMode, ShutdownSignal},
+    counters::OrderCounters,
+    errors::CoffeeMakerError,
+    ingredient_order::{IngredientOrder, IngredientOrdering},
+    latency::LatencyHistograms,
+    order::{Ingredient, Order, ALL_INGREDIENTS},
+    order_source::OrderSource,
+    scheduling::SchedulingPolicy,
+    statistics::{ContainerStatistics, StatisticsPrinter, StatisticsSnapshot},
+};
+
+/// Contenedor compartido entre tareas. `changed` avisa a los dispensers que se recargo
+/// y `refill_needed` despierta a su reponedor
+struct AsyncContainer {
+    state: AsyncMutex<Container>,
+    changed: Notify,
+    refill_needed: Notify,
+}
+
+impl AsyncContainer {
+    fn new(container: Container) -> AsyncContainer {
+        AsyncContainer {
+            state: AsyncMutex::new(container),
+            changed: Notify::new(),
+            refill_needed: Notify::new(),
+        }
+    }
+
+    /// Espera sin bloquear el hilo a que el contenedor cumpla `condition`, revisandola cada vez que llega un aviso
+    /// de `notify`. Devuelve el contenedor tomado
+    async fn wait_until(
+        &self,
+        notify: &Notify,
+        mut condition: impl FnMut(&Container) -> bool,
+    ) -> MutexGuard<'_, Container> {
+        loop {
+            // Se registra antes de revisar la condicion para no perder un aviso que llegue en el medio
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            let container = self.state.lock().await;
+            if condition(&container) {
+                return container;
+            }
+            drop(container);
+            notified.await;
+        }
+    }
+}
+
+/// Estado compartido por las tareas del motor
+struct AsyncCoffeeMaker {
+    containers: HashMap<Ingredient, AsyncContainer>,
+    orders: AsyncMutex<mpsc::UnboundedReceiver<Order>>,
+    counters: OrderCounters,
+    latencies: Mutex<LatencyHistograms>,
+    ingredient_order: Arc<IngredientOrder>,
+    clock: Arc<dyn Clock>,
+    finished: AtomicBool,
+    shutdown: Arc<ShutdownSignal>,
+}
+
+/// Procesa los pedidos de las fuentes con tareas asincronicas a partir de `setup` y devuelve el estado final.
+/// Las fuentes se leen en hilos del runtime reservados para operaciones bloqueantes
+pub fn run(
+    setup: EngineSetup,
+    sources: Vec<Box<dyn OrderSource>>,
+) -> Result<EngineRun, CoffeeMakerError> {
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .map_err(|err| {
+            error!("[ASYNC] Error starting the runtime: {:?}", err);
+            CoffeeMakerError::RuntimeError(err.to_string())
+        })?;
+    runtime.block_on(run_tasks(setup, sources))
+}
+
+async fn run_tasks(
+    setup: EngineSetup,
+    sources: Vec<Box<dyn OrderSource>>,
+) -> Result<EngineRun, CoffeeMakerError> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    for order in setup.pending_orders {
+        _ = sender.send(order);
+    }
+    let counters = OrderCounters::new();
+    counters.set_processed(setup.orders_processed);
+    let coffee_maker = Arc::new(AsyncCoffeeMaker {
+        containers: setup
+            .containers
+            .into_iter()
+            .map(|(ingredient, container)| (ingredient, AsyncContainer::new(container)))
+            .collect(),
+        orders: AsyncMutex::new(receiver),
+        counters,
+        latencies: Mutex::new(LatencyHistograms::new()),
+        ingredient_order: setup.ingredient_order,
+        clock: setup.clock,
+        finished: AtomicBool::new(false),
+        shutdown: setup.shutdown.clone(),
+    });
+
+    let statistics = task::spawn(
+        coffee_maker
+            .clone()
+            .process_statistics(setup.statistics_printer),
+    );
+    let replenished = setup
+        .container_replenishers
+        .iter()
+        .map(|(source, dest)| (*dest, Some(*source)))
+        .chain(setup.external_replenishers.iter().map(|dest| (*dest, None)))
+        .collect::<Vec<(Ingredient, Option<Ingredient>)>>();
+    let replenishers = replenished
+        .iter()
+        .map(|(dest, source)| task::spawn(coffee_maker.clone().replenish(*dest, *source)))
+        .collect::<Vec<JoinHandle<Result<(), CoffeeMakerError>>>>();
+    let dispensers = (0..setup.dispensers)
+        .map(|id| task::spawn(coffee_maker.clone().handle_orders(id)))
+        .collect::<Vec<JoinHandle<Result<(), CoffeeMakerError>>>>();
+
+    let next_id = Arc::new(AtomicUsize::new(setup.next_id));
+    let readers = sources
+        .into_iter()
+        .map(|source| {
+            let sender = sender.clone();
+            let next_id = next_id.clone();
+            let shutdown = setup.shutdown.clone();
+            task::spawn_blocking(move || read_orders(source, sender, next_id, &shutdown))
+        })
+        .collect::<Vec<JoinHandle<()>>>();
+    drop(sender);
+
+    for reader in readers {
+        if let Err(err) = reader.await {
+            println!("[ERROR ON READER] {:?}", err);
+        }
+    }
+    wait_for(dispensers, "DISPENSER").await;
+    for (dest, _) in &replenished {
+        coffee_maker.container(dest)?.state.lock().await.shutdown = true;
+        coffee_maker.container(dest)?.refill_needed.notify_waiters();
+    }
+    wait_for(replenishers, "REPLENISHER").await;
+    coffee_maker.finished.store(true, Ordering::SeqCst);
+    wait_for(vec![statistics], "STATISTICS").await;
+
+    let mut containers = HashMap::new();
+    for (ingredient, container) in &coffee_maker.containers {
+        containers.insert(*ingredient, container.state.lock().await.clone());
+    }
+    let latencies = mem::take(&mut *coffee_maker.latencies.lock()?);
+    Ok(EngineRun {
+        containers,
+        orders_processed: coffee_maker.counters.processed(),
+        orders_skipped: coffee_maker.counters.skipped(),
+        latencies,
+    })
+}
+
+async fn wait_for(tasks: Vec<JoinHandle<Result<(), CoffeeMakerError>>>, name: &str) {
+    for handle in tasks {
+        match handle.await {
+            Ok(Err(err)) => println!("[ERROR ON {}] {:?}", name, err),
+            Err(err) => println!("[ERROR ON {}] {:?}", name, err),
+            Ok(Ok(())) => {}
+        }
+    }
+}
+
+/// Lleva los pedidos de una fuente al canal de pedidos, asignandoles un id, hasta que se agote o se pida finalizar
+fn read_orders(
+    mut source: Box<dyn OrderSource>,
+    sender: mpsc::UnboundedSender<Order>,
+    next_id: Arc<AtomicUsize>,
+    shutdown: &ShutdownSignal,
+) {
+    let stopped = || shutdown.requested().is_some();
+    loop {
+        if stopped() {
+            info!("[READER] Shutdown requested, stopping {}", source.name());
+            return;
+        }
+        match source.next_order_until(&stopped) {
+            Ok(Some(source_order)) => {
+                let order = Order::new(
+                    next_id.fetch_add(1, Ordering::SeqCst),
+                    source_order.ingredients,
+                );
+                if sender.send(order).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(err) => {
+                error!("[READER] Error reading from {}: {:?}", source.name(), err);
+                return;
+            }
+        }
+    }
+}
+
+impl AsyncCoffeeMaker {
+    fn container(&self, ingredient: &Ingredient) -> Result<&AsyncContainer, CoffeeMakerError> {
+        self.containers
+            .get(ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(*ingredient))
+    }
+
+    /// Version asincronica de `Dispenser::handle_orders`. Termina cuando se cerro el canal de pedidos.
+    /// Si se pidio `Abort`, descarta los pedidos que toma en lugar de prepararlos
+    async fn handle_orders(self: Arc<Self>, id: usize) -> Result<(), CoffeeMakerError> {
+        loop {
+            let Some(order) = self.orders.lock().await.recv().await else {
+                return Ok(());
+            };
+            if self.shutdown.requested() == Some(ShutdownMode::Abort) {
+                info!("[DISPENSER {}] Aborting, discarded order {}", id, order.id);
+                continue;
+            }
+            debug!("[DISPENSER {}] Takes order {}", id, order.id);
+            self.latencies
+                .lock()?
+                .record_queue_wait(self.clock.local_now().saturating_sub(order.created_at));
+            self.process_order(id, order).await?;
+        }
+    }
+
+    async fn process_order(&self, id: usize, mut order: Order) -> Result<(), CoffeeMakerError> {
+        let preparation_start = self.clock.local_now();
+        let levels = if self.ingredient_order.get()? == IngredientOrdering::ScarcestFirst {
+            self.levels().await
+        } else {
+            Vec::new()
+        };
+        self.ingredient_order
+            .sort_with(order.id, &mut order.ingredients, || Ok(levels))?;
+        let mut used = Vec::with_capacity(order.ingredients.len());
+        for (ingredient, quantity) in order.ingredients {
+            let container = self.container(&ingredient)?;
+            let wait_start = self.clock.local_now();
+            let mut waited_for_replenisher = false;
+            let state = container
+                .wait_until(&container.changed, |state| {
+                    let need_more_resource =
+                        state.remaining() < quantity && state.replenishable && !state.finished;
+                    if need_more_resource {
+                        container.refill_needed.notify_waiters();
+                    }
+                    waited_for_replenisher |= need_more_resource;
+                    !need_more_resource
+                })
+                .await;
+            if waited_for_replenisher {
+                self.latencies
+                    .lock()?
+                    .record_replenishment_wait(self.clock.local_now().saturating_sub(wait_start));
+            }
+            if !state.level.try_consume(quantity) {
+                info!(
+                    "[DISPENSER {}] Skipped order {}, not enough {:?}",
+                    id, order.id, ingredient
+                );
+                drop(state);
+                for (ingredient, quantity) in used {
+                    self.container(&ingredient)?.state.lock().await.wasted += quantity;
+                }
+                self.counters.increase_skipped();
+                return Ok(());
+            }
+            work(&*self.clock, Duration::from_millis(quantity)).await;
+            used.push((ingredient, quantity));
+        }
+        self.counters.increase_processed();
+        self.latencies
+            .lock()?
+            .record_preparation(self.clock.local_now().saturating_sub(preparation_start));
+        Ok(())
+    }
+
+    /// Recarga `dest` cuando un dispenser lo pide y le queda poco, desde `source` o desde una fuente externa.
+    /// Como en `ContainerReplenisher`, el destino queda tomado durante toda la recarga
+    async fn replenish(
+        self: Arc<Self>,
+        dest: Ingredient,
+        source: Option<Ingredient>,
+    ) -> Result<(), CoffeeMakerError> {
+        let container = self.container(&dest)?;
+        loop {
+            let mut state = container
+                .wait_until(&container.refill_needed, |state| {
+                    (state.remaining() <= MAX_OF_INGREDIENT_IN_AN_ORDER && !state.finished)
+                        || state.shutdown
+                })
+                .await;
+            if state.shutdown {
+                return Ok(());
+            }
+            let wanted = state.capacity() - state.remaining();
+            let quantity = match source {
+                Some(source) => {
+                    let mut source_state = self.container(&source)?.state.lock().await;
+                    let quantity = source_state.level.consume_up_to(wanted);
+                    source_state.transferred += quantity;
+                    state.finished = source_state.is_empty();
+                    quantity
+                }
+                None => {
+                    state.supplied += wanted;
+                    wanted
+                }
+            };
+            state.level.fill(quantity);
+            work(
+                &*self.clock,
+                Duration::from_millis(MINIMUM_WAIT_TIME_REPLENISHER + quantity),
+            )
+            .await;
+            info!("[REPLENISHER] Replenished {:?} with {}", dest, quantity);
+            drop(state);
+            container.changed.notify_waiters();
+        }
+    }
+
+    /// Version asincronica de `StatisticsPrinter::process_statistics`, reporta a los mismos sinks
+    async fn process_statistics(
+        self: Arc<Self>,
+        printer: Arc<StatisticsPrinter>,
+    ) -> Result<(), CoffeeMakerError> {
+        let mut tick = 0;
+        loop {
+            let finished = self.finished.load(Ordering::SeqCst);
+            let snapshot = self.take_snapshot(tick).await?;
+            printer.report(&snapshot, finished);
+            if finished {
+                return Ok(());
+            }
+            tick += 1;
+            let wait = self
+                .clock
+                .real_duration(Duration::from_millis(STATISTICS_WAIT_IN_MS));
+            time::sleep(wait.max(Duration::from_millis(VIRTUAL_CLOCK_WAIT_IN_MS))).await;
+        }
+    }
+
+    async fn take_snapshot(&self, tick: u64) -> Result<StatisticsSnapshot, CoffeeMakerError> {
+        let mut containers = Vec::with_capacity(ALL_INGREDIENTS.len());
+        for ingredient in ALL_INGREDIENTS {
+            if let Some(container) = self.containers.get(&ingredient) {
+                let state = container.state.lock().await;
+                containers.push(ContainerStatistics {
+                    ingredient,
+                    remaining: state.remaining(),
+                    consumed: state.consumed(),
+                    capacity: state.capacity(),
+                });
+            }
+        }
+        Ok(StatisticsSnapshot {
+            tick,
+            elapsed_ms: self.clock.now().as_millis() as u64,
+            orders_processed: self.counters.processed(),
+            containers,
+            paused_dispensers: Vec::new(),
+            latencies: self.latencies.lock()?.summary(),
+            scheduling: SchedulingPolicy::Fifo,
+            incidents: Vec::new(),
+        })
+    }
+
+    async fn levels(&self) -> Vec<ContainerLevel> {
+        let mut levels = Vec::with_capacity(ALL_INGREDIENTS.len());
+        for ingredient in ALL_INGREDIENTS {
+            if let Some(container) = self.containers.get(&ingredient) {
+                let state = container.state.lock().await;
+                levels.push(ContainerLevel {
+                    ingredient,
+                    remaining: state.remaining(),
+                    capacity: state.capacity(),
+                });
+            }
+        }
+        levels
+    }
+}
+
+/// Espera lo que lleva un trabajo segun el reloj sin bloquear el hilo. Con el reloj virtual solo se registra el avance,
+/// que se acumula en la linea de tiempo de la tarea
+async fn work(clock: &dyn Clock, duration: Duration) {
+    let real_duration = clock.real_duration(duration);
+    if real_duration.is_zero() {
+        clock.sleep(duration);
+        task::yield_now().await;
+    } else {
+        time::sleep(real_duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_wake_up_a_task_waiting_for_a_container_level() {
+        let runtime = runtime::Builder::new_current_thread()
+            .build()
+            .expect("Error in test");
+        runtime.block_on(async {
+            let container = Arc::new(AsyncContainer::new(Container::new(100)));
+            container.state.lock().await.level.set_remaining(0);
+            let container_clone = container.clone();
+            let waiting = task::spawn(async move {
+                let state = container_clone
+                    .wait_until(&container_clone.changed, |state| state.remaining() >= 50)
+                    .await;
+                state.remaining()
+            });
+            task::yield_now().await;
+            assert!(!waiting.is_finished());
+
+            container.state.lock().await.level.set_remaining(80);
+            container.changed.notify_waiters();
+            assert_eq!(80, waiting.await.expect("Error in test"));
+        });
+    }
+}
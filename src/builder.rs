@@ -0,0 +1,489 @@
+//! Configuracion de la cafetera para poder armarla desde otras aplicaciones
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use log::error;
+
+use crate::{
+    actors::Engine,
+    clock::{default_clock, Clock},
+    coffee_maker::CoffeeMaker,
+    constants::{
+        A_WATER_STORAGE, C_CACAO_STORAGE, DEFAULT_RESTART_BUDGET, E_FOAM_STORAGE, G_GRAINS_STORAGE,
+        L_MILK_STORAGE, MAX_CONTAINER_CAPACITY, M_COFFEE_STORAGE, N_DISPENSERS,
+    },
+    container::ConsumePath,
+    dry_run::{self, DryRunReport},
+    errors::CoffeeMakerError,
+    ingredient_order::IngredientOrdering,
+    order::{Ingredient, ALL_INGREDIENTS},
+    order_source::{JsonFileSource, OrderIngredients, OrderSource},
+    scheduling::{SchedulingPolicy, ALL_SCHEDULING_POLICIES},
+    statistics_sink::StatisticsSink,
+};
+
+/// Arma una cafetera a partir de su configuracion. Por defecto tiene las capacidades y la cantidad de dispensers
+/// de `constants.rs`, repone el cafe molido con los granos, la espuma con la leche y el agua de una fuente externa,
+/// y no tiene ninguna fuente de pedidos.
+pub struct CoffeeMakerBuilder {
+    pub(crate) capacities: HashMap<Ingredient, u64>,
+    pub(crate) dispensers: usize,
+    pub(crate) container_replenishers: Vec<(Ingredient, Ingredient)>,
+    pub(crate) external_replenishers: Vec<Ingredient>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) ingredient_ordering: IngredientOrdering,
+    pub(crate) order_sources: Vec<Box<dyn OrderSource>>,
+    pub(crate) statistics_sinks: Vec<Arc<dyn StatisticsSink>>,
+    pub(crate) stdout_statistics: bool,
+    pub(crate) engine: Engine,
+    pub(crate) consume_path: ConsumePath,
+    pub(crate) restart_budget: usize,
+    pub(crate) admission_control: bool,
+    pub(crate) scheduling_policy: SchedulingPolicy,
+    dashboard: bool,
+    check_invariants: bool,
+    control_socket: Option<PathBuf>,
+    snapshots: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    journal: Option<PathBuf>,
+}
+
+impl CoffeeMakerBuilder {
+    pub fn new() -> CoffeeMakerBuilder {
+        CoffeeMakerBuilder {
+            capacities: ALL_INGREDIENTS
+                .into_iter()
+                .map(|ingredient| (ingredient, default_capacity(&ingredient)))
+                .collect(),
+            dispensers: N_DISPENSERS,
+            container_replenishers: vec![
+                (Ingredient::GrainsToGrind, Ingredient::GroundCoffee),
+                (Ingredient::ColdMilk, Ingredient::MilkFoam),
+            ],
+            external_replenishers: vec![Ingredient::HotWater],
+            clock: default_clock(),
+            ingredient_ordering: IngredientOrdering::Random,
+            order_sources: Vec::new(),
+            statistics_sinks: Vec::new(),
+            stdout_statistics: true,
+            engine: Engine::Shared,
+            consume_path: ConsumePath::Locked,
+            restart_budget: DEFAULT_RESTART_BUDGET,
+            admission_control: false,
+            scheduling_policy: SchedulingPolicy::Fifo,
+            dashboard: false,
+            check_invariants: false,
+            control_socket: None,
+            snapshots: None,
+            resume: None,
+            journal: None,
+        }
+    }
+
+    /// Capacidad del contenedor del ingrediente. Empieza lleno
+    pub fn container(mut self, ingredient: Ingredient, capacity: u64) -> CoffeeMakerBuilder {
+        self.capacities.insert(ingredient, capacity);
+        self
+    }
+
+    pub fn dispensers(mut self, dispensers: usize) -> CoffeeMakerBuilder {
+        self.dispensers = dispensers;
+        self
+    }
+
+    /// Agrega un reponedor que recarga el contenedor `dest` consumiendo del contenedor `source`
+    pub fn container_replenisher(
+        mut self,
+        source: Ingredient,
+        dest: Ingredient,
+    ) -> CoffeeMakerBuilder {
+        self.container_replenishers.push((source, dest));
+        self
+    }
+
+    /// Agrega un reponedor que recarga el contenedor del ingrediente desde una fuente que no se agota
+    pub fn external_replenisher(mut self, ingredient: Ingredient) -> CoffeeMakerBuilder {
+        self.external_replenishers.push(ingredient);
+        self
+    }
+
+    /// Quita todos los reponedores, incluidos los que vienen por defecto
+    pub fn without_replenishers(mut self) -> CoffeeMakerBuilder {
+        self.container_replenishers.clear();
+        self.external_replenishers.clear();
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> CoffeeMakerBuilder {
+        self.clock = clock;
+        self
+    }
+
+    pub fn ingredient_ordering(mut self, ordering: IngredientOrdering) -> CoffeeMakerBuilder {
+        self.ingredient_ordering = ordering;
+        self
+    }
+
+    /// Agrega un archivo de pedidos con el formato de `orders.json`
+    pub fn orders_file(self, path: impl Into<String>) -> CoffeeMakerBuilder {
+        self.order_source(JsonFileSource::new(path))
+    }
+
+    /// Agrega una fuente de pedidos que se procesa con `CoffeeMaker::run`. Puede haber varias a la vez,
+    /// la cafetera deja de aceptar pedidos cuando se agotaron todas
+    pub fn order_source(mut self, source: impl OrderSource + 'static) -> CoffeeMakerBuilder {
+        self.order_sources.push(Box::new(source));
+        self
+    }
+
+    /// Agrega un destino para las estadisticas. Puede haber varios a la vez, todos reciben la misma foto en cada ronda
+    pub fn statistics_sink(mut self, sink: Arc<dyn StatisticsSink>) -> CoffeeMakerBuilder {
+        self.statistics_sinks.push(sink);
+        self
+    }
+
+    /// No imprime las estadisticas por pantalla. Por defecto se imprimen
+    pub fn without_stdout_statistics(mut self) -> CoffeeMakerBuilder {
+        self.stdout_statistics = false;
+        self
+    }
+
+    /// Motor con el que se procesan los pedidos. Los alternativos no soportan el panel, el socket de control,
+    /// las fotos, el journal ni la verificacion de invariantes, ya que dependen del estado compartido
+    pub fn engine(mut self, engine: Engine) -> CoffeeMakerBuilder {
+        self.engine = engine;
+        self
+    }
+
+    /// Forma en la que los dispensers descuentan los ingredientes. Por defecto sin tomar el lock de los contenedores
+    pub fn consume_path(mut self, consume_path: ConsumePath) -> CoffeeMakerBuilder {
+        self.consume_path = consume_path;
+        self
+    }
+
+    /// Cantidad de veces que se reinicia a un mismo dispenser o reponedor si se cae. Con 0 no se reinician
+    pub fn restart_budget(mut self, restart_budget: usize) -> CoffeeMakerBuilder {
+        self.restart_budget = restart_budget;
+        self
+    }
+
+    /// Rechaza los pedidos que nunca se van a poder preparar, al llegar y al tomarlos un dispenser,
+    /// antes de consumir cualquiera de sus ingredientes (ver `AdmissionControl`)
+    pub fn admission_control(mut self) -> CoffeeMakerBuilder {
+        self.admission_control = true;
+        self
+    }
+
+    /// Orden en el que los dispensers toman los pedidos de la cola. Por defecto en el que llegaron
+    pub fn scheduling_policy(mut self, policy: SchedulingPolicy) -> CoffeeMakerBuilder {
+        self.scheduling_policy = policy;
+        self
+    }
+
+    /// Reemplaza la impresion periodica de estadisticas por el panel de la terminal
+    pub fn dashboard(mut self) -> CoffeeMakerBuilder {
+        self.dashboard = true;
+        self
+    }
+
+    /// Modo de depuracion que verifica continuamente la conservacion de ingredientes y pedidos
+    pub fn check_invariants(mut self) -> CoffeeMakerBuilder {
+        self.check_invariants = true;
+        self
+    }
+
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> CoffeeMakerBuilder {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Guarda periodicamente una foto del estado de la cafetera en `path`
+    pub fn snapshots(mut self, path: impl Into<PathBuf>) -> CoffeeMakerBuilder {
+        self.snapshots = Some(path.into());
+        self
+    }
+
+    /// Arranca desde el estado guardado en la foto de `path`
+    pub fn resume_from(mut self, path: impl Into<PathBuf>) -> CoffeeMakerBuilder {
+        self.resume = Some(path.into());
+        self
+    }
+
+    /// Registra los eventos de la ejecucion en el journal de `path`
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> CoffeeMakerBuilder {
+        self.journal = Some(path.into());
+        self
+    }
+
+    /// Valida la configuracion y, en lugar de armar la cafetera, lee todos los pedidos de las fuentes y simula
+    /// su consumo sin hilos ni esperas (ver `dry_run::simulate`). Los contenedores empiezan llenos, aunque
+    /// se haya indicado una foto de la cual retomar
+    pub fn dry_run(mut self) -> Result<DryRunReport, CoffeeMakerError> {
+        self.validate()?;
+        let orders = self.drain_sources()?;
+        self.simulate(self.scheduling_policy, orders)
+    }
+
+    /// Igual que `dry_run`, pero simula los mismos pedidos con cada una de las politicas de orden de los pedidos
+    pub fn compare_scheduling(mut self) -> Result<Vec<DryRunReport>, CoffeeMakerError> {
+        self.validate()?;
+        let orders = self.drain_sources()?;
+        ALL_SCHEDULING_POLICIES
+            .into_iter()
+            .map(|policy| self.simulate(policy, orders.clone()))
+            .collect()
+    }
+
+    /// Lee todos los pedidos de las fuentes, una detras de la otra. El cliente de cada pedido es el que indica
+    /// el pedido, o su fuente si no lo indica
+    fn drain_sources(&mut self) -> Result<Vec<(String, OrderIngredients)>, CoffeeMakerError> {
+        let mut orders = Vec::new();
+        for source in &mut self.order_sources {
+            let source_name = source.name();
+            while let Some(order) = source.next_order()? {
+                orders.push((order.customer_or(&source_name), order.ingredients));
+            }
+        }
+        Ok(orders)
+    }
+
+    fn simulate(
+        &self,
+        policy: SchedulingPolicy,
+        orders: Vec<(String, OrderIngredients)>,
+    ) -> Result<DryRunReport, CoffeeMakerError> {
+        dry_run::simulate(
+            &self.capacities,
+            &self.container_replenishers,
+            &self.external_replenishers,
+            self.dispensers,
+            self.ingredient_ordering,
+            policy,
+            orders,
+        )
+    }
+
+    /// Valida la configuracion y arma la cafetera. La foto se restaura antes de abrir el journal,
+    /// asi el journal parte del estado restaurado
+    pub fn build(mut self) -> Result<CoffeeMaker, CoffeeMakerError> {
+        self.validate()?;
+        let mut coffee_maker = CoffeeMaker::from_builder(&mut self);
+        if let Some(path) = &self.resume {
+            coffee_maker.restore_snapshot(path)?;
+        }
+        if let Some(path) = self.snapshots {
+            coffee_maker.enable_snapshots(path);
+        }
+        if self.dashboard {
+            coffee_maker.enable_dashboard();
+        }
+        if self.check_invariants {
+            coffee_maker.enable_invariant_checker();
+        }
+        if let Some(path) = &self.journal {
+            coffee_maker.enable_journal(path)?;
+        }
+        if let Some(path) = self.control_socket {
+            coffee_maker.enable_control_socket(path);
+        }
+        Ok(coffee_maker)
+    }
+
+    fn validate(&self) -> Result<(), CoffeeMakerError> {
+        if self.dispensers == 0 {
+            return Err(invalid_configuration(String::from(
+                "the coffee maker needs at least one dispenser",
+            )));
+        }
+        if let Some((ingredient, _)) = self.capacities.iter().find(|(_, capacity)| **capacity == 0)
+        {
+            return Err(invalid_configuration(format!(
+                "the {:?} container has no capacity",
+                ingredient
+            )));
+        }
+        if let Some((ingredient, _)) = self
+            .capacities
+            .iter()
+            .find(|(_, capacity)| **capacity > MAX_CONTAINER_CAPACITY)
+        {
+            return Err(invalid_configuration(format!(
+                "the {:?} container can't hold more than {}",
+                ingredient, MAX_CONTAINER_CAPACITY
+            )));
+        }
+        if let Some((source, _)) = self
+            .container_replenishers
+            .iter()
+            .find(|(source, dest)| source == dest)
+        {
+            return Err(invalid_configuration(format!(
+                "the {:?} container can't replenish itself",
+                source
+            )));
+        }
+        let shared_state_features = self.dashboard
+            || self.admission_control
+            || self.scheduling_policy != SchedulingPolicy::Fifo
+            || self.check_invariants
+            || self.control_socket.is_some()
+            || self.snapshots.is_some()
+            || self.journal.is_some();
+        if self.engine != Engine::Shared && shared_state_features {
+            return Err(invalid_configuration(format!("the {:?} engine does not support the dashboard, admission control, scheduling policies, the control socket, snapshots, the journal or the invariant checker", self.engine)));
+        }
+        let mut replenished = HashSet::new();
+        let dests = self
+            .container_replenishers
+            .iter()
+            .map(|(_, dest)| dest)
+            .chain(self.external_replenishers.iter());
+        for dest in dests {
+            if !replenished.insert(dest) {
+                return Err(invalid_configuration(format!(
+                    "the {:?} container has more than one replenisher",
+                    dest
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid_configuration(reason: String) -> CoffeeMakerError {
+    error!("[CONFIGURATION] Invalid configuration: {}", reason);
+    CoffeeMakerError::InvalidConfiguration(reason)
+}
+
+impl Default for CoffeeMakerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn default_capacity(ingredient: &Ingredient) -> u64 {
+    match ingredient {
+        Ingredient::GroundCoffee => M_COFFEE_STORAGE,
+        Ingredient::HotWater => A_WATER_STORAGE,
+        Ingredient::Cacao => C_CACAO_STORAGE,
+        Ingredient::MilkFoam => E_FOAM_STORAGE,
+        Ingredient::GrainsToGrind => G_GRAINS_STORAGE,
+        Ingredient::ColdMilk => L_MILK_STORAGE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remaining(coffee_maker: &CoffeeMaker, ingredient: Ingredient) -> u64 {
+        coffee_maker
+            .control_handle()
+            .levels()
+            .expect("Error in test")
+            .into_iter()
+            .find(|level| level.ingredient == ingredient)
+            .expect("Error in test")
+            .remaining
+    }
+
+    #[test]
+    fn should_build_a_coffee_maker_with_custom_containers_and_dispensers() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .dispensers(2)
+            .container(Ingredient::Cacao, 100)
+            .orders_file("tests/simple_order.json")
+            .build()
+            .expect("Error in test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(1, coffee_maker.orders_processed().expect("Error in test"));
+        assert_eq!(40, remaining(&coffee_maker, Ingredient::Cacao));
+    }
+
+    #[test]
+    fn should_skip_the_orders_that_need_a_container_without_replenisher() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_replenishers()
+            .container(Ingredient::GroundCoffee, 50)
+            .ingredient_ordering(IngredientOrdering::Canonical)
+            .orders_file("tests/simple_order.json")
+            .build()
+            .expect("Error in test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(0, coffee_maker.orders_processed().expect("Error in test"));
+        assert_eq!(50, remaining(&coffee_maker, Ingredient::GroundCoffee));
+        assert_eq!(
+            A_WATER_STORAGE,
+            remaining(&coffee_maker, Ingredient::HotWater)
+        );
+    }
+
+    #[test]
+    fn should_predict_the_run_with_a_dry_run() {
+        let builder = || {
+            CoffeeMakerBuilder::new()
+                .ingredient_ordering(IngredientOrdering::Canonical)
+                .without_stdout_statistics()
+                .orders_file("tests/consume_all.json")
+        };
+        let report = builder().dry_run().expect("Error in test");
+        let coffee_maker = builder().build().expect("Error in test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(
+            coffee_maker.orders_processed().expect("Error in test"),
+            report.orders_served
+        );
+        assert_eq!(coffee_maker.orders_skipped(), report.orders_skipped);
+        assert_eq!(500, report.orders_served);
+        let first = &report.depletions[0];
+        assert_eq!(Ingredient::Cacao, first.ingredient);
+        assert_eq!(500, first.order);
+    }
+
+    #[test]
+    fn should_compare_the_scheduling_policies_on_the_same_orders() {
+        let reports = CoffeeMakerBuilder::new()
+            .ingredient_ordering(IngredientOrdering::Canonical)
+            .orders_file("tests/scarce_cacao.json")
+            .orders_file("tests/multiple_orders.json")
+            .compare_scheduling()
+            .expect("Error in test");
+
+        let served = |policy| {
+            reports
+                .iter()
+                .find(|report| report.scheduling == policy)
+                .expect("Error in test")
+                .orders_served
+        };
+        assert_eq!(ALL_SCHEDULING_POLICIES.len(), reports.len());
+        assert!(served(SchedulingPolicy::InventoryAware) > served(SchedulingPolicy::Fifo));
+    }
+
+    #[test]
+    fn should_reject_an_invalid_configuration() {
+        let invalid = [
+            CoffeeMakerBuilder::new().dispensers(0),
+            CoffeeMakerBuilder::new().container(Ingredient::Cacao, 0),
+            CoffeeMakerBuilder::new().container(Ingredient::Cacao, MAX_CONTAINER_CAPACITY + 1),
+            CoffeeMakerBuilder::new().container_replenisher(Ingredient::Cacao, Ingredient::Cacao),
+            CoffeeMakerBuilder::new().external_replenisher(Ingredient::GroundCoffee),
+            CoffeeMakerBuilder::new().engine(Engine::Actors).dashboard(),
+            CoffeeMakerBuilder::new()
+                .engine(Engine::Async)
+                .scheduling_policy(SchedulingPolicy::InventoryAware),
+        ];
+        for builder in invalid {
+            assert!(matches!(
+                builder.build().err(),
+                Some(CoffeeMakerError::InvalidConfiguration(_))
+            ));
+        }
+    }
+}
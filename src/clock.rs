@@ -0,0 +1,342 @@
+//! Reloj de la cafetera. Define cuanto tardan las esperas de los dispensers, los reponedores
+//! y las tareas periodicas, para poder simular una ejecucion mas rapido que en tiempo real.
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use log::error;
+use tokio::task;
+
+use crate::{constants::VIRTUAL_CLOCK_WAIT_IN_MS, errors::CoffeeMakerError};
+
+/// Reloj compartido por los hilos de la cafetera.
+/// * `sleep` representa un trabajo que lleva `duration` (por ejemplo, servir un ingrediente o reponer un contenedor).
+/// * `wait` es la espera entre dos ejecuciones de una tarea periodica (estadisticas, panel, fotos).
+/// * `now` es el tiempo transcurrido desde que se creo el reloj, medido en el tiempo del reloj.
+/// * `local_now` es como `now`, pero en la linea de tiempo de quien llama. Las duraciones que mide un hilo (las latencias)
+///   se calculan con este valor, para no mezclar lineas de tiempo distintas del reloj virtual.
+/// * `real_duration` es el tiempo real que representa `duration`, para quien espera sin bloquear el hilo (el motor asincronico).
+pub trait Clock: Send + Sync {
+    fn sleep(&self, duration: Duration);
+
+    fn wait(&self, duration: Duration);
+
+    fn now(&self) -> Duration;
+
+    fn local_now(&self) -> Duration;
+
+    fn real_duration(&self, duration: Duration) -> Duration;
+}
+
+/// Reloj de tiempo real
+pub struct RealClock {
+    started_at: Instant,
+}
+
+impl RealClock {
+    pub fn new() -> RealClock {
+        RealClock {
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for RealClock {
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn wait(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn now(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    fn local_now(&self) -> Duration {
+        self.now()
+    }
+
+    fn real_duration(&self, duration: Duration) -> Duration {
+        duration
+    }
+}
+
+/// Reloj que avanza `factor` veces mas rapido que el tiempo real
+pub struct ScaledClock {
+    started_at: Instant,
+    factor: u32,
+}
+
+impl ScaledClock {
+    pub fn new(factor: u32) -> ScaledClock {
+        ScaledClock {
+            started_at: Instant::now(),
+            factor: factor.max(1),
+        }
+    }
+}
+
+impl Clock for ScaledClock {
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration / self.factor);
+    }
+
+    fn wait(&self, duration: Duration) {
+        thread::sleep(duration / self.factor);
+    }
+
+    fn now(&self) -> Duration {
+        self.started_at.elapsed() * self.factor
+    }
+
+    fn local_now(&self) -> Duration {
+        self.now()
+    }
+
+    fn real_duration(&self, duration: Duration) -> Duration {
+        duration / self.factor
+    }
+}
+
+/// Duenio de una linea de tiempo del reloj virtual: un hilo o, en el motor asincronico, una tarea,
+/// ya que las tareas pueden pasar de un hilo del runtime a otro
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Timeline {
+    Thread(ThreadId),
+    Task(task::Id),
+}
+
+impl Timeline {
+    fn current() -> Timeline {
+        match task::try_id() {
+            Some(id) => Timeline::Task(id),
+            None => Timeline::Thread(thread::current().id()),
+        }
+    }
+}
+
+/// Tiempo simulado del reloj virtual. Cada hilo o tarea lleva su propia linea de tiempo y el reloj
+/// marca la del que mas avanzo
+struct VirtualTime {
+    timelines: HashMap<Timeline, Duration>,
+    now: Duration,
+}
+
+/// Reloj completamente simulado. `sleep` no espera, solo avanza la linea de tiempo del hilo (o la tarea) que lo llama.
+/// `wait` espera a que el reloj avance `duration`, o a lo sumo `VIRTUAL_CLOCK_WAIT_IN_MS` de tiempo real
+/// para que las tareas periodicas no se queden bloqueadas cuando no hay trabajo.
+/// No se cuenta el tiempo que los hilos pasan bloqueados esperando a otros, por lo que el tiempo simulado es una cota inferior.
+pub struct VirtualClock {
+    time: Mutex<VirtualTime>,
+    advanced: Condvar,
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock {
+            time: Mutex::new(VirtualTime {
+                timelines: HashMap::new(),
+                now: Duration::ZERO,
+            }),
+            advanced: Condvar::new(),
+        }
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn sleep(&self, duration: Duration) {
+        match self.time.lock() {
+            Ok(mut time) => {
+                let timeline = time
+                    .timelines
+                    .entry(Timeline::current())
+                    .or_insert(Duration::ZERO);
+                *timeline += duration;
+                let timeline = *timeline;
+                time.now = time.now.max(timeline);
+                self.advanced.notify_all();
+            }
+            Err(_) => error!("[CLOCK] Error taking the virtual clock lock"),
+        }
+        thread::yield_now();
+    }
+
+    fn wait(&self, duration: Duration) {
+        let Ok(time) = self.time.lock() else {
+            error!("[CLOCK] Error taking the virtual clock lock");
+            return;
+        };
+        let until = time.now + duration;
+        let result = self.advanced.wait_timeout_while(
+            time,
+            Duration::from_millis(VIRTUAL_CLOCK_WAIT_IN_MS),
+            |time| time.now < until,
+        );
+        if result.is_err() {
+            error!("[CLOCK] Error waiting on the virtual clock");
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.time
+            .lock()
+            .map(|time| time.now)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Un hilo que todavia no avanzo esta al principio de su linea de tiempo
+    fn local_now(&self) -> Duration {
+        self.time
+            .lock()
+            .map(|time| {
+                time.timelines
+                    .get(&Timeline::current())
+                    .copied()
+                    .unwrap_or(Duration::ZERO)
+            })
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// No se espera en tiempo real, el avance se registra con `sleep`
+    fn real_duration(&self, _duration: Duration) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// Tipo de reloj con el que se ejecuta la cafetera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockKind {
+    Real,
+    Scaled(u32),
+    Virtual,
+}
+
+impl ClockKind {
+    pub fn create(&self) -> Arc<dyn Clock> {
+        match self {
+            ClockKind::Real => Arc::new(RealClock::new()),
+            ClockKind::Scaled(factor) => Arc::new(ScaledClock::new(*factor)),
+            ClockKind::Virtual => Arc::new(VirtualClock::new()),
+        }
+    }
+}
+
+impl FromStr for ClockKind {
+    type Err = CoffeeMakerError;
+
+    /// Acepta `real`, `scaled:<factor>` y `virtual`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "real" => Ok(ClockKind::Real),
+            "virtual" => Ok(ClockKind::Virtual),
+            _ => value
+                .strip_prefix("scaled:")
+                .and_then(|factor| factor.parse::<u32>().ok())
+                .filter(|factor| *factor > 0)
+                .map(ClockKind::Scaled)
+                .ok_or_else(|| CoffeeMakerError::InvalidClock(value.to_string())),
+        }
+    }
+}
+
+/// Reloj por defecto de la cafetera. En los tests se usa el reloj virtual para que no esperen en tiempo real
+pub fn default_clock() -> Arc<dyn Clock> {
+    if cfg!(test) {
+        ClockKind::Virtual.create()
+    } else {
+        ClockKind::Real.create()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_the_clock_kinds() {
+        assert_eq!(Ok(ClockKind::Real), "real".parse());
+        assert_eq!(Ok(ClockKind::Scaled(100)), "scaled:100".parse());
+        assert_eq!(Ok(ClockKind::Virtual), "virtual".parse());
+        assert_eq!(
+            Err(CoffeeMakerError::InvalidClock(String::from("scaled:0"))),
+            "scaled:0".parse::<ClockKind>()
+        );
+    }
+
+    #[test]
+    fn should_advance_the_virtual_clock_without_waiting() {
+        let clock = Arc::new(VirtualClock::new());
+        let started_at = Instant::now();
+        clock.sleep(Duration::from_secs(3600));
+        clock.sleep(Duration::from_secs(3600));
+        assert_eq!(Duration::from_secs(7200), clock.now());
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_keep_the_timeline_of_the_thread_that_advanced_the_most() {
+        let clock = Arc::new(VirtualClock::new());
+        let clock_clone = clock.clone();
+        thread::spawn(move || clock_clone.sleep(Duration::from_secs(10)))
+            .join()
+            .expect("Error in test");
+        clock.sleep(Duration::from_secs(4));
+        assert_eq!(Duration::from_secs(10), clock.now());
+        clock.sleep(Duration::from_secs(8));
+        assert_eq!(Duration::from_secs(12), clock.now());
+    }
+
+    #[test]
+    fn should_give_each_thread_its_own_local_time() {
+        let clock = Arc::new(VirtualClock::new());
+        let clock_clone = clock.clone();
+        thread::spawn(move || clock_clone.sleep(Duration::from_secs(10)))
+            .join()
+            .expect("Error in test");
+        assert_eq!(Duration::ZERO, clock.local_now());
+        clock.sleep(Duration::from_secs(4));
+        assert_eq!(Duration::from_secs(4), clock.local_now());
+        assert_eq!(Duration::from_secs(10), clock.now());
+    }
+
+    #[test]
+    fn should_return_from_a_periodic_wait_while_the_virtual_clock_advances() {
+        let clock = Arc::new(VirtualClock::new());
+        let clock_clone = clock.clone();
+        let handle = thread::spawn(move || {
+            for _ in 0..100 {
+                clock_clone.sleep(Duration::from_millis(10));
+            }
+        });
+        clock.wait(Duration::from_millis(50));
+        handle.join().expect("Error in test");
+        assert_eq!(Duration::from_secs(1), clock.now());
+    }
+
+    #[test]
+    fn should_scale_the_real_time() {
+        let clock = ScaledClock::new(1000);
+        let started_at = Instant::now();
+        clock.sleep(Duration::from_secs(1));
+        assert!(started_at.elapsed() < Duration::from_millis(500));
+        assert!(clock.now() >= Duration::from_secs(1));
+    }
+}
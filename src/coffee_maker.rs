@@ -2,64 +2,141 @@
 
 use std::{
     collections::HashMap,
-    sync::{Arc, Condvar, Mutex, RwLock},
+    mem,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
 use crate::{
-    constants::{
-        A_WATER_STORAGE, C_CACAO_STORAGE, E_FOAM_STORAGE, G_GRAINS_STORAGE, L_MILK_STORAGE,
-        M_COFFEE_STORAGE, N_DISPENSERS,
-    },
-    container::Container,
+    actors::{self, Engine, EngineRun, EngineSetup},
+    admission::AdmissionControl,
+    async_engine,
+    builder::CoffeeMakerBuilder,
+    clock::Clock,
+    container::{Container, Levels},
     container_source_replenisher::ContainerReplenisher,
+    control::{ControlHandle, ShutdownSignal},
+    control_socket::ControlSocket,
+    counters::OrderCounters,
+    dashboard::Dashboard,
     dispenser::Dispenser,
+    epoch::TransferEpoch,
     errors::CoffeeMakerError,
     external_source_replenisher::ExternalReplenisher,
-    order::{Ingredient, TOTAL_INGREDIENTS},
+    ingredient_order::{IngredientOrder, IngredientOrdering},
+    invariants::InvariantChecker,
+    journal::Journal,
+    latency::{LatencyHistograms, LatencySummary},
+    order::Ingredient,
+    order_source::{JsonFileSource, OrderSource},
     orders_queue::OrdersQueue,
     orders_reader::read_and_add_orders,
+    poison::LockOrRecover,
+    scheduling::{Scheduler, SchedulingPolicy, Stock},
+    snapshot::{CoffeeMakerSnapshot, SnapshotWriter},
     statistics::StatisticsPrinter,
+    statistics_sink::StdoutSink,
+    supervisor::{Incident, Supervisor, WorkerId},
 };
 
+/// Cafetera armada a partir de un `CoffeeMakerBuilder`. Los pedidos de las fuentes configuradas se procesan con `run`,
+/// o con `manage_orders` indicando el archivo de pedidos
 pub struct CoffeeMaker {
     orders_queue: Arc<Mutex<OrdersQueue>>,
     orders_cond: Arc<Condvar>,
+    resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+    ingredients_cond: Arc<Condvar>,
     dispensers: Vec<Arc<Dispenser>>,
     container_replenishers: Vec<Arc<ContainerReplenisher>>,
-    water_replenisher: Arc<ExternalReplenisher>,
+    external_replenishers: Vec<Arc<ExternalReplenisher>>,
     statistics_printer: Arc<StatisticsPrinter>,
+    stdout_statistics: Option<Arc<StdoutSink>>,
+    dashboard: Option<Arc<Dashboard>>,
+    control_socket: Option<Arc<ControlSocket>>,
+    snapshot_writer: Option<Arc<SnapshotWriter>>,
+    invariant_checker: Option<Arc<InvariantChecker>>,
+    journal: Arc<Journal>,
+    ingredient_order: Arc<IngredientOrder>,
+    clock: Arc<dyn Clock>,
+    shutdown: Arc<ShutdownSignal>,
+    order_sources: Mutex<Vec<Box<dyn OrderSource>>>,
+    admission: Arc<AdmissionControl>,
+    engine: Engine,
+    restart_budget: usize,
 }
 
 impl CoffeeMaker {
+    /// Crea la cafetera con la configuracion por defecto de `CoffeeMakerBuilder`
     pub fn new() -> CoffeeMaker {
+        CoffeeMaker::from_builder(&mut CoffeeMakerBuilder::new())
+    }
+
+    /// Crea la cafetera con los contenedores, dispensers y reponedores de `builder`, y se queda con sus fuentes de pedidos.
+    /// Se asume que la configuracion ya fue validada
+    pub(crate) fn from_builder(builder: &mut CoffeeMakerBuilder) -> CoffeeMaker {
         // Initialize containers and resources hash map
-        let mut resources = HashMap::with_capacity(TOTAL_INGREDIENTS);
-        let cold_milk = Arc::new(Mutex::new(Container::new(L_MILK_STORAGE)));
-        let milk_foam = Arc::new(Mutex::new(Container::new(E_FOAM_STORAGE)));
-        let hot_water = Arc::new(Mutex::new(Container::new(A_WATER_STORAGE)));
-        let grains_to_grind = Arc::new(Mutex::new(Container::new(G_GRAINS_STORAGE)));
-        let ground_coffee = Arc::new(Mutex::new(Container::new(M_COFFEE_STORAGE)));
-        resources.insert(Ingredient::ColdMilk, cold_milk.clone());
-        resources.insert(Ingredient::MilkFoam, milk_foam.clone());
-        resources.insert(Ingredient::HotWater, hot_water.clone());
-        resources.insert(Ingredient::GrainsToGrind, grains_to_grind.clone());
-        resources.insert(Ingredient::GroundCoffee, ground_coffee.clone());
-        resources.insert(
-            Ingredient::Cacao,
-            Arc::new(Mutex::new(Container::new(C_CACAO_STORAGE))),
-        );
+        let mut containers: HashMap<Ingredient, Container> = builder
+            .capacities
+            .iter()
+            .map(|(ingredient, capacity)| (*ingredient, Container::new(*capacity)))
+            .collect();
+        let replenished = builder
+            .container_replenishers
+            .iter()
+            .map(|(_, dest)| dest)
+            .chain(builder.external_replenishers.iter());
+        for ingredient in replenished {
+            if let Some(container) = containers.get_mut(ingredient) {
+                container.replenishable = true;
+            }
+        }
+        let levels: Levels = containers
+            .iter()
+            .map(|(ingredient, container)| (*ingredient, container.level.clone()))
+            .collect();
+        let levels = Arc::new(levels);
+        let resources: HashMap<Ingredient, Arc<Mutex<Container>>> = containers
+            .into_iter()
+            .map(|(ingredient, container)| (ingredient, Arc::new(Mutex::new(container))))
+            .collect();
 
         // Initialize dispenser shared data
         let resources = Arc::new(resources);
-        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        let clock = builder.clock.clone();
+        let journal = Arc::new(Journal::disabled());
+        let mut queue = OrdersQueue::with_journal(journal.clone());
+        queue.set_clock(clock.clone());
+        if builder.scheduling_policy != SchedulingPolicy::Fifo {
+            queue.set_scheduler(Scheduler::new(
+                builder.scheduling_policy,
+                Stock::new(
+                    levels.clone(),
+                    &builder.container_replenishers,
+                    &builder.external_replenishers,
+                ),
+            ));
+        }
+        let orders_queue = Arc::new(Mutex::new(queue));
         let orders_cond = Arc::new(Condvar::new());
         let replenisher_cond = Arc::new(Condvar::new());
         let ingredients_cond = Arc::new(Condvar::new());
-        let orders_processed = Arc::new(RwLock::new(0));
+        let counters = Arc::new(OrderCounters::new());
+        let latencies = Arc::new(Mutex::new(LatencyHistograms::new()));
+        let ingredient_order = Arc::new(IngredientOrder::new(builder.ingredient_ordering));
+        let admission = Arc::new(if builder.admission_control {
+            AdmissionControl::new(
+                resources.clone(),
+                &builder.container_replenishers,
+                &builder.external_replenishers,
+            )
+        } else {
+            AdmissionControl::disabled()
+        });
 
         // Initialize dispensers
-        let dispensers = (0..N_DISPENSERS)
+        let dispensers = (0..builder.dispensers)
             .map(|id| {
                 Arc::new(Dispenser::new(
                     id,
@@ -68,79 +145,481 @@ impl CoffeeMaker {
                     replenisher_cond.clone(),
                     ingredients_cond.clone(),
                     resources.clone(),
-                    orders_processed.clone(),
+                    levels.clone(),
+                    counters.clone(),
+                    latencies.clone(),
+                    journal.clone(),
+                    ingredient_order.clone(),
+                    admission.clone(),
+                    builder.consume_path,
+                    clock.clone(),
                 ))
             })
             .collect::<Vec<Arc<Dispenser>>>();
 
-        // Initialize containers
-        let container_replenishers = vec![
-            Arc::new(ContainerReplenisher::new(
-                (Ingredient::GrainsToGrind, grains_to_grind),
-                (Ingredient::GroundCoffee, ground_coffee),
-                replenisher_cond.clone(),
-                ingredients_cond.clone(),
-                M_COFFEE_STORAGE,
-            )),
-            Arc::new(ContainerReplenisher::new(
-                (Ingredient::ColdMilk, cold_milk),
-                (Ingredient::MilkFoam, milk_foam),
-                replenisher_cond.clone(),
-                ingredients_cond.clone(),
-                E_FOAM_STORAGE,
-            )),
-        ];
+        // Initialize replenishers
+        let epoch = Arc::new(TransferEpoch::new());
+        let container_replenishers = builder
+            .container_replenishers
+            .iter()
+            .filter_map(|(source, dest)| {
+                let source_container = resources.get(source)?.clone();
+                let dest_container = resources.get(dest)?.clone();
+                Some(Arc::new(ContainerReplenisher::new(
+                    (*source, source_container),
+                    (*dest, dest_container),
+                    replenisher_cond.clone(),
+                    ingredients_cond.clone(),
+                    builder.capacities[dest],
+                    journal.clone(),
+                    epoch.clone(),
+                    clock.clone(),
+                )))
+            })
+            .collect::<Vec<Arc<ContainerReplenisher>>>();
 
-        let water_replenisher = Arc::new(ExternalReplenisher::new(
-            (Ingredient::HotWater, hot_water),
-            replenisher_cond,
-            ingredients_cond,
-            A_WATER_STORAGE,
+        let external_replenishers = builder
+            .external_replenishers
+            .iter()
+            .filter_map(|ingredient| {
+                let container = resources.get(ingredient)?.clone();
+                Some(Arc::new(ExternalReplenisher::new(
+                    (*ingredient, container),
+                    replenisher_cond.clone(),
+                    ingredients_cond.clone(),
+                    builder.capacities[ingredient],
+                    journal.clone(),
+                    clock.clone(),
+                )))
+            })
+            .collect::<Vec<Arc<ExternalReplenisher>>>();
+
+        let stdout_statistics = builder
+            .stdout_statistics
+            .then(|| Arc::new(StdoutSink::new()));
+        let mut sinks = builder.statistics_sinks.clone();
+        if let Some(stdout) = &stdout_statistics {
+            sinks.insert(0, stdout.clone());
+        }
+        let statistics_printer = Arc::new(StatisticsPrinter::new(
+            counters,
+            resources.clone(),
+            levels,
+            latencies,
+            orders_queue.clone(),
+            builder.dispensers,
+            epoch,
+            sinks,
+            clock.clone(),
         ));
 
         CoffeeMaker {
             orders_queue,
             orders_cond,
+            resources,
+            ingredients_cond,
             dispensers,
             container_replenishers,
-            water_replenisher,
-            statistics_printer: Arc::new(StatisticsPrinter::new(orders_processed, resources)),
+            external_replenishers,
+            statistics_printer,
+            stdout_statistics,
+            dashboard: None,
+            control_socket: None,
+            snapshot_writer: None,
+            invariant_checker: None,
+            journal,
+            ingredient_order,
+            admission,
+            clock,
+            shutdown: Arc::new(ShutdownSignal::default()),
+            order_sources: Mutex::new(mem::take(&mut builder.order_sources)),
+            engine: builder.engine,
+            restart_budget: builder.restart_budget,
+        }
+    }
+
+    /// Crea la cafetera con el estado guardado en la foto de `path`: el nivel de los contenedores,
+    /// la cantidad de pedidos procesados y los pedidos que quedaron en la cola
+    pub fn resume(path: &Path) -> Result<CoffeeMaker, CoffeeMakerError> {
+        let coffee_maker = CoffeeMaker::new();
+        coffee_maker.restore_snapshot(path)?;
+        Ok(coffee_maker)
+    }
+
+    /// Vuelca sobre la cafetera el estado guardado en la foto de `path`. Se debe llamar antes de iniciarla
+    pub fn restore_snapshot(&self, path: &Path) -> Result<(), CoffeeMakerError> {
+        let snapshot = CoffeeMakerSnapshot::load(path)?;
+        snapshot.restore(
+            &self.resources,
+            &self.orders_queue,
+            &self.statistics_printer.counters,
+        )?;
+        println!(
+            "[SNAPSHOT] Resumed from {:?} with {} pending orders",
+            path,
+            snapshot.pending_orders.len() + snapshot.in_flight_orders.len()
+        );
+        Ok(())
+    }
+
+    /// Cantidad de pedidos que se prepararon hasta el momento
+    pub fn orders_processed(&self) -> Result<u64, CoffeeMakerError> {
+        Ok(self.statistics_printer.counters.processed())
+    }
+
+    /// Cantidad de pedidos que se saltearon por falta de algun ingrediente
+    pub fn orders_skipped(&self) -> u64 {
+        self.statistics_printer.counters.skipped()
+    }
+
+    /// Pedidos que rechazo el control de admision, al llegar o al tomarlos un dispenser
+    pub fn orders_rejected(&self) -> Result<u64, CoffeeMakerError> {
+        Ok(self.orders_queue.lock_or_recover()?.counts().rejected)
+    }
+
+    /// Percentiles de las latencias medidas hasta el momento
+    pub fn latency_summary(&self) -> Result<LatencySummary, CoffeeMakerError> {
+        self.statistics_printer.latency_summary()
+    }
+
+    /// Caidas de los dispensers y reponedores, y si se los reinicio
+    pub fn incidents(&self) -> Result<Vec<Incident>, CoffeeMakerError> {
+        Ok(self.statistics_printer.incidents.lock()?.clone())
+    }
+
+    /// Tiempo transcurrido desde que se creo la cafetera segun su reloj
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Reemplaza la impresion periodica de estadisticas por un panel que se redibuja en la terminal.
+    /// Las estadisticas finales se siguen imprimiendo, y el resto de los sinks no se ve afectado
+    pub fn enable_dashboard(&mut self) {
+        if let Some(stdout) = &self.stdout_statistics {
+            if let Err(err) = stdout.set_periodic(false) {
+                println!("[ERROR ON STATISTICS THREAD] {:?}", err);
+            }
         }
+        self.dashboard = Some(Arc::new(Dashboard::new(
+            self.resources.clone(),
+            self.orders_queue.clone(),
+            self.statistics_printer.counters.clone(),
+            self.dispensers.clone(),
+            (
+                self.container_replenishers.clone(),
+                self.external_replenishers.clone(),
+            ),
+            self.clock.clone(),
+        )));
+    }
+
+    /// Habilita el socket de control en la ruta indicada. Mientras este habilitado la cafetera
+    /// sigue aceptando pedidos por el socket hasta que se la finalice con `shutdown` o una señal
+    pub fn enable_control_socket(&mut self, path: PathBuf) {
+        self.control_socket = Some(Arc::new(ControlSocket::new(
+            path,
+            self.control_handle(),
+            self.clock.clone(),
+        )));
+    }
+
+    /// Guarda periodicamente en `path` una foto del estado de la cafetera, con la que se puede retomar luego
+    pub fn enable_snapshots(&mut self, path: PathBuf) {
+        self.snapshot_writer = Some(Arc::new(SnapshotWriter::new(
+            path,
+            self.resources.clone(),
+            self.orders_queue.clone(),
+            self.dispensers.clone(),
+            self.statistics_printer.counters.clone(),
+            self.statistics_printer.epoch.clone(),
+            self.clock.clone(),
+        )));
     }
 
-    pub fn manage_orders(&self, path: String) {
-        let reader = self.create_reader_thread(path);
-        let replenisher_threads = self.create_container_replenisher_threads();
-        let water_replenisher_thread = self.create_water_replenisher_thread();
+    /// Modo de depuracion: verifica continuamente que no se creen ni se pierdan ingredientes ni pedidos.
+    /// Ante una violacion imprime un diagnostico y la ejecucion finaliza con un panic
+    pub fn enable_invariant_checker(&mut self) {
+        let replenishers = self
+            .container_replenishers
+            .iter()
+            .map(|replenisher| {
+                (
+                    replenisher.source_ingredient(),
+                    replenisher.dest_ingredient(),
+                )
+            })
+            .collect::<Vec<(Ingredient, Ingredient)>>();
+        self.invariant_checker = Some(Arc::new(InvariantChecker::new(
+            self.resources.clone(),
+            &replenishers,
+            self.orders_queue.clone(),
+            self.statistics_printer.counters.clone(),
+            self.statistics_printer.epoch.clone(),
+            self.clock.clone(),
+        )));
+    }
+
+    /// Cambia el orden en el que los dispensers usan los ingredientes de cada pedido.
+    /// Por defecto se mezclan de forma aleatoria
+    pub fn set_ingredient_ordering(
+        &self,
+        ordering: IngredientOrdering,
+    ) -> Result<(), CoffeeMakerError> {
+        self.ingredient_order.set(ordering)
+    }
+
+    /// Registra en el journal de `path` los eventos de la ejecucion, partiendo del estado actual de la cafetera
+    pub fn enable_journal(&self, path: &Path) -> Result<(), CoffeeMakerError> {
+        let state = CoffeeMakerSnapshot::take(
+            &self.resources,
+            &self.orders_queue,
+            &self.dispensers,
+            &self.statistics_printer.counters,
+        )?;
+        self.journal.open(path, state, self.clock.clone())
+    }
+
+    /// Devuelve un manejador con el que se puede controlar la cafetera desde otro hilo
+    pub fn control_handle(&self) -> ControlHandle {
+        ControlHandle::new(
+            (self.orders_queue.clone(), self.orders_cond.clone()),
+            (self.resources.clone(), self.ingredients_cond.clone()),
+            self.container_replenishers.clone(),
+            self.statistics_printer.epoch.clone(),
+            self.admission.clone(),
+            self.journal.clone(),
+            self.shutdown.clone(),
+        )
+    }
+
+    /// Procesa los pedidos de las fuentes configuradas en el builder, o solo los pendientes si no se configuro ninguna.
+    /// Las fuentes se consumen, por lo que una segunda ejecucion solo procesa los pedidos pendientes.
+    /// Devuelve el primer error que corto la ejecucion: una fuente que no se pudo leer, un hilo que se quedo sin
+    /// reinicios o una violacion de las invariantes
+    pub fn run(&self) -> Result<(), CoffeeMakerError> {
+        let sources = mem::take(&mut *self.order_sources.lock()?);
+        self.process(sources)
+    }
+
+    pub fn manage_orders(&self, path: String) -> Result<(), CoffeeMakerError> {
+        self.process(vec![Box::new(JsonFileSource::new(path))])
+    }
+
+    /// Procesa solo los pedidos que ya estan en la cola (por ejemplo, los restaurados de una foto)
+    /// y los que lleguen por el socket de control, sin leer ningun archivo
+    pub fn manage_pending_orders(&self) -> Result<(), CoffeeMakerError> {
+        self.process(Vec::new())
+    }
+
+    /// Aunque falle una fuente o un hilo se termina de procesar el resto de los pedidos, y recien al final
+    /// se devuelve el primer error
+    fn process(&self, sources: Vec<Box<dyn OrderSource>>) -> Result<(), CoffeeMakerError> {
+        if self.engine != Engine::Shared {
+            return self.process_with_engine(sources);
+        }
+        let invariant_checker_thread = self.create_invariant_checker_thread();
+        let control_socket_thread = self.create_control_socket_thread();
+        let readers = self.create_reader_threads(sources);
+        if readers.is_empty() && self.control_socket.is_none() {
+            if let Ok(mut queue) = self.orders_queue.lock_or_recover() {
+                queue.close();
+            }
+        }
+        let snapshot_thread = self.create_snapshot_thread();
+        let mut supervisor = Supervisor::new(
+            self.restart_budget,
+            self.statistics_printer.incidents.clone(),
+            self.clock.clone(),
+        );
+        self.spawn_replenishers(&mut supervisor);
+        let dashboard_thread = self.create_dashboard_thread();
         let statistics_thread = self.create_statistics_thread();
-        let dispenser_threads = self.create_dispenser_threads();
-        wait_for_reader(reader);
-        wait_for_dispensers(dispenser_threads);
-        self.wait_for_replenishers(replenisher_threads, water_replenisher_thread);
+        self.spawn_dispensers(&mut supervisor);
+        // Los dispensers terminan recien cuando se cierra la cola, por lo que se supervisa mientras se leen los pedidos
+        supervisor.wait_for(|worker| matches!(worker, WorkerId::Dispenser(_)));
+        self.discard_unattended_orders();
+        self.wait_for_control_socket(control_socket_thread);
+        self.signal_replenishers_to_finish();
+        supervisor.wait_for_all();
+        let readers_result = wait_for_readers(readers);
+        let invariants_result = self.wait_for_invariant_checker(invariant_checker_thread);
+        self.wait_for_snapshot_thread(snapshot_thread);
+        self.wait_for_dashboard_thread(dashboard_thread);
         self.wait_for_statistics_thread(statistics_thread);
+        readers_result?;
+        if let Some(err) = supervisor.first_failure() {
+            return Err(err);
+        }
+        invariants_result
     }
 
-    fn create_reader_thread(&self, path: String) -> JoinHandle<Result<(), CoffeeMakerError>> {
+    /// Procesa los pedidos con el motor alternativo configurado, que parte del estado actual de la cafetera,
+    /// y al finalizar vuelca su estado final sobre la cafetera
+    fn process_with_engine(
+        &self,
+        sources: Vec<Box<dyn OrderSource>>,
+    ) -> Result<(), CoffeeMakerError> {
+        let mut containers = HashMap::new();
+        for (ingredient, container_lock) in self.resources.iter() {
+            containers.insert(*ingredient, container_lock.lock_or_recover()?.clone());
+        }
+        let (pending_orders, next_id) = {
+            let mut queue = self.orders_queue.lock_or_recover()?;
+            let next_id = queue.next_id();
+            (std::iter::from_fn(|| queue.pop()).collect(), next_id)
+        };
+        let setup = EngineSetup {
+            containers,
+            container_replenishers: self
+                .container_replenishers
+                .iter()
+                .map(|replenisher| {
+                    (
+                        replenisher.source_ingredient(),
+                        replenisher.dest_ingredient(),
+                    )
+                })
+                .collect(),
+            external_replenishers: self
+                .external_replenishers
+                .iter()
+                .map(|replenisher| replenisher.ingredient())
+                .collect(),
+            dispensers: self.dispensers.len(),
+            pending_orders,
+            next_id,
+            orders_processed: self.orders_processed()?,
+            ingredient_order: self.ingredient_order.clone(),
+            statistics_printer: self.statistics_printer.clone(),
+            clock: self.clock.clone(),
+            shutdown: self.shutdown.clone(),
+        };
+        let run = match self.engine {
+            Engine::Async => async_engine::run(setup, sources)?,
+            _ => actors::run(setup, sources)?,
+        };
+        self.apply_engine_run(run)
+    }
+
+    fn apply_engine_run(&self, run: EngineRun) -> Result<(), CoffeeMakerError> {
+        for (ingredient, state) in run.containers {
+            let mut container = self
+                .resources
+                .get(&ingredient)
+                .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))?
+                .lock_or_recover()?;
+            container.level.set(state.remaining(), state.consumed());
+            container.transferred = state.transferred;
+            container.wasted = state.wasted;
+            container.supplied = state.supplied;
+            container.finished = state.finished;
+        }
+        self.statistics_printer
+            .counters
+            .set_processed(run.orders_processed);
+        self.statistics_printer
+            .counters
+            .set_skipped(run.orders_skipped);
+        *self.statistics_printer.latencies.lock()? = run.latencies;
+        Ok(())
+    }
+
+    fn create_control_socket_thread(&self) -> Option<JoinHandle<Result<(), CoffeeMakerError>>> {
+        let control_socket_clone = self.control_socket.clone()?;
+        // El socket es una fuente de pedidos mas, se registra antes de que el lector pueda cerrar la cola
+        match self.orders_queue.lock_or_recover() {
+            Ok(mut queue) => queue.register_source(),
+            Err(err) => {
+                println!("[ERROR ON CONTROL SOCKET] {:?}", err);
+                return None;
+            }
+        }
         let orders_queue_clone = self.orders_queue.clone();
         let orders_cond_clone = self.orders_cond.clone();
-        thread::spawn(move || read_and_add_orders(orders_queue_clone, orders_cond_clone, path))
+        Some(thread::spawn(move || {
+            let result = control_socket_clone.serve();
+            if result.is_err() {
+                if let Ok(mut queue) = orders_queue_clone.lock_or_recover() {
+                    queue.finish_source();
+                    orders_cond_clone.notify_all();
+                }
+            }
+            result
+        }))
     }
 
-    fn create_container_replenisher_threads(
+    /// Crea un hilo lector por fuente. Se registran todas antes de iniciar los hilos,
+    /// asi una fuente que se agota rapido no cierra la cola mientras las demas siguen leyendo
+    fn create_reader_threads(
         &self,
+        sources: Vec<Box<dyn OrderSource>>,
     ) -> Vec<JoinHandle<Result<(), CoffeeMakerError>>> {
-        self.container_replenishers
-            .iter()
-            .map(|replenisher| {
-                let replenisher_clone = replenisher.clone();
-                thread::spawn(move || replenisher_clone.replenish_container())
+        match self.orders_queue.lock_or_recover() {
+            Ok(mut queue) => {
+                for _ in &sources {
+                    queue.register_source();
+                }
+            }
+            Err(err) => {
+                println!("[ERROR ON READER] {:?}", err);
+                return Vec::new();
+            }
+        }
+        sources
+            .into_iter()
+            .map(|source| {
+                let orders_queue_clone = self.orders_queue.clone();
+                let orders_cond_clone = self.orders_cond.clone();
+                let admission_clone = self.admission.clone();
+                thread::spawn(move || {
+                    read_and_add_orders(
+                        orders_queue_clone,
+                        orders_cond_clone,
+                        admission_clone,
+                        source,
+                    )
+                })
             })
             .collect()
     }
 
-    fn create_water_replenisher_thread(&self) -> JoinHandle<Result<(), CoffeeMakerError>> {
-        let water_replenisher_clone = self.water_replenisher.clone();
-        thread::spawn(move || water_replenisher_clone.replenish_container())
+    /// Toma el estado inicial antes de que arranque cualquier otro hilo
+    fn create_invariant_checker_thread(&self) -> Option<JoinHandle<Result<(), CoffeeMakerError>>> {
+        let invariant_checker_clone = self.invariant_checker.clone()?;
+        if let Err(err) = invariant_checker_clone.start() {
+            println!("[ERROR ON INVARIANT CHECKER] {:?}", err);
+            return None;
+        }
+        Some(thread::spawn(move || {
+            invariant_checker_clone.check_invariants()
+        }))
+    }
+
+    fn create_snapshot_thread(&self) -> Option<JoinHandle<Result<(), CoffeeMakerError>>> {
+        let snapshot_writer_clone = self.snapshot_writer.clone()?;
+        Some(thread::spawn(move || {
+            snapshot_writer_clone.write_snapshots()
+        }))
+    }
+
+    fn spawn_replenishers(&self, supervisor: &mut Supervisor) {
+        for replenisher in &self.container_replenishers {
+            supervisor.spawn(
+                WorkerId::ContainerReplenisher(replenisher.dest_ingredient()),
+                replenisher.clone(),
+            );
+        }
+        for replenisher in &self.external_replenishers {
+            supervisor.spawn(
+                WorkerId::ExternalReplenisher(replenisher.ingredient()),
+                replenisher.clone(),
+            );
+        }
+    }
+
+    fn create_dashboard_thread(&self) -> Option<JoinHandle<Result<(), CoffeeMakerError>>> {
+        let dashboard_clone = self.dashboard.clone()?;
+        Some(thread::spawn(move || dashboard_clone.show()))
     }
 
     fn create_statistics_thread(&self) -> JoinHandle<Result<(), CoffeeMakerError>> {
@@ -148,41 +627,93 @@ impl CoffeeMaker {
         thread::spawn(move || statistics_printer_clone.process_statistics())
     }
 
-    fn create_dispenser_threads(&self) -> Vec<JoinHandle<Result<(), CoffeeMakerError>>> {
-        self.dispensers
-            .iter()
-            .map(|dispenser| {
-                let dispenser_clone = dispenser.clone();
-                thread::spawn(move || dispenser_clone.handle_orders())
-            })
-            .collect()
+    fn spawn_dispensers(&self, supervisor: &mut Supervisor) {
+        for dispenser in &self.dispensers {
+            supervisor.spawn(WorkerId::Dispenser(dispenser.id()), dispenser.clone());
+        }
     }
 
-    fn wait_for_statistics_thread(
+    /// Si todos los dispensers se quedaron sin reinicios, los pedidos que quedaron en la cola no los va a preparar nadie
+    fn discard_unattended_orders(&self) {
+        match self.orders_queue.lock_or_recover() {
+            Ok(mut queue) if !queue.is_empty() || !queue.finished => {
+                // Tambien se cierra la cola para que los lectores dejen de agregar pedidos
+                let discarded = queue.abort();
+                println!(
+                    "[ERROR] No dispensers left, discarded {} pending orders",
+                    discarded
+                );
+                self.orders_cond.notify_all();
+            }
+            Ok(_) => {}
+            Err(err) => println!("[ERROR ON DISPENSER] {}", err),
+        }
+    }
+
+    fn wait_for_control_socket(
         &self,
-        statistics_thread: JoinHandle<Result<(), CoffeeMakerError>>,
+        control_socket_thread: Option<JoinHandle<Result<(), CoffeeMakerError>>>,
     ) {
-        self.statistics_printer.finish();
-        if let Err(err) = statistics_thread.join() {
-            println!("[ERROR ON STATISTICS THREAD] {:?}", err);
+        if let (Some(control_socket), Some(thread)) = (&self.control_socket, control_socket_thread)
+        {
+            control_socket.finish();
+            if let Err(err) = thread.join() {
+                println!("[ERROR ON CONTROL SOCKET] {:?}", err);
+            }
+        }
+    }
+
+    /// Se espera despues de los dispensers y reponedores para que la ultima verificacion sea exacta.
+    /// Si el verificador encontro una violacion, su hilo termino con un panic y se devuelve `InvariantViolation`
+    fn wait_for_invariant_checker(
+        &self,
+        invariant_checker_thread: Option<JoinHandle<Result<(), CoffeeMakerError>>>,
+    ) -> Result<(), CoffeeMakerError> {
+        if let (Some(invariant_checker), Some(thread)) =
+            (&self.invariant_checker, invariant_checker_thread)
+        {
+            invariant_checker.finish();
+            match thread.join() {
+                Ok(Err(err)) => println!("[ERROR ON INVARIANT CHECKER] {}", err),
+                Err(_) => return Err(CoffeeMakerError::InvariantViolation),
+                Ok(Ok(())) => {}
+            }
         }
+        Ok(())
     }
 
-    fn wait_for_replenishers(
+    fn wait_for_snapshot_thread(
         &self,
-        replenisher_threads: Vec<JoinHandle<Result<(), CoffeeMakerError>>>,
-        water_replenisher_thread: JoinHandle<Result<(), CoffeeMakerError>>,
+        snapshot_thread: Option<JoinHandle<Result<(), CoffeeMakerError>>>,
     ) {
-        self.signal_replenishers_to_finish();
+        if let (Some(snapshot_writer), Some(thread)) = (&self.snapshot_writer, snapshot_thread) {
+            snapshot_writer.finish();
+            if let Err(err) = thread.join() {
+                println!("[ERROR ON SNAPSHOT] {:?}", err);
+            }
+        }
+    }
 
-        for replenisher in replenisher_threads {
-            if let Err(err) = replenisher.join() {
-                println!("[ERROR ON REPLENISHER] {:?}", err);
+    fn wait_for_dashboard_thread(
+        &self,
+        dashboard_thread: Option<JoinHandle<Result<(), CoffeeMakerError>>>,
+    ) {
+        if let (Some(dashboard), Some(thread)) = (&self.dashboard, dashboard_thread) {
+            dashboard.finish();
+            if let Err(err) = thread.join() {
+                println!("[ERROR ON DASHBOARD] {:?}", err);
             }
         }
+    }
 
-        if let Err(err) = water_replenisher_thread.join() {
-            println!("[ERROR ON REPLENISHER] {:?}", err);
+    /// Se espera despues del panel para que las estadisticas finales queden debajo de su ultimo cuadro
+    fn wait_for_statistics_thread(
+        &self,
+        statistics_thread: JoinHandle<Result<(), CoffeeMakerError>>,
+    ) {
+        self.statistics_printer.finish();
+        if let Err(err) = statistics_thread.join() {
+            println!("[ERROR ON STATISTICS THREAD] {:?}", err);
         }
     }
 
@@ -190,22 +721,28 @@ impl CoffeeMaker {
         for replenisher in &self.container_replenishers {
             replenisher.finish();
         }
-        self.water_replenisher.finish();
-    }
-}
-
-fn wait_for_reader(reader: JoinHandle<Result<(), CoffeeMakerError>>) {
-    if let Err(err) = reader.join() {
-        println!("[ERROR ON READER] {:?}", err);
+        for replenisher in &self.external_replenishers {
+            replenisher.finish();
+        }
     }
 }
 
-fn wait_for_dispensers(dispenser_threads: Vec<JoinHandle<Result<(), CoffeeMakerError>>>) {
-    for dispenser in dispenser_threads {
-        if let Err(err) = dispenser.join() {
-            println!("[ERROR ON DISPENSER] {:?}", err);
+/// Devuelve el error de la primera fuente que no se pudo leer
+fn wait_for_readers(
+    readers: Vec<JoinHandle<Result<(), CoffeeMakerError>>>,
+) -> Result<(), CoffeeMakerError> {
+    let mut result = Ok(());
+    for reader in readers {
+        match reader.join() {
+            Ok(Err(err)) => {
+                println!("[ERROR ON READER] {}", err);
+                result = result.and(Err(err));
+            }
+            Err(err) => println!("[ERROR ON READER] {:?}", err),
+            Ok(Ok(())) => {}
         }
     }
+    result
 }
 
 impl Default for CoffeeMaker {
@@ -216,46 +753,177 @@ impl Default for CoffeeMaker {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError},
+    };
+
     use super::*;
+    use crate::{
+        clock::VirtualClock,
+        constants::{
+            A_WATER_STORAGE, C_CACAO_STORAGE, E_FOAM_STORAGE, G_GRAINS_STORAGE, L_MILK_STORAGE,
+            M_COFFEE_STORAGE, N_DISPENSERS,
+        },
+        container::ConsumePath,
+        control::ShutdownMode,
+        dispenser::DispenserState,
+        order::{Order, OrderStatus},
+        order_source::{OrderIngredients, SourceOrder, VecSource},
+        statistics_sink::MemorySink,
+    };
+
+    /// Reloj virtual que hace caer a los dispensers las primeras `crashes` veces que sirven un ingrediente
+    struct CrashingClock {
+        clock: VirtualClock,
+        crashes: AtomicUsize,
+    }
+
+    impl CrashingClock {
+        fn new(crashes: usize) -> CrashingClock {
+            CrashingClock {
+                clock: VirtualClock::new(),
+                crashes: AtomicUsize::new(crashes),
+            }
+        }
+    }
+
+    impl Clock for CrashingClock {
+        fn sleep(&self, duration: Duration) {
+            let in_dispenser = thread::current()
+                .name()
+                .is_some_and(|name| name.starts_with("dispenser"));
+            if in_dispenser
+                && self
+                    .crashes
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |crashes| {
+                        crashes.checked_sub(1)
+                    })
+                    .is_ok()
+            {
+                panic!("injected crash");
+            }
+            self.clock.sleep(duration);
+        }
+
+        fn wait(&self, duration: Duration) {
+            self.clock.wait(duration);
+        }
+
+        fn now(&self) -> Duration {
+            self.clock.now()
+        }
+
+        fn local_now(&self) -> Duration {
+            self.clock.local_now()
+        }
+
+        fn real_duration(&self, duration: Duration) -> Duration {
+            self.clock.real_duration(duration)
+        }
+    }
+
+    /// Fuente que entrega los pedidos que le llegan por un canal, y se agota cuando se cierra
+    struct ChannelSource {
+        orders: Receiver<OrderIngredients>,
+    }
+
+    impl OrderSource for ChannelSource {
+        fn name(&self) -> String {
+            String::from("channel")
+        }
+
+        fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+            Ok(self.orders.recv().ok().map(SourceOrder::from))
+        }
+
+        fn next_order_until(
+            &mut self,
+            stopped: &dyn Fn() -> bool,
+        ) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+            loop {
+                match self.orders.recv_timeout(Duration::from_millis(10)) {
+                    Ok(order) => return Ok(Some(SourceOrder::from(order))),
+                    Err(RecvTimeoutError::Timeout) if !stopped() => {}
+                    Err(_) => return Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Corre la cafetera con `engine` y una fuente que no se cierra, y la finaliza con `mode` desde el control
+    fn shutdown_with_an_open_source(engine: Engine, mode: ShutdownMode) -> u64 {
+        let (orders, receiver) = mpsc::channel();
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .engine(engine)
+            .without_stdout_statistics()
+            .order_source(ChannelSource { orders: receiver })
+            .build()
+            .expect("Fail test");
+
+        thread::scope(|scope| {
+            let run = scope.spawn(|| coffee_maker.run());
+            orders
+                .send(vec![(Ingredient::Cacao, 10)])
+                .expect("Fail test");
+            coffee_maker
+                .control_handle()
+                .shutdown(mode)
+                .expect("Fail test");
+            run.join().expect("Fail test").expect("Fail test");
+        });
+        coffee_maker.orders_processed().expect("Fail test")
+    }
 
     #[test]
     fn should_finish_correctly_if_the_file_does_not_exists() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("not-a-file.json"));
+        let result = coffee_maker.manage_orders(String::from("not-a-file.json"));
+        assert_eq!(0, coffee_maker.statistics_printer.counters.processed());
         assert_eq!(
-            0,
-            *coffee_maker
-                .statistics_printer
-                .processed
-                .read()
-                .expect("Fail test")
+            Err(CoffeeMakerError::FileNotFound {
+                path: String::from("not-a-file.json")
+            }),
+            result
         );
     }
 
+    #[test]
+    fn should_process_the_other_sources_and_report_the_malformed_one() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .orders_file("tests/wrong_format.json")
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        let result = coffee_maker.run();
+
+        assert_eq!(41, coffee_maker.orders_processed().expect("Fail test"));
+        let error = result.expect_err("Fail test");
+        assert!(matches!(
+            &error,
+            CoffeeMakerError::MalformedOrders { source, .. } if source == "tests/wrong_format.json"
+        ));
+        assert_eq!(4, error.exit_code());
+    }
+
     #[test]
     fn should_finish_correctly_if_there_are_no_orders_on_the_file() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/no_orders.json"));
-        assert_eq!(
-            0,
-            *coffee_maker
-                .statistics_printer
-                .processed
-                .read()
-                .expect("Fail test")
-        );
+        coffee_maker
+            .manage_orders(String::from("tests/no_orders.json"))
+            .expect("Fail test");
+        assert_eq!(0, coffee_maker.statistics_printer.counters.processed());
     }
 
     #[test]
     fn should_process_an_order_and_finish() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/simple_order.json"));
-
-        let processed = *coffee_maker
-            .statistics_printer
-            .processed
-            .read()
+        coffee_maker
+            .manage_orders(String::from("tests/simple_order.json"))
             .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
         assert_eq!(1, processed);
 
         let resources = &coffee_maker.statistics_printer.resources;
@@ -276,36 +944,798 @@ mod tests {
         let grains = grains.lock().expect("Fail test");
         let cold_milk = cold_milk.lock().expect("Fail test");
 
+        assert_eq!(C_CACAO_STORAGE - 60, cacao.remaining());
+        assert_eq!(60, cacao.consumed());
+
+        assert_eq!(E_FOAM_STORAGE - 70, milk_foam.remaining());
+        assert_eq!(70, milk_foam.consumed());
+
+        assert_eq!(M_COFFEE_STORAGE - 100, ground_coffee.remaining());
+        assert_eq!(100, ground_coffee.consumed());
+
+        assert_eq!(A_WATER_STORAGE - 150, water.remaining());
+        assert_eq!(150, water.consumed());
+
+        assert_eq!(G_GRAINS_STORAGE, grains.remaining());
+        assert_eq!(0, grains.consumed());
+
+        assert_eq!(L_MILK_STORAGE, cold_milk.remaining());
+        assert_eq!(0, cold_milk.consumed());
+    }
+
+    #[test]
+    fn should_show_the_final_state_on_the_dashboard() {
+        let mut coffee_maker = CoffeeMaker::new();
+        coffee_maker.enable_dashboard();
+        coffee_maker
+            .manage_orders(String::from("tests/simple_order.json"))
+            .expect("Fail test");
+
+        let snapshot = coffee_maker
+            .dashboard
+            .as_ref()
+            .expect("Fail test")
+            .take_snapshot()
+            .expect("Fail test");
+        assert_eq!(1, snapshot.orders_processed);
+        assert_eq!(0, snapshot.queue_depth);
+        assert_eq!(N_DISPENSERS, snapshot.dispensers.len());
+        assert!(snapshot
+            .dispensers
+            .iter()
+            .all(|(_, state)| *state == DispenserState::Idle));
+        let cacao = snapshot
+            .levels
+            .iter()
+            .find(|level| level.ingredient == Ingredient::Cacao)
+            .expect("Fail test");
         assert_eq!(C_CACAO_STORAGE - 60, cacao.remaining);
-        assert_eq!(60, cacao.consumed);
+    }
 
-        assert_eq!(E_FOAM_STORAGE - 70, milk_foam.remaining);
-        assert_eq!(70, milk_foam.consumed);
+    #[test]
+    fn should_not_process_orders_if_aborted_before_starting() {
+        let coffee_maker = CoffeeMaker::new();
+        coffee_maker
+            .control_handle()
+            .shutdown(ShutdownMode::Abort)
+            .expect("Fail test");
+        coffee_maker
+            .manage_orders(String::from("tests/multiple_orders.json"))
+            .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
+        assert_eq!(0, processed);
+    }
 
-        assert_eq!(M_COFFEE_STORAGE - 100, ground_coffee.remaining);
-        assert_eq!(100, ground_coffee.consumed);
+    #[test]
+    fn should_not_process_orders_with_the_actors_if_aborted_before_starting() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .engine(Engine::Actors)
+            .without_stdout_statistics()
+            .build()
+            .expect("Fail test");
+        coffee_maker
+            .control_handle()
+            .shutdown(ShutdownMode::Abort)
+            .expect("Fail test");
+        coffee_maker
+            .manage_orders(String::from("tests/multiple_orders.json"))
+            .expect("Fail test");
 
-        assert_eq!(A_WATER_STORAGE - 150, water.remaining);
-        assert_eq!(150, water.consumed);
+        assert_eq!(0, coffee_maker.orders_processed().expect("Fail test"));
+    }
 
-        assert_eq!(G_GRAINS_STORAGE, grains.remaining);
-        assert_eq!(0, grains.consumed);
+    #[test]
+    fn should_not_process_orders_with_the_async_engine_if_aborted_before_starting() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .engine(Engine::Async)
+            .without_stdout_statistics()
+            .build()
+            .expect("Fail test");
+        coffee_maker
+            .control_handle()
+            .shutdown(ShutdownMode::Abort)
+            .expect("Fail test");
+        coffee_maker
+            .manage_orders(String::from("tests/multiple_orders.json"))
+            .expect("Fail test");
 
-        assert_eq!(L_MILK_STORAGE, cold_milk.remaining);
-        assert_eq!(0, cold_milk.consumed);
+        assert_eq!(0, coffee_maker.orders_processed().expect("Fail test"));
     }
 
-    /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000
     #[test]
-    fn should_process_three_big_orders_and_replenish_the_containers() {
+    fn should_stop_reading_the_open_sources_of_the_async_engine_on_shutdown() {
+        assert!(shutdown_with_an_open_source(Engine::Async, ShutdownMode::Drain) <= 1);
+        assert!(shutdown_with_an_open_source(Engine::Async, ShutdownMode::Abort) <= 1);
+    }
+
+    #[test]
+    fn should_stop_reading_the_open_sources_of_the_actors_on_shutdown() {
+        assert!(shutdown_with_an_open_source(Engine::Actors, ShutdownMode::Drain) <= 1);
+        assert!(shutdown_with_an_open_source(Engine::Actors, ShutdownMode::Abort) <= 1);
+    }
+
+    #[test]
+    fn should_not_take_orders_while_paused_and_finish_them_after_resuming() {
+        let coffee_maker = Arc::new(CoffeeMaker::new());
+        let control = coffee_maker.control_handle();
+        control.pause(None).expect("Fail test");
+
+        let coffee_maker_clone = coffee_maker.clone();
+        let handle = thread::spawn(move || {
+            coffee_maker_clone.manage_orders(String::from("tests/multiple_orders.json"))
+        });
+
+        while !coffee_maker
+            .orders_queue
+            .lock()
+            .expect("Fail test")
+            .finished
+        {
+            thread::yield_now();
+        }
+        assert_eq!(0, coffee_maker.statistics_printer.counters.processed());
+
+        control.resume(None).expect("Fail test");
+        handle.join().expect("Fail test").expect("Fail test");
+        assert_eq!(41, coffee_maker.statistics_printer.counters.processed());
+    }
+
+    #[test]
+    fn should_resume_the_paused_dispensers_to_drain_the_queue() {
+        let coffee_maker = Arc::new(CoffeeMaker::new());
+        let control = coffee_maker.control_handle();
+        control.pause(None).expect("Fail test");
+
+        let coffee_maker_clone = coffee_maker.clone();
+        let handle = thread::spawn(move || {
+            coffee_maker_clone.manage_orders(String::from("tests/multiple_orders.json"))
+        });
+        while !coffee_maker
+            .orders_queue
+            .lock()
+            .expect("Fail test")
+            .finished
+        {
+            thread::yield_now();
+        }
+
+        control.shutdown(ShutdownMode::Drain).expect("Fail test");
+        handle.join().expect("Fail test").expect("Fail test");
+        assert_eq!(41, coffee_maker.statistics_printer.counters.processed());
+        assert!(!coffee_maker
+            .orders_queue
+            .lock()
+            .expect("Fail test")
+            .is_paused(0));
+    }
+
+    #[test]
+    fn should_serve_the_small_orders_first_when_the_cacao_is_not_enough() {
+        let coffee_maker = Arc::new(
+            CoffeeMakerBuilder::new()
+                .without_stdout_statistics()
+                .check_invariants()
+                .scheduling_policy(SchedulingPolicy::InventoryAware)
+                .orders_file("tests/scarce_cacao.json")
+                .build()
+                .expect("Fail test"),
+        );
+        let control = coffee_maker.control_handle();
+        control.pause(None).expect("Fail test");
+
+        let coffee_maker_clone = coffee_maker.clone();
+        let handle = thread::spawn(move || coffee_maker_clone.run());
+        while !coffee_maker
+            .orders_queue
+            .lock()
+            .expect("Fail test")
+            .finished
+        {
+            thread::yield_now();
+        }
+
+        control.resume(None).expect("Fail test");
+        handle.join().expect("Fail test").expect("Fail test");
+        // Los 100 pedidos chicos y 4 de los 5 grandes, que en orden de llegada se hubieran llevado todo el cacao
+        assert_eq!(104, coffee_maker.orders_processed().expect("Fail test"));
+        assert_eq!(1, coffee_maker.orders_skipped());
+    }
+
+    #[test]
+    fn should_keep_accepting_orders_from_the_control_socket_until_shutdown() {
+        let path = std::env::temp_dir().join(format!(
+            "coffee-maker-socket-test-{}.sock",
+            std::process::id()
+        ));
+        let mut coffee_maker = CoffeeMaker::new();
+        coffee_maker.enable_control_socket(path);
+        let coffee_maker = Arc::new(coffee_maker);
+
+        let coffee_maker_clone = coffee_maker.clone();
+        let handle = thread::spawn(move || {
+            coffee_maker_clone.manage_orders(String::from("tests/simple_order.json"))
+        });
+
+        let control = coffee_maker.control_handle();
+        while control.order_status(0).expect("Fail test") != Some(OrderStatus::Served) {
+            thread::yield_now();
+        }
+        let id = control
+            .submit(vec![(Ingredient::HotWater, 10)])
+            .expect("Fail test");
+        control.shutdown(ShutdownMode::Drain).expect("Fail test");
+        handle.join().expect("Fail test").expect("Fail test");
+
+        assert_eq!(
+            Some(OrderStatus::Served),
+            control.order_status(id).expect("Fail test")
+        );
+        assert_eq!(2, coffee_maker.statistics_printer.counters.processed());
+    }
+
+    #[test]
+    fn should_process_the_orders_of_all_the_sources() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .orders_file("tests/multiple_orders.json")
+            .orders_file("tests/simple_order.json")
+            .order_source(VecSource::new(vec![vec![(Ingredient::HotWater, 10)]]))
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(43, coffee_maker.orders_processed().expect("Fail test"));
+        assert!(
+            coffee_maker
+                .orders_queue
+                .lock()
+                .expect("Fail test")
+                .finished
+        );
+    }
+
+    #[test]
+    fn should_give_the_same_statistics_to_every_sink() {
+        let first = Arc::new(MemorySink::new());
+        let second = Arc::new(MemorySink::new());
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .statistics_sink(first.clone())
+            .statistics_sink(second.clone())
+            .orders_file("tests/simple_order.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        let snapshots = first.snapshots().expect("Fail test");
+        assert_eq!(snapshots, second.snapshots().expect("Fail test"));
+        assert!(first.is_finished().expect("Fail test"));
+        let last = snapshots.last().expect("Fail test");
+        assert_eq!(snapshots.len() as u64 - 1, last.tick);
+        assert_eq!(1, last.orders_processed);
+        assert_eq!(1, last.latencies.preparation.count);
+        assert_eq!(SchedulingPolicy::Fifo, last.scheduling);
+    }
+
+    #[test]
+    fn should_report_the_latencies_of_the_selected_dispatch_policy() {
+        for policy in [
+            SchedulingPolicy::ShortestJobFirst,
+            SchedulingPolicy::FairShare,
+        ] {
+            let sink = Arc::new(MemorySink::new());
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .without_stdout_statistics()
+                .statistics_sink(sink.clone())
+                .scheduling_policy(policy)
+                .orders_file("tests/multiple_orders.json")
+                .orders_file("tests/simple_order.json")
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+
+            let snapshots = sink.snapshots().expect("Fail test");
+            let last = snapshots.last().expect("Fail test");
+            assert_eq!(policy, last.scheduling);
+            assert_eq!(42, last.orders_processed);
+            assert_eq!(42, last.latencies.queue_wait.count);
+        }
+    }
+
+    /// Los tests usan el reloj virtual, las latencias se miden con el mismo reloj que el trabajo.
+    /// Cada pedido de `multiple_orders.json` lleva 40ms de preparacion
+    #[test]
+    fn should_measure_the_latencies_with_the_clock_of_the_coffee_maker() {
+        let sink = Arc::new(MemorySink::new());
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .statistics_sink(sink.clone())
+            .dispensers(1)
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        let snapshots = sink.snapshots().expect("Fail test");
+        let latencies = &snapshots.last().expect("Fail test").latencies;
+        assert_eq!(41, latencies.preparation.count);
+        assert!(latencies.preparation.p50 >= 40_000);
+        assert!(latencies.queue_wait.max >= 40 * 40_000);
+    }
+
+    #[test]
+    fn should_keep_the_total_of_each_ingredient_chain_in_every_snapshot() {
+        let sink = Arc::new(MemorySink::new());
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .statistics_sink(sink.clone())
+            .orders_file("tests/replenish_containers.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        let snapshots = sink.snapshots().expect("Fail test");
+        assert!(!snapshots.is_empty());
+        let chains = [
+            (
+                Ingredient::GrainsToGrind,
+                Ingredient::GroundCoffee,
+                M_COFFEE_STORAGE,
+            ),
+            (Ingredient::ColdMilk, Ingredient::MilkFoam, E_FOAM_STORAGE),
+        ];
+        for snapshot in snapshots {
+            let container = |ingredient| {
+                snapshot
+                    .containers
+                    .iter()
+                    .find(|container| container.ingredient == ingredient)
+                    .expect("Fail test")
+            };
+            for (source, dest, initial) in chains {
+                let (source, dest) = (container(source), container(dest));
+                assert_eq!(
+                    initial + source.consumed,
+                    dest.remaining + dest.consumed,
+                    "Tick {}",
+                    snapshot.tick
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_hold_the_invariants_while_processing_the_orders() {
+        for path in [
+            "tests/multiple_orders.json",
+            "tests/replenish_containers.json",
+            "tests/consume_all.json",
+        ] {
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .without_stdout_statistics()
+                .check_invariants()
+                .orders_file(path)
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+        }
+    }
+
+    #[test]
+    fn should_reject_the_orders_once_an_ingredient_can_no_longer_be_obtained() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .check_invariants()
+            .admission_control()
+            .orders_file("tests/consume_all.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        let rejected = coffee_maker.orders_rejected().expect("Fail test");
+        assert_eq!(500, coffee_maker.orders_processed().expect("Fail test"));
+        assert_eq!(2529, rejected + coffee_maker.orders_skipped());
+        assert!(rejected > 0);
+    }
+
+    #[test]
+    fn should_give_the_same_summary_with_every_engine() {
+        let summaries = [Engine::Shared, Engine::Actors, Engine::Async].map(|engine| {
+            let sink = Arc::new(MemorySink::new());
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .engine(engine)
+                .without_stdout_statistics()
+                .statistics_sink(sink.clone())
+                .orders_file("tests/multiple_orders.json")
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+            assert_eq!(41, coffee_maker.orders_processed().expect("Fail test"));
+            let last = sink
+                .snapshots()
+                .expect("Fail test")
+                .pop()
+                .expect("Fail test");
+            (
+                last.orders_processed,
+                last.containers,
+                last.latencies.preparation.count,
+            )
+        });
+        assert_eq!(summaries[0], summaries[1]);
+        assert_eq!(summaries[0], summaries[2]);
+    }
+
+    #[test]
+    fn should_restart_the_crashed_dispensers_and_prepare_their_orders() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .clock(Arc::new(CrashingClock::new(2)))
+            .without_stdout_statistics()
+            .check_invariants()
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(41, coffee_maker.orders_processed().expect("Fail test"));
+        let incidents = coffee_maker.incidents().expect("Fail test");
+        assert_eq!(2, incidents.len());
+        for incident in incidents {
+            assert!(matches!(incident.worker, WorkerId::Dispenser(_)));
+            assert_eq!("panic: injected crash", incident.cause);
+            assert!(incident.requeued_order.is_some());
+            assert!(incident.restarted);
+        }
+    }
+
+    #[test]
+    fn should_restart_a_crashed_dispenser_while_the_source_is_still_open() {
+        let (orders, receiver) = mpsc::channel();
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .dispensers(1)
+            .clock(Arc::new(CrashingClock::new(1)))
+            .without_stdout_statistics()
+            .order_source(ChannelSource { orders: receiver })
+            .build()
+            .expect("Fail test");
+
+        thread::scope(|scope| {
+            let run = scope.spawn(|| coffee_maker.run());
+            orders
+                .send(vec![(Ingredient::Cacao, 10)])
+                .expect("Fail test");
+            let mut processed = 0;
+            for _ in 0..500 {
+                processed = coffee_maker.orders_processed().expect("Fail test");
+                if processed == 1 {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            drop(orders);
+            run.join().expect("Fail test").expect("Fail test");
+            assert_eq!(1, processed);
+        });
+        let incidents = coffee_maker.incidents().expect("Fail test");
+        assert_eq!(1, incidents.len());
+        assert!(incidents[0].restarted);
+    }
+
+    /// Con `ConsumePath::Locked` el dispenser se cae mientras sirve con el contenedor tomado, y lo deja envenenado
+    #[test]
+    fn should_recover_the_containers_poisoned_by_a_dispenser_that_crashed_mid_consume() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .consume_path(ConsumePath::Locked)
+            .clock(Arc::new(CrashingClock::new(2)))
+            .without_stdout_statistics()
+            .check_invariants()
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(41, coffee_maker.orders_processed().expect("Fail test"));
+        let incidents = coffee_maker.incidents().expect("Fail test");
+        assert_eq!(2, incidents.len());
+        assert!(incidents
+            .iter()
+            .all(|incident| incident.requeued_order.is_some() && incident.restarted));
+        for container in coffee_maker.resources.values() {
+            assert!(!container.is_poisoned());
+        }
+    }
+
+    #[test]
+    fn should_keep_taking_orders_after_the_queue_lock_is_poisoned() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .without_stdout_statistics()
+            .check_invariants()
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        let orders_queue = coffee_maker.orders_queue.clone();
+        let crashed = thread::spawn(move || {
+            let _queue = orders_queue.lock().expect("Fail test");
+            panic!("injected crash");
+        })
+        .join();
+        assert!(crashed.is_err());
+        assert!(coffee_maker.orders_queue.is_poisoned());
+
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(41, coffee_maker.orders_processed().expect("Fail test"));
+        assert!(coffee_maker.incidents().expect("Fail test").is_empty());
+    }
+
+    #[test]
+    fn should_discard_the_orders_left_when_no_dispenser_can_be_restarted() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .dispensers(1)
+            .restart_budget(0)
+            .clock(Arc::new(CrashingClock::new(1)))
+            .without_stdout_statistics()
+            .check_invariants()
+            .orders_file("tests/multiple_orders.json")
+            .build()
+            .expect("Fail test");
+        let result = coffee_maker.run();
+
+        assert_eq!(
+            Err(CoffeeMakerError::WorkerGaveUp {
+                worker: String::from("dispenser 0"),
+                cause: String::from("panic: injected crash"),
+            }),
+            result
+        );
+        assert_eq!(7, result.expect_err("Fail test").exit_code());
+        assert_eq!(0, coffee_maker.orders_processed().expect("Fail test"));
+        let incidents = coffee_maker.incidents().expect("Fail test");
+        assert_eq!(1, incidents.len());
+        assert_eq!(WorkerId::Dispenser(0), incidents[0].worker);
+        assert!(!incidents[0].restarted);
+        let orders_queue = coffee_maker.orders_queue.lock().expect("Fail test");
+        assert_eq!(41, orders_queue.counts().discarded);
+    }
+
+    #[test]
+    fn should_give_the_same_summary_with_both_consume_paths() {
+        let summaries = [ConsumePath::Locked, ConsumePath::LockFree].map(|consume_path| {
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .consume_path(consume_path)
+                .without_stdout_statistics()
+                .orders_file("tests/consume_all.json")
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+            (
+                coffee_maker.orders_processed().expect("Fail test"),
+                coffee_maker.orders_skipped(),
+            )
+        });
+        assert_eq!(500, summaries[0].0);
+        assert_eq!(summaries[0], summaries[1]);
+    }
+
+    #[test]
+    fn should_replenish_the_containers_with_the_alternative_engines() {
+        for engine in [Engine::Actors, Engine::Async] {
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .engine(engine)
+                .without_stdout_statistics()
+                .orders_file("tests/replenish_containers.json")
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+
+            assert_eq!(3, coffee_maker.orders_processed().expect("Fail test"));
+            let grains = coffee_maker
+                .resources
+                .get(&Ingredient::GrainsToGrind)
+                .expect("Fail test")
+                .lock()
+                .expect("Fail test");
+            let coffee = coffee_maker
+                .resources
+                .get(&Ingredient::GroundCoffee)
+                .expect("Fail test")
+                .lock()
+                .expect("Fail test");
+            assert_eq!(6000, coffee.consumed());
+            assert_eq!(grains.transferred, grains.consumed());
+            assert_eq!(
+                M_COFFEE_STORAGE + grains.consumed(),
+                coffee.remaining() + coffee.consumed()
+            );
+        }
+    }
+
+    #[test]
+    fn should_process_the_orders_with_hundreds_of_async_dispensers() {
+        let coffee_maker = CoffeeMakerBuilder::new()
+            .engine(Engine::Async)
+            .dispensers(500)
+            .without_stdout_statistics()
+            .orders_file("tests/consume_all.json")
+            .build()
+            .expect("Fail test");
+        coffee_maker.run().expect("Fail test");
+
+        assert_eq!(500, coffee_maker.orders_processed().expect("Fail test"));
+        let cacao = coffee_maker
+            .resources
+            .get(&Ingredient::Cacao)
+            .expect("Fail test")
+            .lock()
+            .expect("Fail test");
+        assert_eq!(0, cacao.remaining());
+    }
+
+    #[test]
+    fn should_resume_from_a_snapshot_and_process_the_pending_orders() {
+        let path = std::env::temp_dir().join(format!(
+            "coffee-maker-resume-test-{}.json",
+            std::process::id()
+        ));
+        let mut coffee_maker = CoffeeMaker::new();
+        coffee_maker.enable_snapshots(path.clone());
+        coffee_maker
+            .control_handle()
+            .pause(None)
+            .expect("Fail test");
+        coffee_maker
+            .control_handle()
+            .shutdown(ShutdownMode::Drain)
+            .expect("Fail test");
+        {
+            let cacao = coffee_maker
+                .resources
+                .get(&Ingredient::Cacao)
+                .expect("Fail test");
+            let cacao = cacao.lock().expect("Fail test");
+            cacao.level.consume_up_to(100);
+        }
+        coffee_maker
+            .orders_queue
+            .lock()
+            .expect("Fail test")
+            .push(Order::new(0, vec![(Ingredient::Cacao, 60)]));
+        coffee_maker
+            .snapshot_writer
+            .as_ref()
+            .expect("Fail test")
+            .write_snapshot()
+            .expect("Fail test");
+
+        let resumed = CoffeeMaker::resume(&path).expect("Fail test");
+        resumed.manage_pending_orders().expect("Fail test");
+        _ = std::fs::remove_file(&path);
+
+        let processed = resumed.statistics_printer.counters.processed();
+        assert_eq!(1, processed);
+        let cacao = resumed
+            .resources
+            .get(&Ingredient::Cacao)
+            .expect("Fail test")
+            .lock()
+            .expect("Fail test");
+        assert_eq!(C_CACAO_STORAGE - 160, cacao.remaining());
+        assert_eq!(160, cacao.consumed());
+    }
+
+    #[test]
+    fn should_rebuild_the_final_state_from_the_journal() {
+        let path = std::env::temp_dir().join(format!(
+            "coffee-maker-journal-test-{}.jsonl",
+            std::process::id()
+        ));
+        _ = std::fs::remove_file(&path);
+        let coffee_maker = CoffeeMaker::new();
+        coffee_maker.enable_journal(&path).expect("Fail test");
+        coffee_maker
+            .manage_orders(String::from("tests/replenish_containers.json"))
+            .expect("Fail test");
+
+        let summary = crate::journal::replay_file(&path).expect("Fail test");
+        _ = std::fs::remove_file(&path);
+        let state = CoffeeMakerSnapshot::take(
+            &coffee_maker.resources,
+            &coffee_maker.orders_queue,
+            &coffee_maker.dispensers,
+            &coffee_maker.statistics_printer.counters,
+        )
+        .expect("Fail test");
+        assert_eq!(3, summary.orders_enqueued);
+        assert_eq!(state.orders_processed, summary.orders_processed);
+        assert_eq!(state.containers, summary.containers);
+    }
+
+    /// Con `ConsumePath::LockFree` los dispensers consumen sin tomar el contenedor, por lo que la recarga
+    /// tiene que quedar en el journal antes que cualquier consumo que la use
+    #[test]
+    fn should_replay_the_journal_of_lock_free_dispensers_with_an_external_replenisher() {
+        for run in 0..5 {
+            let path = std::env::temp_dir().join(format!(
+                "coffee-maker-lock-free-journal-test-{}-{}.jsonl",
+                std::process::id(),
+                run
+            ));
+            _ = std::fs::remove_file(&path);
+            // El contenedor es chico, asi casi todos los pedidos usan lo que se acaba de recargar
+            let orders = (0..1000)
+                .map(|quantity| vec![(Ingredient::HotWater, 30 + quantity % 40)])
+                .collect();
+            let coffee_maker = CoffeeMakerBuilder::new()
+                .without_replenishers()
+                .external_replenisher(Ingredient::HotWater)
+                .container(Ingredient::HotWater, 100)
+                .dispensers(16)
+                .consume_path(ConsumePath::LockFree)
+                .without_stdout_statistics()
+                .journal(&path)
+                .order_source(VecSource::new(orders))
+                .build()
+                .expect("Fail test");
+            coffee_maker.run().expect("Fail test");
+
+            let summary = crate::journal::replay_file(&path);
+            _ = std::fs::remove_file(&path);
+            let summary = summary.expect("Fail test");
+            assert_eq!(1000, summary.orders_processed);
+        }
+    }
+
+    /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000.
+    /// Se recarga el cacao antes de empezar, por lo que no se saltea ninguna orden
+    #[test]
+    fn should_not_skip_orders_if_an_operator_refills_the_container() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/replenish_containers.json"));
+        {
+            let cacao = coffee_maker
+                .resources
+                .get(&Ingredient::Cacao)
+                .expect("Fail test");
+            cacao.lock().expect("Fail test").level.set_remaining(0);
+        }
+        coffee_maker
+            .control_handle()
+            .refill(Ingredient::Cacao, C_CACAO_STORAGE)
+            .expect("Fail test");
+        coffee_maker
+            .manage_orders(String::from("tests/skip_orders.json"))
+            .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
+        assert_eq!(6, processed);
+    }
 
-        let processed = *coffee_maker
+    #[test]
+    fn should_record_the_latencies_of_the_processed_orders() {
+        let coffee_maker = CoffeeMaker::new();
+        coffee_maker
+            .manage_orders(String::from("tests/multiple_orders.json"))
+            .expect("Fail test");
+
+        let summary = coffee_maker
             .statistics_printer
-            .processed
-            .read()
+            .latency_summary()
+            .expect("Fail test");
+        assert_eq!(41, summary.queue_wait.count);
+        assert_eq!(41, summary.preparation.count);
+        assert!(summary.preparation.p50 <= summary.preparation.p90);
+        assert!(summary.preparation.p90 <= summary.preparation.p99);
+        assert!(summary.preparation.p99 <= summary.preparation.max);
+    }
+
+    /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000
+    #[test]
+    fn should_process_three_big_orders_and_replenish_the_containers() {
+        let coffee_maker = CoffeeMaker::new();
+        coffee_maker
+            .manage_orders(String::from("tests/replenish_containers.json"))
             .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
         assert_eq!(3, processed);
 
         let resources = &coffee_maker.statistics_printer.resources;
@@ -326,36 +1756,34 @@ mod tests {
         let grains = grains.lock().expect("Fail test");
         let cold_milk = cold_milk.lock().expect("Fail test");
 
-        assert_eq!(C_CACAO_STORAGE - 1800, cacao.remaining);
-        assert_eq!(1800, cacao.consumed);
+        assert_eq!(C_CACAO_STORAGE - 1800, cacao.remaining());
+        assert_eq!(1800, cacao.consumed());
 
-        assert_eq!(E_FOAM_STORAGE - 2000, milk_foam.remaining);
-        assert_eq!(6000, milk_foam.consumed);
+        assert_eq!(E_FOAM_STORAGE - 2000, milk_foam.remaining());
+        assert_eq!(6000, milk_foam.consumed());
 
-        assert_eq!(M_COFFEE_STORAGE - 2000, ground_coffee.remaining);
-        assert_eq!(6000, ground_coffee.consumed);
+        assert_eq!(M_COFFEE_STORAGE - 2000, ground_coffee.remaining());
+        assert_eq!(6000, ground_coffee.consumed());
 
-        assert_eq!(A_WATER_STORAGE - 2000, water.remaining);
-        assert_eq!(6000, water.consumed);
+        assert_eq!(A_WATER_STORAGE - 2000, water.remaining());
+        assert_eq!(6000, water.consumed());
 
-        assert_eq!(G_GRAINS_STORAGE - 4000, grains.remaining);
-        assert_eq!(4000, grains.consumed);
+        assert_eq!(G_GRAINS_STORAGE - 4000, grains.remaining());
+        assert_eq!(4000, grains.consumed());
 
-        assert_eq!(L_MILK_STORAGE - 4000, cold_milk.remaining);
-        assert_eq!(4000, cold_milk.consumed);
+        assert_eq!(L_MILK_STORAGE - 4000, cold_milk.remaining());
+        assert_eq!(4000, cold_milk.consumed());
     }
 
     /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000
     #[test]
     fn should_process_multiple_orders_and_finish() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/multiple_orders.json"));
-
-        let processed = *coffee_maker
-            .statistics_printer
-            .processed
-            .read()
+        coffee_maker
+            .manage_orders(String::from("tests/multiple_orders.json"))
             .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
         assert_eq!(41, processed);
 
         let resources = &coffee_maker.statistics_printer.resources;
@@ -376,23 +1804,23 @@ mod tests {
         let grains = grains.lock().expect("Fail test");
         let cold_milk = cold_milk.lock().expect("Fail test");
 
-        assert_eq!(C_CACAO_STORAGE - 410, cacao.remaining);
-        assert_eq!(410, cacao.consumed);
+        assert_eq!(C_CACAO_STORAGE - 410, cacao.remaining());
+        assert_eq!(410, cacao.consumed());
 
-        assert_eq!(E_FOAM_STORAGE - 410, milk_foam.remaining);
-        assert_eq!(410, milk_foam.consumed);
+        assert_eq!(E_FOAM_STORAGE - 410, milk_foam.remaining());
+        assert_eq!(410, milk_foam.consumed());
 
-        assert_eq!(M_COFFEE_STORAGE - 410, ground_coffee.remaining);
-        assert_eq!(410, ground_coffee.consumed);
+        assert_eq!(M_COFFEE_STORAGE - 410, ground_coffee.remaining());
+        assert_eq!(410, ground_coffee.consumed());
 
-        assert_eq!(A_WATER_STORAGE - 410, water.remaining);
-        assert_eq!(410, water.consumed);
+        assert_eq!(A_WATER_STORAGE - 410, water.remaining());
+        assert_eq!(410, water.consumed());
 
-        assert_eq!(G_GRAINS_STORAGE, grains.remaining);
-        assert_eq!(0, grains.consumed);
+        assert_eq!(G_GRAINS_STORAGE, grains.remaining());
+        assert_eq!(0, grains.consumed());
 
-        assert_eq!(L_MILK_STORAGE, cold_milk.remaining);
-        assert_eq!(0, cold_milk.consumed);
+        assert_eq!(L_MILK_STORAGE, cold_milk.remaining());
+        assert_eq!(0, cold_milk.consumed());
     }
 
     /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000.
@@ -400,13 +1828,11 @@ mod tests {
     #[test]
     fn should_skip_an_order_if_there_is_not_enough_of_an_ingredient() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/skip_orders.json"));
-
-        let processed = *coffee_maker
-            .statistics_printer
-            .processed
-            .read()
+        coffee_maker
+            .manage_orders(String::from("tests/skip_orders.json"))
             .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
         assert_eq!(6, processed);
 
         let resources = &coffee_maker.statistics_printer.resources;
@@ -427,36 +1853,34 @@ mod tests {
         let grains = grains.lock().expect("Fail test");
         let cold_milk = cold_milk.lock().expect("Fail test");
 
-        assert_eq!(0, cacao.remaining);
-        assert_eq!(C_CACAO_STORAGE, cacao.consumed);
+        assert_eq!(0, cacao.remaining());
+        assert_eq!(C_CACAO_STORAGE, cacao.consumed());
 
-        assert_eq!(E_FOAM_STORAGE - 1000, milk_foam.remaining);
-        assert_eq!(1000, milk_foam.consumed);
+        assert_eq!(E_FOAM_STORAGE - 1000, milk_foam.remaining());
+        assert_eq!(1000, milk_foam.consumed());
 
-        assert_eq!(M_COFFEE_STORAGE - 1000, ground_coffee.remaining);
-        assert_eq!(1000, ground_coffee.consumed);
+        assert_eq!(M_COFFEE_STORAGE - 1000, ground_coffee.remaining());
+        assert_eq!(1000, ground_coffee.consumed());
 
-        assert_eq!(A_WATER_STORAGE - 1000, water.remaining);
-        assert_eq!(1000, water.consumed);
+        assert_eq!(A_WATER_STORAGE - 1000, water.remaining());
+        assert_eq!(1000, water.consumed());
 
-        assert_eq!(G_GRAINS_STORAGE, grains.remaining);
-        assert_eq!(0, grains.consumed);
+        assert_eq!(G_GRAINS_STORAGE, grains.remaining());
+        assert_eq!(0, grains.consumed());
 
-        assert_eq!(L_MILK_STORAGE, cold_milk.remaining);
-        assert_eq!(0, cold_milk.consumed);
+        assert_eq!(L_MILK_STORAGE, cold_milk.remaining());
+        assert_eq!(0, cold_milk.consumed());
     }
 
     /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000.
     #[test]
     fn should_consume_all_ingredients() {
         let coffee_maker = CoffeeMaker::new();
-        coffee_maker.manage_orders(String::from("tests/consume_all.json"));
-
-        let processed = *coffee_maker
-            .statistics_printer
-            .processed
-            .read()
+        coffee_maker
+            .manage_orders(String::from("tests/consume_all.json"))
             .expect("Fail test");
+
+        let processed = coffee_maker.statistics_printer.counters.processed();
         assert_eq!(500, processed);
 
         let resources = &coffee_maker.statistics_printer.resources;
@@ -475,19 +1899,22 @@ mod tests {
         let grains = grains.lock().expect("Fail test");
         let cold_milk = cold_milk.lock().expect("Fail test");
 
-        assert_eq!(0, cacao.remaining);
-        assert_eq!(C_CACAO_STORAGE, cacao.consumed);
+        assert_eq!(0, cacao.remaining());
+        assert_eq!(C_CACAO_STORAGE, cacao.consumed());
 
-        assert_eq!(0, milk_foam.remaining);
-        assert_eq!(E_FOAM_STORAGE + L_MILK_STORAGE, milk_foam.consumed);
+        assert_eq!(0, milk_foam.remaining());
+        assert_eq!(E_FOAM_STORAGE + L_MILK_STORAGE, milk_foam.consumed());
 
-        assert_eq!(0, ground_coffee.remaining);
-        assert_eq!(M_COFFEE_STORAGE + G_GRAINS_STORAGE, ground_coffee.consumed);
+        assert_eq!(0, ground_coffee.remaining());
+        assert_eq!(
+            M_COFFEE_STORAGE + G_GRAINS_STORAGE,
+            ground_coffee.consumed()
+        );
 
-        assert_eq!(0, grains.remaining);
-        assert_eq!(G_GRAINS_STORAGE, grains.consumed);
+        assert_eq!(0, grains.remaining());
+        assert_eq!(G_GRAINS_STORAGE, grains.consumed());
 
-        assert_eq!(0, cold_milk.remaining);
-        assert_eq!(L_MILK_STORAGE, cold_milk.consumed);
+        assert_eq!(0, cold_milk.remaining());
+        assert_eq!(L_MILK_STORAGE, cold_milk.consumed());
     }
 }
@@ -33,3 +33,39 @@ pub const MAX_OF_INGREDIENT_IN_AN_ORDER: u64 = 2500;
 
 /// Tiempo minimo de espera de los reponedores de ingredientes
 pub const MINIMUM_WAIT_TIME_REPLENISHER: u64 = 100;
+
+/// Cada cuanto tiempo se redibuja el panel de la cafetera en la terminal
+pub const DASHBOARD_REFRESH_IN_MS: u64 = 100;
+
+/// Cantidad de alertas recientes que se muestran en el panel
+pub const DASHBOARD_RECENT_ALERTS: usize = 5;
+
+/// Ancho de las barras de nivel de los contenedores en el panel
+pub const DASHBOARD_BAR_WIDTH: usize = 30;
+
+/// Cada cuanto tiempo el socket de control revisa si la cafetera finalizo mientras espera conexiones o comandos
+pub const CONTROL_SOCKET_POLL_IN_MS: u64 = 50;
+
+/// Cantidad de pedidos terminados cuyo estado recuerda la cola. Los mas viejos solo quedan en los totales
+pub const MAX_RECENT_STATUSES: usize = 10_000;
+
+/// Cada cuanto una fuente de pedidos que espera datos revisa si la cola se cerro
+pub const SOURCE_POLL_IN_MS: u64 = 50;
+
+/// Cada cuanto tiempo se guarda en disco la foto del estado de la cafetera (si esta habilitado)
+pub const SNAPSHOT_INTERVAL_IN_MS: u64 = 1000;
+
+/// Cada cuanto tiempo se verifican las invariantes de conservacion (si esta habilitado)
+pub const INVARIANT_CHECK_INTERVAL_IN_MS: u64 = 100;
+
+/// Maximo tiempo real que una tarea periodica espera a que avance el reloj virtual
+pub const VIRTUAL_CLOCK_WAIT_IN_MS: u64 = 1;
+
+/// Archivo por defecto donde se guarda la foto del estado de la cafetera
+pub const DEFAULT_SNAPSHOT_PATH: &str = "coffee_maker_snapshot.json";
+
+/// Capacidad maxima de un contenedor
+pub const MAX_CONTAINER_CAPACITY: u64 = u32::MAX as u64;
+
+/// Cantidad de veces que el supervisor reinicia a un mismo dispenser o reponedor que se cayo
+pub const DEFAULT_RESTART_BUDGET: usize = 3;
@@ -1,24 +1,346 @@
 //! Contenedor de recursos de la cafetera
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    errors::CoffeeMakerError,
+    order::{Ingredient, ALL_INGREDIENTS},
+    poison::LockOrRecover,
+    poison::Recoverable,
+};
+
+/// Forma en la que los dispensers descuentan los ingredientes de los contenedores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumePath {
+    /// Se toma el lock del contenedor y queda tomado mientras se sirve el ingrediente
+    Locked,
+    /// Se descuenta con un compare-and-swap sobre el nivel del contenedor, sin tomar su lock.
+    /// Solo se toma el lock si no alcanza y hay que esperar al reponedor
+    LockFree,
+}
+
+impl FromStr for ConsumePath {
+    type Err = CoffeeMakerError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        match path {
+            "locked" => Ok(ConsumePath::Locked),
+            "lock-free" => Ok(ConsumePath::LockFree),
+            _ => Err(CoffeeMakerError::InvalidConsumePath(path.to_string())),
+        }
+    }
+}
+
+/// Nivel de un contenedor: lo que le queda y lo que se consumio. El compare-and-swap de los dispensers solo
+/// necesita lo que queda, por lo que se guarda aparte el total que se cargo en el contenedor y lo consumido
+/// se deriva de ambos. Consumir no cambia el total cargado, asi una lectura nunca ve lo que queda actualizado
+/// y lo consumido no. Se puede leer y consumir sin tomar el lock del contenedor, pero las recargas se hacen
+/// con el lock tomado: quien necesite leer ambos valores exactos durante una recarga debe tomarlo.
+#[derive(Debug)]
+pub struct AtomicLevel {
+    remaining: AtomicU64,
+    loaded: AtomicU64,
+    capacity: u64,
+}
+
+impl AtomicLevel {
+    pub fn new(capacity: u64) -> AtomicLevel {
+        AtomicLevel {
+            remaining: AtomicU64::new(capacity),
+            loaded: AtomicU64::new(capacity),
+            capacity,
+        }
+    }
+
+    /// Devuelve lo que queda y lo consumido
+    pub fn load(&self) -> (u64, u64) {
+        let loaded = self.loaded.load(Ordering::Acquire);
+        let remaining = self.remaining.load(Ordering::Acquire);
+        (remaining, loaded.saturating_sub(remaining))
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.remaining.load(Ordering::Acquire)
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.load().1
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Descuenta `quantity` si alcanza. Devuelve si se pudo descontar
+    pub fn try_consume(&self, quantity: u64) -> bool {
+        self.update(|remaining| remaining.checked_sub(quantity))
+    }
+
+    /// Descuenta hasta `quantity`, lo que haya. Devuelve cuanto se desconto
+    pub fn consume_up_to(&self, quantity: u64) -> u64 {
+        let mut taken = 0;
+        self.update(|remaining| {
+            taken = quantity.min(remaining);
+            Some(remaining - taken)
+        });
+        taken
+    }
+
+    /// Agrega hasta `quantity` sin superar la capacidad. Devuelve cuanto se agrego
+    pub fn fill(&self, quantity: u64) -> u64 {
+        let mut added = 0;
+        self.update(|remaining| {
+            added = quantity.min(self.capacity.saturating_sub(remaining));
+            Some(remaining + added)
+        });
+        self.loaded.fetch_add(added, Ordering::AcqRel);
+        added
+    }
+
+    /// Como `fill`, pero antes de que los dispensers puedan ver lo cargado llama a `announce` con la cantidad,
+    /// por ejemplo para que la recarga quede en el journal antes que los consumos que la usen.
+    /// Se tiene que llamar con el contenedor tomado: mientras tanto el nivel solo puede bajar, y entra todo lo anunciado
+    pub fn fill_announced(&self, quantity: u64, announce: impl FnOnce(u64)) -> u64 {
+        let added = quantity.min(self.capacity.saturating_sub(self.remaining()));
+        announce(added);
+        self.fill(added)
+    }
+
+    /// Pisa el nivel, por ejemplo al retomar desde una foto
+    pub fn set(&self, remaining: u64, consumed: u64) {
+        let remaining = remaining.min(self.capacity);
+        self.remaining.store(remaining, Ordering::Release);
+        self.loaded.store(remaining + consumed, Ordering::Release);
+    }
+
+    pub fn set_remaining(&self, remaining: u64) {
+        let consumed = self.consumed();
+        self.set(remaining, consumed);
+    }
+
+    fn update(&self, change: impl FnMut(u64) -> Option<u64>) -> bool {
+        self.remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, change)
+            .is_ok()
+    }
+}
 
 /// Representa a un contenedor de ingredientes.
-/// Tiene como estado la cantidad que le queda de recurso, cuanto se consumio,
-/// y si se acabo la reposicion del contenedor.
+/// Tiene como estado su nivel (la cantidad que le queda de recurso, cuanto se consumio y la capacidad maxima
+/// que puede almacenar) y si se acabo la reposicion del contenedor. El nivel se comparte con quienes lo leen o
+/// consumen sin tomar el lock del contenedor.
+/// La reposicion puede volver a estar disponible si un operador recarga la fuente del contenedor.
+/// El campo `replenishable` indica si el contenedor tiene un reponedor asignado.
+/// El campo `shutdown` le indica al reponedor del contenedor que la cafetera esta finalizando.
+/// Del total consumido, `transferred` es lo que se llevo un reponedor a otro contenedor y `wasted` lo que se tiro
+/// en pedidos salteados. `supplied` es lo que se cargo desde fuera de la cafetera (fuente externa u operador).
+#[derive(Debug)]
 pub struct Container {
-    pub remaining: u64,
-    pub consumed: u64,
+    pub level: Arc<AtomicLevel>,
+    pub transferred: u64,
+    pub wasted: u64,
+    pub supplied: u64,
     pub finished: bool,
+    pub shutdown: bool,
+    pub replenishable: bool,
 }
 
 impl Container {
     pub fn new(initial_capacity: u64) -> Container {
         Container {
-            remaining: initial_capacity,
-            consumed: 0,
+            level: Arc::new(AtomicLevel::new(initial_capacity)),
+            transferred: 0,
+            wasted: 0,
+            supplied: 0,
             finished: false,
+            shutdown: false,
+            replenishable: false,
         }
     }
 
+    pub fn remaining(&self) -> u64 {
+        self.level.remaining()
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.level.consumed()
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.level.capacity()
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.remaining == 0
+        self.remaining() == 0
+    }
+}
+
+/// La copia tiene su propio nivel, no comparte el del contenedor original
+impl Clone for Container {
+    fn clone(&self) -> Self {
+        let level = AtomicLevel::new(self.capacity());
+        let (remaining, consumed) = self.level.load();
+        level.set(remaining, consumed);
+        Container {
+            level: Arc::new(level),
+            transferred: self.transferred,
+            wasted: self.wasted,
+            supplied: self.supplied,
+            finished: self.finished,
+            shutdown: self.shutdown,
+            replenishable: self.replenishable,
+        }
+    }
+}
+
+/// El nivel se cambia con operaciones atomicas, por lo que no queda a medias. Lo que se pudo cortar es la actualizacion
+/// del resto de los contadores
+impl Recoverable for Container {
+    const NAME: &'static str = "container";
+
+    fn validate(&self) -> Result<(), String> {
+        let (remaining, consumed) = self.level.load();
+        if remaining > self.capacity() {
+            return Err(format!(
+                "remaining {} is above the capacity {}",
+                remaining,
+                self.capacity()
+            ));
+        }
+        if self.transferred.saturating_add(self.wasted) > consumed {
+            return Err(format!(
+                "transferred {} plus wasted {} is above the consumed {}",
+                self.transferred, self.wasted, consumed
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Recursos de la cafetera, agrupa a los contenedores de cada ingrediente
+pub type Resources = HashMap<Ingredient, Arc<Mutex<Container>>>;
+
+/// Niveles de los contenedores de cada ingrediente, se leen sin tomar el lock de los contenedores
+pub type Levels = HashMap<Ingredient, Arc<AtomicLevel>>;
+
+/// Devuelve el nivel compartido de cada uno de los contenedores de los recursos
+pub fn levels_of(resources: &Resources) -> Result<Levels, CoffeeMakerError> {
+    let mut levels = HashMap::with_capacity(resources.len());
+    for (ingredient, container_lock) in resources {
+        levels.insert(*ingredient, container_lock.lock_or_recover()?.level.clone());
+    }
+    Ok(levels)
+}
+
+/// Nivel de un contenedor en un momento dado
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerLevel {
+    pub ingredient: Ingredient,
+    pub remaining: u64,
+    pub capacity: u64,
+}
+
+/// Devuelve el nivel de cada uno de los contenedores, siempre en el mismo orden
+pub fn get_levels(levels: &Levels) -> Vec<ContainerLevel> {
+    ALL_INGREDIENTS
+        .into_iter()
+        .filter_map(|ingredient| {
+            let level = levels.get(&ingredient)?;
+            Some(ContainerLevel {
+                ingredient,
+                remaining: level.remaining(),
+                capacity: level.capacity(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn should_never_consume_more_than_what_remains() {
+        let level = Arc::new(AtomicLevel::new(1000));
+        let threads = (0..8)
+            .map(|_| {
+                let level = level.clone();
+                thread::spawn(move || (0..100).filter(|_| level.try_consume(3)).count() as u64)
+            })
+            .collect::<Vec<_>>();
+        let consumed = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("Error in test") * 3)
+            .sum::<u64>();
+
+        assert_eq!(999, consumed);
+        assert_eq!((1, 999), level.load());
+        assert!(!level.try_consume(3));
+    }
+
+    #[test]
+    fn should_only_recover_a_poisoned_container_with_consistent_counters() {
+        let container = Arc::new(Mutex::new(Container::new(100)));
+        let poison = |wasted: u64| {
+            let container = container.clone();
+            thread::spawn(move || {
+                let mut container = container.lock().expect("Error in test");
+                container.level.consume_up_to(30);
+                container.wasted = wasted;
+                panic!("injected crash");
+            })
+            .join()
+            .expect_err("Error in test");
+        };
+
+        poison(20);
+        assert_eq!(
+            (70, 30),
+            container
+                .lock_or_recover()
+                .expect("Error in test")
+                .level
+                .load()
+        );
+        poison(90);
+        assert!(matches!(
+            container
+                .lock_or_recover()
+                .map(|container| container.wasted),
+            Err(CoffeeMakerError::CorruptedState {
+                state: "container",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_keep_counting_the_consumed_past_32_bits() {
+        let level = AtomicLevel::new(u64::from(u32::MAX));
+        for _ in 0..3 {
+            assert_eq!(
+                u64::from(u32::MAX),
+                level.consume_up_to(u64::from(u32::MAX))
+            );
+            level.fill(u64::from(u32::MAX));
+        }
+        assert_eq!((u64::from(u32::MAX), 3 * u64::from(u32::MAX)), level.load());
+    }
+
+    #[test]
+    fn should_fill_up_to_the_capacity() {
+        let level = AtomicLevel::new(100);
+        assert_eq!(60, level.consume_up_to(60));
+        assert_eq!(40, level.consume_up_to(80));
+        assert_eq!(100, level.fill(500));
+        assert_eq!((100, 100), level.load());
     }
 }
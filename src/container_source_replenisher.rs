@@ -1,35 +1,25 @@
 //! Reponedor de un contenedor a partir de otro contenedor
 use std::{
-    cmp::min,
-    sync::{Arc, Condvar, Mutex},
+    sync::{Arc, Condvar, Mutex, RwLock},
     time::Duration,
 };
 
 use log::{debug, error};
 
 use crate::{
+    clock::Clock,
     constants::{MAX_OF_INGREDIENT_IN_AN_ORDER, MINIMUM_WAIT_TIME_REPLENISHER},
     container::Container,
+    epoch::TransferEpoch,
     errors::CoffeeMakerError,
+    journal::{Journal, JournalEvent},
     order::Ingredient,
+    poison::{LockOrRecover, WaitOrRecover},
+    supervisor::Worker,
 };
 
-mod sync {
-    use std::thread;
-    use std::time::Duration;
-
-    #[cfg(not(test))]
-    pub(crate) fn sleep(d: Duration) {
-        thread::sleep(d);
-    }
-
-    #[cfg(test)]
-    pub(crate) fn sleep(_: Duration) {
-        thread::yield_now();
-    }
-}
-
-/// Representa a un reponedor de un contenedor a partir de otro contenedor. El contenedor usado como fuente puede agotarse
+/// Representa a un reponedor de un contenedor a partir de otro contenedor. El contenedor usado como fuente puede agotarse.
+/// Cada recarga se registra como una transferencia en `epoch`, para que las lecturas de todos los contenedores sean consistentes
 pub struct ContainerReplenisher {
     source_ingredient: Ingredient,
     dest_ingredient: Ingredient,
@@ -38,15 +28,23 @@ pub struct ContainerReplenisher {
     replenisher_cond: Arc<Condvar>,
     ingredients_cond: Arc<Condvar>,
     max_storage_of_dest_container: u64,
+    replenishing: RwLock<bool>,
+    journal: Arc<Journal>,
+    epoch: Arc<TransferEpoch>,
+    clock: Arc<dyn Clock>,
 }
 
 impl ContainerReplenisher {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         source: (Ingredient, Arc<Mutex<Container>>),
         dest: (Ingredient, Arc<Mutex<Container>>),
         replenisher_cond: Arc<Condvar>,
         ingredients_cond: Arc<Condvar>,
         max_storage_of_container: u64,
+        journal: Arc<Journal>,
+        epoch: Arc<TransferEpoch>,
+        clock: Arc<dyn Clock>,
     ) -> ContainerReplenisher {
         let (source_ingredient, source_container_lock) = source;
         let (dest_ingredient, dest_container_lock) = dest;
@@ -58,31 +56,63 @@ impl ContainerReplenisher {
             replenisher_cond,
             ingredients_cond,
             max_storage_of_dest_container: max_storage_of_container,
+            replenishing: RwLock::new(false),
+            journal,
+            epoch,
+            clock,
         }
     }
 
+    pub fn source_ingredient(&self) -> Ingredient {
+        self.source_ingredient
+    }
+
+    pub fn dest_ingredient(&self) -> Ingredient {
+        self.dest_ingredient
+    }
+
+    /// Indica si el reponedor se encuentra recargando su contenedor en este momento
+    pub fn is_replenishing(&self) -> Result<bool, CoffeeMakerError> {
+        Ok(*self.replenishing.read()?)
+    }
+
     pub fn finish(&self) {
-        if let Ok(mut container) = self.dest_container_lock.lock() {
-            container.finished = true;
+        if let Ok(mut container) = self.dest_container_lock.lock_or_recover() {
+            container.shutdown = true;
             self.replenisher_cond.notify_all();
             return;
         }
         error!("Error setting replenisher to finish");
     }
 
+    /// Avisa que se recargo el contenedor fuente. Si el contenedor destino se habia quedado
+    /// sin reposicion, se vuelve a habilitar y se despierta al reponedor
+    pub fn source_refilled(&self) -> Result<(), CoffeeMakerError> {
+        let mut dest_container = self.dest_container_lock.lock_or_recover()?;
+        dest_container.finished = false;
+        self.replenisher_cond.notify_all();
+        Ok(())
+    }
+
+    /// Repone el contenedor destino cada vez que lo despiertan y le falta recurso.
+    /// Si se acaba la fuente se queda esperando a que la recarguen o a que la cafetera finalice
     pub fn replenish_container(&self) -> Result<(), CoffeeMakerError> {
         loop {
-            let mut dest_container = self
-                .replenisher_cond
-                .wait_while(self.dest_container_lock.lock()?, |container| {
-                    container.remaining > MAX_OF_INGREDIENT_IN_AN_ORDER && !container.finished
-                })
-                .map_err(|_| CoffeeMakerError::LockError)?;
+            let mut dest_container = self.replenisher_cond.wait_while_or_recover(
+                &self.dest_container_lock,
+                |container| {
+                    (container.remaining() > MAX_OF_INGREDIENT_IN_AN_ORDER || container.finished)
+                        && !container.shutdown
+                },
+            )?;
 
-            if dest_container.finished {
+            if dest_container.shutdown {
                 return Ok(());
             }
-            self.replenish(&mut dest_container)?;
+            *self.replenishing.write()? = true;
+            let result = self.replenish(&mut dest_container);
+            *self.replenishing.write()? = false;
+            result?;
             self.ingredients_cond.notify_all();
         }
     }
@@ -91,11 +121,11 @@ impl ContainerReplenisher {
         &self,
         dest_container: &mut std::sync::MutexGuard<Container>,
     ) -> Result<(), CoffeeMakerError> {
-        let (replenish_quantity, source_is_empty) =
-            self.take_resource_from_source(dest_container.remaining)?;
-        dest_container.remaining += replenish_quantity;
-        dest_container.finished = source_is_empty;
-        sync::sleep(Duration::from_millis(
+        self.epoch.begin()?;
+        let result = self.transfer(dest_container);
+        self.epoch.end()?;
+        let replenish_quantity = result?;
+        self.clock.sleep(Duration::from_millis(
             MINIMUM_WAIT_TIME_REPLENISHER + replenish_quantity,
         ));
         debug!(
@@ -105,31 +135,70 @@ impl ContainerReplenisher {
         Ok(())
     }
 
+    /// Pasa el recurso de la fuente al destino. Devuelve la cantidad transferida.
+    /// Los dispensers pueden seguir consumiendo de ambos niveles mientras tanto, solo pueden bajar.
+    /// La recarga queda en el journal antes de que los dispensers puedan consumir lo que se paso al destino
+    fn transfer(
+        &self,
+        dest_container: &mut std::sync::MutexGuard<Container>,
+    ) -> Result<u64, CoffeeMakerError> {
+        let (replenish_quantity, source_is_empty) =
+            self.take_resource_from_source(dest_container.remaining())?;
+        dest_container
+            .level
+            .fill_announced(replenish_quantity, |quantity| {
+                self.journal.record(JournalEvent::Replenished {
+                    ingredient: self.dest_ingredient,
+                    source: Some(self.source_ingredient),
+                    quantity,
+                })
+            });
+        dest_container.finished = source_is_empty;
+        Ok(replenish_quantity)
+    }
+
     fn take_resource_from_source(
         &self,
         dest_remaining: u64,
     ) -> Result<(u64, bool), CoffeeMakerError> {
-        let mut source_container = self
-            .source_container_lock
-            .lock()
-            .map_err(|_| CoffeeMakerError::LockError)?;
-        let replenish_quantity = min(
-            self.max_storage_of_dest_container - dest_remaining,
-            source_container.remaining,
-        );
-
-        source_container.remaining -= replenish_quantity;
-        source_container.consumed += replenish_quantity;
+        let mut source_container = self.source_container_lock.lock_or_recover()?;
+        let replenish_quantity = source_container
+            .level
+            .consume_up_to(self.max_storage_of_dest_container - dest_remaining);
+        source_container.transferred += replenish_quantity;
 
         let source_is_empty = source_container.is_empty();
         Ok((replenish_quantity, source_is_empty))
     }
 }
 
+impl Worker for ContainerReplenisher {
+    fn work(&self) -> Result<(), CoffeeMakerError> {
+        self.replenish_container()
+    }
+
+    fn recover(&self) -> Result<Option<usize>, CoffeeMakerError> {
+        *self.replenishing.write()? = false;
+        Ok(None)
+    }
+
+    /// Sin reponedor, los dispensers dejan de esperar a que se recargue el contenedor
+    fn give_up(&self) {
+        if let Ok(mut container) = self.dest_container_lock.lock_or_recover() {
+            container.replenishable = false;
+            self.ingredients_cond.notify_all();
+            return;
+        }
+        error!("Error disabling the replenishment of the container");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread;
 
+    use crate::clock::VirtualClock;
+
     use crate::constants::{E_FOAM_STORAGE, L_MILK_STORAGE};
 
     use super::*;
@@ -148,28 +217,83 @@ mod tests {
             replenisher_cond.clone(),
             ingredients_cond.clone(),
             E_FOAM_STORAGE,
+            Arc::new(Journal::disabled()),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(VirtualClock::new()),
         ));
         let milk_clone = milk_replenisher.clone();
 
         let handle = thread::spawn(move || milk_clone.replenish_container());
 
         {
-            let mut container = milk_foam.lock().expect("Lock error in test");
-            container.remaining = 0;
+            let container = milk_foam.lock().expect("Lock error in test");
+            container.level.set_remaining(0);
         }
         replenisher_cond.notify_all();
         {
             let container = ingredients_cond
                 .wait_while(milk_foam.lock().expect("Lock error in test"), |container| {
-                    container.remaining < E_FOAM_STORAGE
+                    container.remaining() < E_FOAM_STORAGE
                 })
                 .expect("Test error when returning from condvar");
-            assert_eq!(container.remaining, E_FOAM_STORAGE);
+            assert_eq!(container.remaining(), E_FOAM_STORAGE);
         }
         {
             let container = cold_milk.lock().expect("Lock error in test");
-            assert_eq!(container.remaining, 0);
-            assert_eq!(container.consumed, L_MILK_STORAGE);
+            assert_eq!(container.remaining(), 0);
+            assert_eq!(container.consumed(), L_MILK_STORAGE);
+        }
+
+        milk_replenisher.finish();
+        _ = handle.join().expect("Error when joining thread");
+    }
+
+    #[test]
+    fn should_replenish_again_after_the_empty_source_is_refilled() {
+        let cold_milk = Arc::new(Mutex::new(Container::new(L_MILK_STORAGE)));
+        let milk_foam = Arc::new(Mutex::new(Container::new(E_FOAM_STORAGE)));
+        let replenisher_cond = Arc::new(Condvar::new());
+        let ingredients_cond = Arc::new(Condvar::new());
+
+        let milk_replenisher = Arc::new(ContainerReplenisher::new(
+            (Ingredient::ColdMilk, cold_milk.clone()),
+            (Ingredient::MilkFoam, milk_foam.clone()),
+            replenisher_cond.clone(),
+            ingredients_cond.clone(),
+            E_FOAM_STORAGE,
+            Arc::new(Journal::disabled()),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(VirtualClock::new()),
+        ));
+        let milk_clone = milk_replenisher.clone();
+        let handle = thread::spawn(move || milk_clone.replenish_container());
+
+        {
+            cold_milk
+                .lock()
+                .expect("Lock error in test")
+                .level
+                .set_remaining(0);
+            let mut container = milk_foam.lock().expect("Lock error in test");
+            container.level.set_remaining(0);
+            container.finished = true;
+        }
+        cold_milk
+            .lock()
+            .expect("Lock error in test")
+            .level
+            .set_remaining(100);
+        milk_replenisher
+            .source_refilled()
+            .expect("Error when refilling in test");
+        {
+            let container = ingredients_cond
+                .wait_while(milk_foam.lock().expect("Lock error in test"), |container| {
+                    container.remaining() < 100
+                })
+                .expect("Test error when returning from condvar");
+            assert_eq!(100, container.remaining());
+            assert!(container.finished);
         }
 
         milk_replenisher.finish();
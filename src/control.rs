@@ -0,0 +1,418 @@
+//! Control de la cafetera mientras esta funcionando. Permite pausarla, reanudarla y finalizarla
+//! de forma ordenada (por ejemplo, al recibir una señal)
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+use log::info;
+
+use crate::{
+    admission::AdmissionControl,
+    container::{get_levels, levels_of, ContainerLevel, Resources},
+    container_source_replenisher::ContainerReplenisher,
+    epoch::TransferEpoch,
+    errors::CoffeeMakerError,
+    journal::{Journal, JournalEvent},
+    order::{Ingredient, Order, OrderStatus},
+    orders_queue::OrdersQueue,
+    poison::LockOrRecover,
+};
+
+/// Cliente de los pedidos que se cargan con `ControlHandle::submit`
+pub const CONTROL_CUSTOMER: &str = "control";
+
+/// Formas de finalizar la cafetera antes de tiempo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// No se aceptan mas pedidos, pero se terminan todos los que ya estan en la cola
+    Drain,
+    /// Se descartan los pedidos de la cola, solo se terminan los que se estan preparando
+    Abort,
+}
+
+/// Finalizacion pedida a la cafetera, para los motores alternativos que no leen la cola de pedidos compartida.
+/// Si se pide mas de una vez queda la mas estricta, `Abort` no vuelve a ser `Drain`
+#[derive(Debug, Default)]
+pub struct ShutdownSignal {
+    /// 0 si no se pidio, 1 para `Drain` y 2 para `Abort`
+    mode: AtomicU8,
+}
+
+impl ShutdownSignal {
+    pub fn request(&self, mode: ShutdownMode) {
+        let code = match mode {
+            ShutdownMode::Drain => 1,
+            ShutdownMode::Abort => 2,
+        };
+        self.mode.fetch_max(code, Ordering::SeqCst);
+    }
+
+    pub fn requested(&self) -> Option<ShutdownMode> {
+        match self.mode.load(Ordering::SeqCst) {
+            0 => None,
+            1 => Some(ShutdownMode::Drain),
+            _ => Some(ShutdownMode::Abort),
+        }
+    }
+}
+
+/// Permite controlar a la cafetera desde otro hilo.
+/// Al cerrar la cola de pedidos los dispensers terminan por su cuenta, y luego `CoffeeMaker`
+/// finaliza a los reponedores y a las estadisticas como en una ejecucion normal.
+/// Al pausar, los dispensers terminan el pedido actual y no toman nuevos hasta ser reanudados.
+/// Tambien permite cargar pedidos, consultar su estado y el de los contenedores, y recargarlos.
+#[derive(Clone)]
+pub struct ControlHandle {
+    orders_queue: Arc<Mutex<OrdersQueue>>,
+    orders_cond: Arc<Condvar>,
+    resources: Arc<Resources>,
+    ingredients_cond: Arc<Condvar>,
+    container_replenishers: Vec<Arc<ContainerReplenisher>>,
+    epoch: Arc<TransferEpoch>,
+    admission: Arc<AdmissionControl>,
+    journal: Arc<Journal>,
+    shutdown: Arc<ShutdownSignal>,
+}
+
+impl ControlHandle {
+    pub fn new(
+        orders: (Arc<Mutex<OrdersQueue>>, Arc<Condvar>),
+        resources: (Arc<Resources>, Arc<Condvar>),
+        container_replenishers: Vec<Arc<ContainerReplenisher>>,
+        epoch: Arc<TransferEpoch>,
+        admission: Arc<AdmissionControl>,
+        journal: Arc<Journal>,
+        shutdown: Arc<ShutdownSignal>,
+    ) -> ControlHandle {
+        let (orders_queue, orders_cond) = orders;
+        let (resources, ingredients_cond) = resources;
+        ControlHandle {
+            orders_queue,
+            orders_cond,
+            resources,
+            ingredients_cond,
+            container_replenishers,
+            epoch,
+            admission,
+            journal,
+            shutdown,
+        }
+    }
+
+    /// Agrega un pedido a la cola. Devuelve el id asignado, o `OrderRejected` si el control de admision lo rechazo
+    pub fn submit(&self, ingredients: Vec<(Ingredient, u64)>) -> Result<usize, CoffeeMakerError> {
+        let rejection = self.admission.check(&ingredients)?;
+        let mut queue = self.orders_queue.lock_or_recover()?;
+        if queue.finished {
+            return Err(CoffeeMakerError::QueueClosed);
+        }
+        let id = queue.next_id();
+        if let Some(rejection) = rejection {
+            queue.reject(id, rejection);
+            info!("[CONTROL] Rejected order {}: {}", id, rejection);
+            return Err(CoffeeMakerError::OrderRejected {
+                order: id,
+                rejection,
+            });
+        }
+        queue.push(Order::new(id, ingredients).with_customer(CONTROL_CUSTOMER));
+        info!("[CONTROL] Submitted order {}", id);
+        self.orders_cond.notify_all();
+        Ok(id)
+    }
+
+    pub fn order_status(&self, id: usize) -> Result<Option<OrderStatus>, CoffeeMakerError> {
+        Ok(self.orders_queue.lock_or_recover()?.status(id))
+    }
+
+    pub fn levels(&self) -> Result<Vec<ContainerLevel>, CoffeeMakerError> {
+        Ok(get_levels(&levels_of(&self.resources)?))
+    }
+
+    /// Recarga manual de un operador. Agrega `quantity` al contenedor del ingrediente sin superar su capacidad
+    /// y vuelve a habilitar la reposicion de los contenedores que dependen de el.
+    /// Como en `ContainerReplenisher`, la recarga se registra en la epoca de las transferencias.
+    /// Devuelve la cantidad que efectivamente se agrego
+    pub fn refill(&self, ingredient: Ingredient, quantity: u64) -> Result<u64, CoffeeMakerError> {
+        self.epoch.begin()?;
+        let result = self.fill_container(ingredient, quantity);
+        self.epoch.end()?;
+        let added = result?;
+        for replenisher in &self.container_replenishers {
+            if replenisher.source_ingredient() == ingredient {
+                replenisher.source_refilled()?;
+            }
+        }
+        info!(
+            "[CONTROL] Operator refilled {:?} with {} (requested {})",
+            ingredient, added, quantity
+        );
+        self.ingredients_cond.notify_all();
+        Ok(added)
+    }
+
+    fn fill_container(
+        &self,
+        ingredient: Ingredient,
+        quantity: u64,
+    ) -> Result<u64, CoffeeMakerError> {
+        let mut container = self
+            .resources
+            .get(&ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))?
+            .lock_or_recover()?;
+        // Se registra antes de que los dispensers puedan consumir lo recargado
+        let added = container.level.fill_announced(quantity, |added| {
+            self.journal.record(JournalEvent::Refilled {
+                ingredient,
+                quantity: added,
+                requested: quantity,
+            })
+        });
+        container.supplied += added;
+        container.finished = false;
+        Ok(added)
+    }
+
+    /// Pausa a todos los dispensers (`None`) o al indicado
+    pub fn pause(&self, dispenser: Option<usize>) -> Result<(), CoffeeMakerError> {
+        self.orders_queue.lock_or_recover()?.pause(dispenser);
+        info!("[CONTROL] Paused {}", describe_target(dispenser));
+        Ok(())
+    }
+
+    /// Reanuda a todos los dispensers (`None`) o al indicado
+    pub fn resume(&self, dispenser: Option<usize>) -> Result<(), CoffeeMakerError> {
+        self.orders_queue.lock_or_recover()?.resume(dispenser);
+        info!("[CONTROL] Resumed {}", describe_target(dispenser));
+        self.orders_cond.notify_all();
+        Ok(())
+    }
+
+    /// Con `Drain` se reanudan los dispensers pausados, si no los pedidos que quedan en la cola no se terminarian nunca
+    pub fn shutdown(&self, mode: ShutdownMode) -> Result<(), CoffeeMakerError> {
+        self.shutdown.request(mode);
+        let mut queue = self.orders_queue.lock_or_recover()?;
+        match mode {
+            ShutdownMode::Drain => {
+                info!(
+                    "[SHUTDOWN] Draining, {} orders left in the queue",
+                    queue.len()
+                );
+                queue.resume(None);
+                queue.close();
+            }
+            ShutdownMode::Abort => {
+                let discarded = queue.abort();
+                info!("[SHUTDOWN] Aborting, discarded {} orders", discarded);
+            }
+        }
+        self.orders_cond.notify_all();
+        Ok(())
+    }
+}
+
+fn describe_target(dispenser: Option<usize>) -> String {
+    match dispenser {
+        Some(id) => format!("dispenser {}", id),
+        None => String::from("all dispensers"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{clock::VirtualClock, container::Container};
+
+    use crate::admission::{AdmissionControl, Rejection};
+
+    use super::*;
+
+    fn create_handle(orders_queue: Arc<Mutex<OrdersQueue>>) -> ControlHandle {
+        let mut resources = HashMap::new();
+        resources.insert(Ingredient::Cacao, Arc::new(Mutex::new(Container::new(100))));
+        ControlHandle::new(
+            (orders_queue, Arc::new(Condvar::new())),
+            (Arc::new(resources), Arc::new(Condvar::new())),
+            Vec::new(),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(AdmissionControl::disabled()),
+            Arc::new(Journal::disabled()),
+            Arc::new(ShutdownSignal::default()),
+        )
+    }
+
+    #[test]
+    fn should_keep_the_pending_orders_when_draining() {
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        orders_queue
+            .lock()
+            .expect("Lock error in test")
+            .push(Order::new(1, Vec::new()));
+        let handle = create_handle(orders_queue.clone());
+
+        assert!(handle.shutdown(ShutdownMode::Drain).is_ok());
+
+        let queue = orders_queue.lock().expect("Lock error in test");
+        assert!(queue.finished);
+        assert_eq!(1, queue.len());
+    }
+
+    #[test]
+    fn should_discard_the_pending_orders_when_aborting() {
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        orders_queue
+            .lock()
+            .expect("Lock error in test")
+            .push(Order::new(1, Vec::new()));
+        let handle = create_handle(orders_queue.clone());
+
+        assert!(handle.shutdown(ShutdownMode::Abort).is_ok());
+
+        let queue = orders_queue.lock().expect("Lock error in test");
+        assert!(queue.finished);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn should_pause_and_resume_the_dispensers() {
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        let handle = create_handle(orders_queue.clone());
+
+        assert!(handle.pause(None).is_ok());
+        assert!(handle.resume(Some(3)).is_ok());
+        {
+            let queue = orders_queue.lock().expect("Lock error in test");
+            assert!(queue.is_paused(0));
+            assert!(!queue.is_paused(3));
+        }
+        assert!(handle.resume(None).is_ok());
+        let queue = orders_queue.lock().expect("Lock error in test");
+        assert!(!queue.is_paused(0));
+    }
+
+    #[test]
+    fn should_submit_an_order_and_report_its_status() {
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        let handle = create_handle(orders_queue.clone());
+
+        let id = handle
+            .submit(vec![(Ingredient::Cacao, 10)])
+            .expect("Error in test");
+        assert_eq!(
+            Some(OrderStatus::Queued),
+            handle.order_status(id).expect("Error in test")
+        );
+        assert_eq!(None, handle.order_status(id + 1).expect("Error in test"));
+    }
+
+    #[test]
+    fn should_not_submit_orders_after_the_queue_is_closed() {
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        let handle = create_handle(orders_queue);
+        handle.shutdown(ShutdownMode::Drain).expect("Error in test");
+        assert_eq!(
+            Err(CoffeeMakerError::QueueClosed),
+            handle.submit(vec![(Ingredient::Cacao, 10)])
+        );
+    }
+
+    #[test]
+    fn should_reject_an_order_that_can_never_be_fulfilled() {
+        let mut resources = HashMap::new();
+        resources.insert(Ingredient::Cacao, Arc::new(Mutex::new(Container::new(100))));
+        let resources = Arc::new(resources);
+        let handle = ControlHandle::new(
+            (
+                Arc::new(Mutex::new(OrdersQueue::new())),
+                Arc::new(Condvar::new()),
+            ),
+            (resources.clone(), Arc::new(Condvar::new())),
+            Vec::new(),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(AdmissionControl::new(resources, &[], &[])),
+            Arc::new(Journal::disabled()),
+            Arc::new(ShutdownSignal::default()),
+        );
+
+        let rejection = Rejection {
+            ingredient: Ingredient::Cacao,
+            required: 150,
+            obtainable: 100,
+        };
+        assert_eq!(
+            Err(CoffeeMakerError::OrderRejected {
+                order: 0,
+                rejection
+            }),
+            handle.submit(vec![(Ingredient::Cacao, 150)])
+        );
+        assert_eq!(
+            Some(OrderStatus::Rejected(rejection)),
+            handle.order_status(0).expect("Error in test")
+        );
+        assert_eq!(Ok(1), handle.submit(vec![(Ingredient::Cacao, 100)]));
+    }
+
+    #[test]
+    fn should_refill_a_container_up_to_its_capacity() {
+        let handle = create_handle(Arc::new(Mutex::new(OrdersQueue::new())));
+        {
+            let container = handle
+                .resources
+                .get(&Ingredient::Cacao)
+                .expect("Error in test");
+            container
+                .lock()
+                .expect("Error in test")
+                .level
+                .set_remaining(20);
+        }
+        assert_eq!(Ok(50), handle.refill(Ingredient::Cacao, 50));
+        assert_eq!(Ok(30), handle.refill(Ingredient::Cacao, 50));
+        let levels = handle.levels().expect("Error in test");
+        assert_eq!(100, levels[0].remaining);
+    }
+
+    #[test]
+    fn should_enable_again_the_replenishment_of_the_dependent_container() {
+        let grains = Arc::new(Mutex::new(Container::new(100)));
+        let coffee = Arc::new(Mutex::new(Container::new(100)));
+        let mut resources = HashMap::new();
+        resources.insert(Ingredient::GrainsToGrind, grains.clone());
+        resources.insert(Ingredient::GroundCoffee, coffee.clone());
+        let replenisher_cond = Arc::new(Condvar::new());
+        let ingredients_cond = Arc::new(Condvar::new());
+        let epoch = Arc::new(TransferEpoch::new());
+        let replenisher = Arc::new(ContainerReplenisher::new(
+            (Ingredient::GrainsToGrind, grains.clone()),
+            (Ingredient::GroundCoffee, coffee.clone()),
+            replenisher_cond,
+            ingredients_cond.clone(),
+            100,
+            Arc::new(Journal::disabled()),
+            epoch.clone(),
+            Arc::new(VirtualClock::new()),
+        ));
+        let handle = ControlHandle::new(
+            (
+                Arc::new(Mutex::new(OrdersQueue::new())),
+                Arc::new(Condvar::new()),
+            ),
+            (Arc::new(resources), ingredients_cond),
+            vec![replenisher],
+            epoch,
+            Arc::new(AdmissionControl::disabled()),
+            Arc::new(Journal::disabled()),
+            Arc::new(ShutdownSignal::default()),
+        );
+        grains.lock().expect("Error in test").level.set_remaining(0);
+        coffee.lock().expect("Error in test").finished = true;
+
+        assert_eq!(Ok(100), handle.refill(Ingredient::GrainsToGrind, 500));
+        assert!(!coffee.lock().expect("Error in test").finished);
+        assert_eq!(100, grains.lock().expect("Error in test").remaining());
+    }
+}
@@ -0,0 +1,389 @@
+//! Interfaz de control de la cafetera a traves de un socket Unix local.
+//! Se usa un protocolo de lineas, cada comando recibe una unica linea de respuesta que empieza con `OK` o `ERR`.
+//!
+//! Comandos soportados:
+//! * `submit <pedido json>` agrega un pedido a la cola y responde con su id
+//! * `status <id>` estado del pedido
+//! * `levels` nivel de cada contenedor
+//! * `refill <ingrediente> <cantidad>` recarga un contenedor
+//! * `pause [dispenser]` y `resume [dispenser]` pausan o reanudan a todos los dispensers o a uno en particular
+//! * `shutdown [abort]` finaliza la cafetera
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::{error, info};
+
+use crate::{
+    clock::Clock,
+    constants::CONTROL_SOCKET_POLL_IN_MS,
+    control::{ControlHandle, ShutdownMode},
+    errors::CoffeeMakerError,
+    order::{Ingredient, OrderStatus},
+    orders_reader::parse_json_order,
+};
+
+/// Socket de control de la cafetera. Atiende a cada cliente en su propio hilo hasta que se le indica que finalice
+pub struct ControlSocket {
+    path: PathBuf,
+    control: ControlHandle,
+    finish: Mutex<bool>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ControlSocket {
+    pub fn new(path: PathBuf, control: ControlHandle, clock: Arc<dyn Clock>) -> ControlSocket {
+        ControlSocket {
+            path,
+            control,
+            finish: Mutex::new(false),
+            clock,
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Ok(mut finish) = self.finish.lock() {
+            *finish = true;
+            return;
+        }
+        error!("Error setting control socket to finish");
+    }
+
+    fn socket_error(&self, err: io::Error) -> CoffeeMakerError {
+        CoffeeMakerError::SocketError {
+            path: self.path.display().to_string(),
+            reason: err.to_string(),
+        }
+    }
+
+    fn is_finished(&self) -> Result<bool, CoffeeMakerError> {
+        Ok(*self.finish.lock()?)
+    }
+
+    pub fn serve(&self) -> Result<(), CoffeeMakerError> {
+        // Si quedo el socket de una ejecucion anterior no se puede volver a usar la ruta
+        _ = fs::remove_file(&self.path);
+        let listener = UnixListener::bind(&self.path).map_err(|err| {
+            error!("[CONTROL] Error binding socket {:?}: {:?}", self.path, err);
+            self.socket_error(err)
+        })?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|err| self.socket_error(err))?;
+        info!("[CONTROL] Listening on {:?}", self.path);
+
+        // Los clientes tambien revisan si se finalizo, por lo que al salir del scope ya terminaron todos
+        let result = thread::scope(|scope| {
+            while !self.is_finished()? {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        scope.spawn(move || {
+                            if let Err(err) = self.handle_client(stream) {
+                                error!("[CONTROL] Error with client: {:?}", err);
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        self.clock
+                            .wait(Duration::from_millis(CONTROL_SOCKET_POLL_IN_MS));
+                    }
+                    Err(err) => error!("[CONTROL] Error accepting client: {:?}", err),
+                }
+            }
+            Ok(())
+        });
+        _ = fs::remove_file(&self.path);
+        result
+    }
+
+    fn handle_client(&self, stream: UnixStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_millis(CONTROL_SOCKET_POLL_IN_MS)))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            match reader.read_line(&mut line) {
+                Ok(0) => return Ok(()),
+                Ok(_) => {
+                    let response = handle_command(&self.control, line.trim());
+                    writeln!(writer, "{}", response)?;
+                    line.clear();
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.is_finished().unwrap_or(true) {
+                        return Ok(());
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Ejecuta un comando del protocolo y devuelve la linea de respuesta
+pub fn handle_command(control: &ControlHandle, line: &str) -> String {
+    let (command, arguments) = match line.split_once(' ') {
+        Some((command, arguments)) => (command, arguments.trim()),
+        None => (line, ""),
+    };
+    let result = match command {
+        "submit" => submit(control, arguments),
+        "status" => status(control, arguments),
+        "levels" => levels(control),
+        "refill" => refill(control, arguments),
+        "pause" => parse_dispenser(arguments)
+            .and_then(|dispenser| control.pause(dispenser).map_err(describe_error))
+            .map(|_| String::new()),
+        "resume" => parse_dispenser(arguments)
+            .and_then(|dispenser| control.resume(dispenser).map_err(describe_error))
+            .map(|_| String::new()),
+        "shutdown" => shutdown(control, arguments),
+        _ => Err(format!("unknown command {}", command)),
+    };
+    match result {
+        Ok(response) if response.is_empty() => String::from("OK"),
+        Ok(response) => format!("OK {}", response),
+        Err(err) => format!("ERR {}", err),
+    }
+}
+
+fn submit(control: &ControlHandle, json: &str) -> Result<String, String> {
+    let ingredients = parse_json_order(json).map_err(describe_error)?;
+    let id = control.submit(ingredients).map_err(|err| match err {
+        CoffeeMakerError::OrderRejected { order, rejection } => {
+            format!("rejected {} {}", order, rejection)
+        }
+        err => describe_error(err),
+    })?;
+    Ok(id.to_string())
+}
+
+fn status(control: &ControlHandle, arguments: &str) -> Result<String, String> {
+    let id = arguments
+        .parse::<usize>()
+        .map_err(|_| format!("invalid order id {}", arguments))?;
+    let status = control
+        .order_status(id)
+        .map_err(describe_error)?
+        .ok_or(format!("unknown order {}", id))?;
+    Ok(match status {
+        OrderStatus::Queued => String::from("queued"),
+        OrderStatus::InProgress(dispenser) => format!("in-progress {}", dispenser),
+        OrderStatus::Served => String::from("served"),
+        OrderStatus::Skipped => String::from("skipped"),
+        OrderStatus::Discarded => String::from("discarded"),
+        OrderStatus::Rejected(rejection) => format!("rejected {}", rejection),
+    })
+}
+
+fn levels(control: &ControlHandle) -> Result<String, String> {
+    let levels = control.levels().map_err(describe_error)?;
+    Ok(levels
+        .iter()
+        .map(|level| {
+            format!(
+                "{}={}/{}",
+                level.ingredient.name(),
+                level.remaining,
+                level.capacity
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" "))
+}
+
+fn refill(control: &ControlHandle, arguments: &str) -> Result<String, String> {
+    let (ingredient, quantity) = arguments
+        .split_once(' ')
+        .ok_or(String::from("usage: refill <ingredient> <quantity>"))?;
+    let ingredient = ingredient
+        .parse::<Ingredient>()
+        .map_err(|_| format!("unknown ingredient {}", ingredient))?;
+    let quantity = quantity
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("invalid quantity {}", quantity))?;
+    let added = control
+        .refill(ingredient, quantity)
+        .map_err(describe_error)?;
+    Ok(added.to_string())
+}
+
+fn shutdown(control: &ControlHandle, arguments: &str) -> Result<String, String> {
+    let mode = match arguments {
+        "" | "drain" => ShutdownMode::Drain,
+        "abort" => ShutdownMode::Abort,
+        _ => return Err(format!("unknown shutdown mode {}", arguments)),
+    };
+    control.shutdown(mode).map_err(describe_error)?;
+    Ok(String::new())
+}
+
+fn parse_dispenser(arguments: &str) -> Result<Option<usize>, String> {
+    if arguments.is_empty() {
+        return Ok(None);
+    }
+    arguments
+        .parse::<usize>()
+        .map(Some)
+        .map_err(|_| format!("invalid dispenser id {}", arguments))
+}
+
+fn describe_error(err: CoffeeMakerError) -> String {
+    format!("{:?}", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        env, process,
+        sync::{Arc, Condvar},
+        thread,
+    };
+
+    use crate::{
+        clock::default_clock, container::Container, control::ShutdownSignal, epoch::TransferEpoch,
+        journal::Journal, orders_queue::OrdersQueue,
+    };
+
+    use crate::admission::AdmissionControl;
+
+    use super::*;
+
+    fn create_handle() -> ControlHandle {
+        let mut resources = HashMap::new();
+        resources.insert(Ingredient::Cacao, Arc::new(Mutex::new(Container::new(100))));
+        resources.insert(
+            Ingredient::HotWater,
+            Arc::new(Mutex::new(Container::new(200))),
+        );
+        ControlHandle::new(
+            (
+                Arc::new(Mutex::new(OrdersQueue::new())),
+                Arc::new(Condvar::new()),
+            ),
+            (Arc::new(resources), Arc::new(Condvar::new())),
+            Vec::new(),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(AdmissionControl::disabled()),
+            Arc::new(Journal::disabled()),
+            Arc::new(ShutdownSignal::default()),
+        )
+    }
+
+    #[test]
+    fn should_submit_an_order_and_return_its_status() {
+        let control = create_handle();
+        let response = handle_command(
+            &control,
+            r#"submit {"ground_coffee": 0, "hot_water": 10, "cacao": 20, "milk_foam": 0}"#,
+        );
+        assert_eq!("OK 0", response);
+        assert_eq!("OK queued", handle_command(&control, "status 0"));
+        assert_eq!("ERR unknown order 1", handle_command(&control, "status 1"));
+    }
+
+    #[test]
+    fn should_report_the_levels_and_refill_the_containers() {
+        let control = create_handle();
+        assert_eq!(
+            "OK hot_water=200/200 cacao=100/100",
+            handle_command(&control, "levels")
+        );
+        assert_eq!("OK 0", handle_command(&control, "refill cacao 10"));
+        assert!(handle_command(&control, "refill coffee 10").starts_with("ERR"));
+        assert!(handle_command(&control, "refill cacao").starts_with("ERR"));
+    }
+
+    #[test]
+    fn should_reject_invalid_commands() {
+        let control = create_handle();
+        assert!(handle_command(&control, "submit {").starts_with("ERR"));
+        assert!(handle_command(&control, "pause x").starts_with("ERR"));
+        assert!(handle_command(&control, "dance").starts_with("ERR"));
+    }
+
+    #[test]
+    fn should_not_accept_orders_after_shutdown() {
+        let control = create_handle();
+        assert_eq!("OK", handle_command(&control, "pause 2"));
+        assert_eq!("OK", handle_command(&control, "resume"));
+        assert_eq!("OK", handle_command(&control, "shutdown"));
+        let response = handle_command(
+            &control,
+            r#"submit {"ground_coffee": 0, "hot_water": 10, "cacao": 20, "milk_foam": 0}"#,
+        );
+        assert_eq!("ERR QueueClosed", response);
+    }
+
+    #[test]
+    fn should_answer_commands_through_the_socket() {
+        let path = env::temp_dir().join(format!("coffee-maker-test-{}.sock", process::id()));
+        let socket = Arc::new(ControlSocket::new(
+            path.clone(),
+            create_handle(),
+            default_clock(),
+        ));
+        let socket_clone = socket.clone();
+        let handle = thread::spawn(move || socket_clone.serve());
+
+        let stream = loop {
+            if let Ok(stream) = UnixStream::connect(&path) {
+                break stream;
+            }
+            thread::yield_now();
+        };
+        let mut writer = stream.try_clone().expect("Error in test");
+        let mut reader = BufReader::new(stream);
+        writeln!(writer, "levels").expect("Error in test");
+        let mut response = String::new();
+        reader.read_line(&mut response).expect("Error in test");
+        assert_eq!("OK hot_water=200/200 cacao=100/100\n", response);
+        drop(writer);
+        drop(reader);
+
+        socket.finish();
+        assert!(handle.join().expect("Error in test").is_ok());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_answer_a_client_while_another_one_stays_connected() {
+        let path =
+            env::temp_dir().join(format!("coffee-maker-test-{}-clients.sock", process::id()));
+        let socket = Arc::new(ControlSocket::new(
+            path.clone(),
+            create_handle(),
+            default_clock(),
+        ));
+        let socket_clone = socket.clone();
+        let handle = thread::spawn(move || socket_clone.serve());
+
+        let idle = loop {
+            if let Ok(stream) = UnixStream::connect(&path) {
+                break stream;
+            }
+            thread::yield_now();
+        };
+        let stream = UnixStream::connect(&path).expect("Error in test");
+        let mut writer = stream.try_clone().expect("Error in test");
+        let mut reader = BufReader::new(stream);
+        writeln!(writer, "levels").expect("Error in test");
+        let mut response = String::new();
+        reader.read_line(&mut response).expect("Error in test");
+        assert_eq!("OK hot_water=200/200 cacao=100/100\n", response);
+
+        // El cliente que sigue conectado no impide finalizar
+        socket.finish();
+        assert!(handle.join().expect("Error in test").is_ok());
+        drop(idle);
+    }
+}
@@ -0,0 +1,72 @@
+//! Contadores de pedidos de la cafetera
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Cantidad de pedidos procesados y salteados. Los dispensers los incrementan y las estadisticas los leen
+/// sin tomar ningun lock. Cada contador es independiente, no publica ningun otro dato, por lo que alcanza con `Relaxed`
+#[derive(Debug, Default)]
+pub struct OrderCounters {
+    processed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl OrderCounters {
+    pub fn new() -> OrderCounters {
+        OrderCounters::default()
+    }
+
+    pub fn processed(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    pub fn skipped(&self) -> u64 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    pub fn increase_processed(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increase_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Pisa la cantidad de pedidos procesados, por ejemplo al retomar desde una foto
+    pub fn set_processed(&self, processed: u64) {
+        self.processed.store(processed, Ordering::Relaxed);
+    }
+
+    pub fn set_skipped(&self, skipped: u64) {
+        self.skipped.store(skipped, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn should_not_lose_increments_from_many_threads() {
+        let counters = Arc::new(OrderCounters::new());
+        let threads = (0..8)
+            .map(|_| {
+                let counters = counters.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        if i % 4 == 0 {
+                            counters.increase_skipped();
+                        } else {
+                            counters.increase_processed();
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("Error in test");
+        }
+        assert_eq!(6000, counters.processed());
+        assert_eq!(2000, counters.skipped());
+    }
+}
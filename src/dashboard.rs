@@ -0,0 +1,293 @@
+//! Panel de la cafetera para la terminal. Alternativa a la impresion periodica de estadisticas
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::error;
+
+use crate::{
+    clock::Clock,
+    constants::{DASHBOARD_BAR_WIDTH, DASHBOARD_RECENT_ALERTS, DASHBOARD_REFRESH_IN_MS},
+    container::{get_levels, levels_of, Container, ContainerLevel},
+    container_source_replenisher::ContainerReplenisher,
+    counters::OrderCounters,
+    dispenser::{Dispenser, DispenserState},
+    errors::CoffeeMakerError,
+    external_source_replenisher::ExternalReplenisher,
+    order::Ingredient,
+    orders_queue::OrdersQueue,
+    poison::LockOrRecover,
+    statistics::is_below_warning_level,
+};
+
+/// Secuencia ANSI para limpiar la pantalla y volver el cursor al inicio
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Estado de un reponedor en un momento dado
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplenisherStatus {
+    pub ingredient: Ingredient,
+    pub source: Option<Ingredient>,
+    pub replenishing: bool,
+}
+
+/// Foto del estado de la cafetera que se dibuja en el panel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DashboardSnapshot {
+    pub levels: Vec<ContainerLevel>,
+    pub dispensers: Vec<(usize, DispenserState)>,
+    pub replenishers: Vec<ReplenisherStatus>,
+    pub queue_depth: usize,
+    pub orders_processed: u64,
+    pub alerts: Vec<String>,
+}
+
+/// Alertas recientes del panel. Se recuerdan los contenedores que ya estan por debajo
+/// del nivel de alerta para no repetir el aviso en cada actualizacion.
+struct Alerts {
+    recent: VecDeque<String>,
+    below_level: HashSet<Ingredient>,
+}
+
+/// Panel que se redibuja periodicamente en la terminal con el estado de los contenedores,
+/// dispensers, reponedores y la cola de pedidos.
+pub struct Dashboard {
+    resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+    orders_queue: Arc<Mutex<OrdersQueue>>,
+    counters: Arc<OrderCounters>,
+    dispensers: Vec<Arc<Dispenser>>,
+    container_replenishers: Vec<Arc<ContainerReplenisher>>,
+    external_replenishers: Vec<Arc<ExternalReplenisher>>,
+    alerts: Mutex<Alerts>,
+    clock: Arc<dyn Clock>,
+    finish: Mutex<bool>,
+}
+
+impl Dashboard {
+    pub fn new(
+        resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+        orders_queue: Arc<Mutex<OrdersQueue>>,
+        counters: Arc<OrderCounters>,
+        dispensers: Vec<Arc<Dispenser>>,
+        replenishers: (
+            Vec<Arc<ContainerReplenisher>>,
+            Vec<Arc<ExternalReplenisher>>,
+        ),
+        clock: Arc<dyn Clock>,
+    ) -> Dashboard {
+        let (container_replenishers, external_replenishers) = replenishers;
+        Dashboard {
+            resources,
+            orders_queue,
+            counters,
+            dispensers,
+            container_replenishers,
+            external_replenishers,
+            alerts: Mutex::new(Alerts {
+                recent: VecDeque::with_capacity(DASHBOARD_RECENT_ALERTS),
+                below_level: HashSet::new(),
+            }),
+            clock,
+            finish: Mutex::new(false),
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Ok(mut finish) = self.finish.lock() {
+            *finish = true;
+            return;
+        }
+        error!("Error setting dashboard thread to finish");
+    }
+
+    pub fn show(&self) -> Result<(), CoffeeMakerError> {
+        loop {
+            let finished = *self.finish.lock()?;
+            let snapshot = self.take_snapshot()?;
+            print!("{}{}", CLEAR_SCREEN, render(&snapshot));
+            if let Err(err) = io::stdout().flush() {
+                error!("Error drawing the dashboard: {:?}", err);
+            }
+            if finished {
+                return Ok(());
+            }
+            self.clock
+                .wait(Duration::from_millis(DASHBOARD_REFRESH_IN_MS));
+        }
+    }
+
+    pub fn take_snapshot(&self) -> Result<DashboardSnapshot, CoffeeMakerError> {
+        let levels = get_levels(&levels_of(&self.resources)?);
+        self.update_alerts(&levels)?;
+
+        let mut dispensers = Vec::with_capacity(self.dispensers.len());
+        for dispenser in &self.dispensers {
+            dispensers.push((dispenser.id(), dispenser.state()?));
+        }
+
+        let mut replenishers = Vec::with_capacity(
+            self.container_replenishers.len() + self.external_replenishers.len(),
+        );
+        for replenisher in &self.container_replenishers {
+            replenishers.push(ReplenisherStatus {
+                ingredient: replenisher.dest_ingredient(),
+                source: Some(replenisher.source_ingredient()),
+                replenishing: replenisher.is_replenishing()?,
+            });
+        }
+        for replenisher in &self.external_replenishers {
+            replenishers.push(ReplenisherStatus {
+                ingredient: replenisher.ingredient(),
+                source: None,
+                replenishing: replenisher.is_replenishing()?,
+            });
+        }
+
+        Ok(DashboardSnapshot {
+            levels,
+            dispensers,
+            replenishers,
+            queue_depth: self.orders_queue.lock_or_recover()?.len(),
+            orders_processed: self.counters.processed(),
+            alerts: self.alerts.lock()?.recent.iter().cloned().collect(),
+        })
+    }
+
+    fn update_alerts(&self, levels: &[ContainerLevel]) -> Result<(), CoffeeMakerError> {
+        let mut alerts = self.alerts.lock()?;
+        for level in levels {
+            if !is_below_warning_level(&level.ingredient, level.remaining, level.capacity) {
+                alerts.below_level.remove(&level.ingredient);
+                continue;
+            }
+            if alerts.below_level.insert(level.ingredient) {
+                if alerts.recent.len() == DASHBOARD_RECENT_ALERTS {
+                    alerts.recent.pop_front();
+                }
+                alerts.recent.push_back(format!(
+                    "{:?} container low at {}",
+                    level.ingredient, level.remaining
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Arma el texto del panel a partir de una foto del estado de la cafetera
+pub fn render(snapshot: &DashboardSnapshot) -> String {
+    let mut screen = String::from("CoffeeGPT\n\nContainers\n");
+    for level in &snapshot.levels {
+        screen.push_str(&format!(
+            "  {:<14} [{}] {:>5}/{}\n",
+            format!("{:?}", level.ingredient),
+            fill_bar(level.remaining, level.capacity),
+            level.remaining,
+            level.capacity
+        ));
+    }
+
+    screen.push_str("\nDispensers\n");
+    for (id, state) in &snapshot.dispensers {
+        let description = match state {
+            DispenserState::Idle => String::from("idle"),
+            DispenserState::Paused => String::from("paused"),
+            DispenserState::Serving(order) => format!("serving order {}", order),
+            DispenserState::WaitingFor(order, ingredient) => {
+                format!("order {} waiting for {:?}", order, ingredient)
+            }
+        };
+        screen.push_str(&format!("  #{:<3} {}\n", id, description));
+    }
+
+    screen.push_str("\nReplenishers\n");
+    for replenisher in &snapshot.replenishers {
+        let source = match replenisher.source {
+            Some(source) => format!("{:?}", source),
+            None => String::from("external source"),
+        };
+        let state = if replenisher.replenishing {
+            "replenishing"
+        } else {
+            "idle"
+        };
+        screen.push_str(&format!(
+            "  {:?} <- {}: {}\n",
+            replenisher.ingredient, source, state
+        ));
+    }
+
+    screen.push_str(&format!(
+        "\nQueue depth: {} | Orders processed: {}\n\nRecent alerts\n",
+        snapshot.queue_depth, snapshot.orders_processed
+    ));
+    if snapshot.alerts.is_empty() {
+        screen.push_str("  none\n");
+    }
+    for alert in &snapshot.alerts {
+        screen.push_str(&format!("  {}\n", alert));
+    }
+    screen
+}
+
+fn fill_bar(remaining: u64, capacity: u64) -> String {
+    let filled = (remaining.min(capacity) * DASHBOARD_BAR_WIDTH as u64)
+        .checked_div(capacity)
+        .unwrap_or(0) as usize;
+    format!(
+        "{}{}",
+        "#".repeat(filled),
+        "-".repeat(DASHBOARD_BAR_WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_draw_the_fill_bar_proportional_to_the_remaining() {
+        assert_eq!("#".repeat(DASHBOARD_BAR_WIDTH), fill_bar(100, 100));
+        assert_eq!("-".repeat(DASHBOARD_BAR_WIDTH), fill_bar(0, 100));
+        let half = fill_bar(50, 100);
+        assert_eq!(DASHBOARD_BAR_WIDTH / 2, half.matches('#').count());
+        assert_eq!(DASHBOARD_BAR_WIDTH, half.len());
+    }
+
+    #[test]
+    fn should_render_the_state_of_the_coffee_maker() {
+        let snapshot = DashboardSnapshot {
+            levels: vec![ContainerLevel {
+                ingredient: Ingredient::Cacao,
+                remaining: 500,
+                capacity: 5000,
+            }],
+            dispensers: vec![
+                (0, DispenserState::Idle),
+                (1, DispenserState::Serving(7)),
+                (2, DispenserState::WaitingFor(8, Ingredient::MilkFoam)),
+            ],
+            replenishers: vec![ReplenisherStatus {
+                ingredient: Ingredient::HotWater,
+                source: None,
+                replenishing: true,
+            }],
+            queue_depth: 3,
+            orders_processed: 12,
+            alerts: vec![String::from("Cacao container low at 500")],
+        };
+
+        let screen = render(&snapshot);
+        assert!(screen.contains("Cacao"));
+        assert!(screen.contains("500/5000"));
+        assert!(screen.contains("#0   idle"));
+        assert!(screen.contains("serving order 7"));
+        assert!(screen.contains("order 8 waiting for MilkFoam"));
+        assert!(screen.contains("HotWater <- external source: replenishing"));
+        assert!(screen.contains("Queue depth: 3 | Orders processed: 12"));
+        assert!(screen.contains("Cacao container low at 500"));
+    }
+}
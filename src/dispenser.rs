@@ -1,48 +1,75 @@
 //! Dispenser de la cafetera. Procesa los pedidos.
 use std::{
     collections::HashMap,
-    sync::{Arc, Condvar, Mutex, MutexGuard, RwLock},
+    sync::{Arc, Condvar, Mutex, RwLock},
     time::Duration,
 };
 
-use log::{debug, info};
+use log::{debug, error, info};
 
 use crate::{
-    container::Container,
+    admission::AdmissionControl,
+    clock::Clock,
+    container::{AtomicLevel, ConsumePath, Container, Levels},
+    counters::OrderCounters,
     errors::CoffeeMakerError,
-    order::{Ingredient, Order},
+    ingredient_order::IngredientOrder,
+    journal::{Journal, JournalEvent},
+    latency::LatencyHistograms,
+    order::{Ingredient, Order, OrderStatus},
+    order_source::OrderIngredients,
     orders_queue::OrdersQueue,
+    poison::{LockOrRecover, WaitOrRecover},
+    supervisor::Worker,
 };
 
-mod sync {
-    use std::thread;
-    use std::time::Duration;
-
-    #[cfg(not(test))]
-    pub(crate) fn sleep(d: Duration) {
-        thread::sleep(d);
-    }
+/// Estado en el que se encuentra un dispenser. Se usa para mostrarlo en el panel de la cafetera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispenserState {
+    /// Esperando a que llegue un pedido
+    Idle,
+    /// Pausado, no toma nuevos pedidos hasta que se lo reanude
+    Paused,
+    /// Preparando el pedido con el id indicado
+    Serving(usize),
+    /// Esperando a que se reponga un ingrediente para continuar con el pedido
+    WaitingFor(usize, Ingredient),
+}
 
-    #[cfg(test)]
-    pub(crate) fn sleep(_: Duration) {
-        thread::yield_now();
-    }
+/// Pedido que esta preparando un dispenser junto con los ingredientes que ya desconto.
+/// Si el dispenser se cae, con esto se devuelve el pedido a la cola
+struct InFlightOrder {
+    order: Order,
+    used: Vec<(Ingredient, u64)>,
 }
 
 /// Representa a un dispenser de la cafetera.
 /// Tiene referencias a la cola de pedidos (junto con su variable condicional),
-/// reponedores de ingredientes (junto con su variable condicional), los recursos, y el contador de ordenes procesadas
+/// reponedores de ingredientes (junto con su variable condicional), los recursos y sus niveles, los contadores de pedidos,
+/// los histogramas de latencia, el journal de eventos, la estrategia con la que ordena los ingredientes de cada pedido,
+/// el control de admision que revisa el pedido antes de consumir algun ingrediente, la forma en la que descuenta los ingredientes, y el reloj que define cuanto tarda en servir cada ingrediente.
+/// Tambien guarda el pedido que esta preparando, para que el supervisor lo pueda devolver a la cola si se cae
 pub struct Dispenser {
     id: usize,
     orders_queue: Arc<Mutex<OrdersQueue>>,
     orders_cond: Arc<Condvar>,
     replenisher: Arc<Condvar>,
     resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+    levels: Arc<Levels>,
     ingredients_cond: Arc<Condvar>,
-    orders_processed: Arc<RwLock<u64>>,
+    counters: Arc<OrderCounters>,
+    latencies: Arc<Mutex<LatencyHistograms>>,
+    journal: Arc<Journal>,
+    ingredient_order: Arc<IngredientOrder>,
+    admission: Arc<AdmissionControl>,
+    consume_path: ConsumePath,
+    clock: Arc<dyn Clock>,
+    state: RwLock<DispenserState>,
+    in_flight: Mutex<Option<InFlightOrder>>,
 }
 
 impl Dispenser {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: usize,
         orders_queue: Arc<Mutex<OrdersQueue>>,
@@ -50,7 +77,14 @@ impl Dispenser {
         replenisher: Arc<Condvar>,
         ingredients_cond: Arc<Condvar>,
         resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
-        orders_processed: Arc<RwLock<u64>>,
+        levels: Arc<Levels>,
+        counters: Arc<OrderCounters>,
+        latencies: Arc<Mutex<LatencyHistograms>>,
+        journal: Arc<Journal>,
+        ingredient_order: Arc<IngredientOrder>,
+        admission: Arc<AdmissionControl>,
+        consume_path: ConsumePath,
+        clock: Arc<dyn Clock>,
     ) -> Dispenser {
         Dispenser {
             id,
@@ -59,54 +93,200 @@ impl Dispenser {
             replenisher,
             ingredients_cond,
             resources,
-            orders_processed,
+            levels,
+            counters,
+            latencies,
+            journal,
+            ingredient_order,
+            admission,
+            consume_path,
+            clock,
+            state: RwLock::new(DispenserState::Idle),
+            in_flight: Mutex::new(None),
         }
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn state(&self) -> Result<DispenserState, CoffeeMakerError> {
+        Ok(*self.state.read()?)
+    }
+
+    /// Pedido que esta preparando, junto con lo que ya desconto de cada contenedor
+    pub fn in_flight_order(&self) -> Result<Option<(Order, OrderIngredients)>, CoffeeMakerError> {
+        Ok(self
+            .in_flight
+            .lock()?
+            .as_ref()
+            .map(|in_flight| (in_flight.order.clone(), in_flight.used.clone())))
+    }
+
+    fn set_state(&self, state: DispenserState) {
+        if let Ok(mut current) = self.state.write() {
+            *current = state;
+            return;
+        }
+        error!("[DISPENSER {}] Error updating its state", self.id);
+    }
+
     pub fn handle_orders(&self) -> Result<(), CoffeeMakerError> {
         loop {
             let order = {
-                let mut orders = self
-                    .orders_cond
-                    .wait_while(self.orders_queue.lock()?, |queue| {
-                        queue.is_empty() && !queue.finished
-                    })?;
+                let mut orders =
+                    self.orders_cond
+                        .wait_while_or_recover(&self.orders_queue, |queue| {
+                            let paused = queue.is_paused(self.id);
+                            self.set_state(if paused {
+                                DispenserState::Paused
+                            } else {
+                                DispenserState::Idle
+                            });
+                            if queue.is_empty() {
+                                return !queue.finished;
+                            }
+                            paused
+                        })?;
 
                 if orders.is_empty() && orders.finished {
                     return Ok(());
                 }
 
-                orders
+                let order = orders
                     .pop()
-                    .ok_or(CoffeeMakerError::EmptyQueueWhenNotExpected)?
+                    .ok_or(CoffeeMakerError::EmptyQueueWhenNotExpected { dispenser: self.id })?;
+                orders.set_status(order.id, OrderStatus::InProgress(self.id));
+                // Se registra con la cola tomada, asi una foto siempre lo encuentra en la cola o en preparacion
+                self.start_order(&order)?;
+                order
             };
 
             debug!("[DISPENSER {}] Takes order {}", self.id, order.id);
-            self.process_order(order)?;
+            self.latencies
+                .lock()?
+                .record_queue_wait(self.clock.local_now().saturating_sub(order.created_at));
+            let id = order.id;
+            if let Some(rejection) = self.admission.check(&order.ingredients)? {
+                info!(
+                    "[DISPENSER {}] Rejected order {}: {}",
+                    self.id, id, rejection
+                );
+                *self.in_flight.lock()? = None;
+                self.orders_queue.lock_or_recover()?.reject(id, rejection);
+                continue;
+            }
+            self.set_state(DispenserState::Serving(id));
+            let result = self.process_order(order);
+            self.set_state(DispenserState::Idle);
+            let status = if result? {
+                OrderStatus::Served
+            } else {
+                OrderStatus::Skipped
+            };
+            self.orders_queue.lock_or_recover()?.set_status(id, status);
         }
     }
 
-    fn process_order(&self, order: Order) -> Result<(), CoffeeMakerError> {
+    /// Registra el pedido como en preparacion, sin ningun ingrediente usado todavia
+    fn start_order(&self, order: &Order) -> Result<(), CoffeeMakerError> {
+        *self.in_flight.lock()? = Some(InFlightOrder {
+            order: order.clone(),
+            used: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Prepara el pedido, que ya tiene que estar registrado con `start_order`.
+    /// Devuelve si se pudo completar o si se salteo por falta de algun ingrediente
+    fn process_order(&self, mut order: Order) -> Result<bool, CoffeeMakerError> {
+        let preparation_start = self.clock.local_now();
+        self.ingredient_order
+            .sort(order.id, &mut order.ingredients, &self.levels)?;
         for (ingredient, quantity_required) in order.ingredients {
-            let resource_lock = self.get_resource_lock(&ingredient)?;
-
-            let mut container = self
-                .ingredients_cond
-                .wait_while(resource_lock.lock()?, |container| {
-                    self.should_wake_replenisher(container, quantity_required, &ingredient)
-                })
-                .map_err(|_| CoffeeMakerError::LockError)?;
-            if container.remaining < quantity_required {
+            if !self.take_ingredient(order.id, ingredient, quantity_required)? {
                 info!(
                     "[DISPENSER {}] Skipped order {}, not enough {:?}",
                     self.id, order.id, ingredient
                 );
-                return Ok(());
+                self.journal.record(JournalEvent::OrderSkipped {
+                    order: order.id,
+                    dispenser: self.id,
+                    ingredient,
+                });
+                if let Some(in_flight) = self.in_flight.lock()?.take() {
+                    self.record_waste(&in_flight.used)?;
+                }
+                self.counters.increase_skipped();
+                return Ok(false);
             }
-            self.consume_ingredient(&mut container, quantity_required, &ingredient);
         }
-        self.increase_processed_orders()?;
-        Ok(())
+        self.counters.increase_processed();
+        self.journal.record(JournalEvent::OrderServed {
+            order: order.id,
+            dispenser: self.id,
+        });
+        *self.in_flight.lock()? = None;
+        self.latencies
+            .lock()?
+            .record_preparation(self.clock.local_now().saturating_sub(preparation_start));
+        Ok(true)
+    }
+
+    /// Descuenta `quantity_required` del contenedor y lo sirve. Devuelve falso si no alcanza y no se va a reponer.
+    /// Con `ConsumePath::LockFree` primero se intenta descontar sin tomar el lock del contenedor, y solo se lo toma
+    /// si hay que esperar al reponedor. Con `ConsumePath::Locked` el contenedor queda tomado mientras se sirve
+    fn take_ingredient(
+        &self,
+        order_id: usize,
+        ingredient: Ingredient,
+        quantity_required: u64,
+    ) -> Result<bool, CoffeeMakerError> {
+        if self.consume_path == ConsumePath::LockFree
+            && self.get_level(&ingredient)?.try_consume(quantity_required)
+        {
+            self.record_used(order_id, ingredient, quantity_required)?;
+            self.serve_ingredient(quantity_required, &ingredient)?;
+            return Ok(true);
+        }
+
+        let resource_lock = self.get_resource_lock(&ingredient)?;
+        let wait_start = self.clock.local_now();
+        let mut waited_for_replenisher = false;
+        let mut taken = false;
+        let container =
+            self.ingredients_cond
+                .wait_while_or_recover(resource_lock, |container| {
+                    // Con el camino sin lock otro dispenser puede descontar en cualquier momento,
+                    // por eso se descuenta en el mismo paso en el que se revisa si alcanza
+                    taken = container.level.try_consume(quantity_required);
+                    if taken {
+                        return false;
+                    }
+                    let need_more_resource =
+                        self.should_wake_replenisher(container, quantity_required, &ingredient);
+                    if need_more_resource && !waited_for_replenisher {
+                        self.set_state(DispenserState::WaitingFor(order_id, ingredient));
+                    }
+                    waited_for_replenisher |= need_more_resource;
+                    need_more_resource
+                })?;
+        if waited_for_replenisher {
+            self.set_state(DispenserState::Serving(order_id));
+            self.latencies
+                .lock()?
+                .record_replenishment_wait(self.clock.local_now().saturating_sub(wait_start));
+        }
+        if !taken {
+            return Ok(false);
+        }
+        self.record_used(order_id, ingredient, quantity_required)?;
+        // Con `ConsumePath::Locked` el contenedor sigue tomado hasta terminar de servir
+        if self.consume_path == ConsumePath::LockFree {
+            drop(container);
+        }
+        self.serve_ingredient(quantity_required, &ingredient)?;
+        Ok(true)
     }
 
     fn should_wake_replenisher(
@@ -115,10 +295,10 @@ impl Dispenser {
         quantity_required: u64,
         ingredient: &Ingredient,
     ) -> bool {
-        if container.finished || has_no_replenisher(ingredient) {
+        if container.finished || !container.replenishable {
             return false;
         }
-        let need_more_resource = container.remaining < quantity_required;
+        let need_more_resource = container.remaining() < quantity_required;
         if need_more_resource {
             info!(
                 "[DISPENSER {}] Not enough {:?} for this order, waking up replenisher",
@@ -136,51 +316,110 @@ impl Dispenser {
         let resource_lock = self
             .resources
             .get(ingredient)
-            .ok_or(CoffeeMakerError::IngredientNotInMap)?;
+            .ok_or(CoffeeMakerError::IngredientNotInMap(*ingredient))?;
         Ok(resource_lock)
     }
 
-    fn increase_processed_orders(&self) -> Result<(), CoffeeMakerError> {
-        let mut processed = self
-            .orders_processed
-            .write()
-            .map_err(|_| CoffeeMakerError::LockError)?;
-        *processed += 1;
+    fn get_level(&self, ingredient: &Ingredient) -> Result<&AtomicLevel, CoffeeMakerError> {
+        self.levels
+            .get(ingredient)
+            .map(|level| level.as_ref())
+            .ok_or(CoffeeMakerError::IngredientNotInMap(*ingredient))
+    }
+
+    /// Registra que se desconto un ingrediente del pedido en preparacion, antes de servirlo
+    fn record_used(
+        &self,
+        order_id: usize,
+        ingredient: Ingredient,
+        quantity: u64,
+    ) -> Result<(), CoffeeMakerError> {
+        if let Some(in_flight) = self.in_flight.lock()?.as_mut() {
+            in_flight.used.push((ingredient, quantity));
+        }
+        self.journal.record(JournalEvent::IngredientConsumed {
+            order: order_id,
+            dispenser: self.id,
+            ingredient,
+            quantity,
+        });
         Ok(())
     }
 
-    fn consume_ingredient(
+    /// Devuelve a la cola el pedido que se estaba preparando al caerse el dispenser. Lo que ya se habia usado
+    /// se registra como desperdicio, ya que el pedido se vuelve a preparar desde el principio
+    fn requeue_in_flight_order(&self) -> Result<Option<usize>, CoffeeMakerError> {
+        self.set_state(DispenserState::Idle);
+        let Some(in_flight) = self.in_flight.lock()?.take() else {
+            return Ok(None);
+        };
+        self.record_waste(&in_flight.used)?;
+        let id = in_flight.order.id;
+        info!("[DISPENSER {}] Returns order {} to the queue", self.id, id);
+        self.orders_queue
+            .lock_or_recover()?
+            .requeue(in_flight.order);
+        self.orders_cond.notify_all();
+        Ok(Some(id))
+    }
+
+    /// Registra como desperdicio lo que ya se habia usado de un pedido que se salteo
+    fn record_waste(&self, used: &[(Ingredient, u64)]) -> Result<(), CoffeeMakerError> {
+        for (ingredient, quantity) in used {
+            self.get_resource_lock(ingredient)?
+                .lock_or_recover()?
+                .wasted += quantity;
+        }
+        Ok(())
+    }
+
+    /// Sirve un ingrediente que ya se desconto del contenedor
+    fn serve_ingredient(
         &self,
-        mutex: &mut MutexGuard<Container>,
         quantity_required: u64,
         ingredient: &Ingredient,
-    ) {
+    ) -> Result<(), CoffeeMakerError> {
         debug!(
-            "[DISPENSER {}] Uses {} of {:?}, there is {}",
-            self.id, quantity_required, ingredient, mutex.remaining
+            "[DISPENSER {}] Uses {} of {:?}",
+            self.id, quantity_required, ingredient
         );
-        mutex.remaining -= quantity_required;
-        mutex.consumed += quantity_required;
-        sync::sleep(Duration::from_millis(quantity_required));
+        self.clock.sleep(Duration::from_millis(quantity_required));
         debug!(
             "[DISPENSER {}] Remains {} of {:?}",
-            self.id, mutex.remaining, ingredient
+            self.id,
+            self.get_level(ingredient)?.remaining(),
+            ingredient
         );
+        Ok(())
     }
 }
 
-fn has_no_replenisher(ingredient: &Ingredient) -> bool {
-    *ingredient == Ingredient::Cacao
+impl Worker for Dispenser {
+    fn work(&self) -> Result<(), CoffeeMakerError> {
+        self.handle_orders()
+            .map_err(|err| CoffeeMakerError::Dispenser {
+                id: self.id,
+                source: Box::new(err),
+            })
+    }
+
+    fn recover(&self) -> Result<Option<usize>, CoffeeMakerError> {
+        self.requeue_in_flight_order()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
+        clock::VirtualClock,
         constants::{
             A_WATER_STORAGE, C_CACAO_STORAGE, E_FOAM_STORAGE, G_GRAINS_STORAGE, L_MILK_STORAGE,
             M_COFFEE_STORAGE,
         },
+        container::levels_of,
+        ingredient_order::IngredientOrdering,
         order::TOTAL_INGREDIENTS,
+        snapshot::CoffeeMakerSnapshot,
     };
 
     use super::*;
@@ -210,7 +449,8 @@ mod tests {
         let orders_cond = Arc::new(Condvar::new());
         let replenisher_cond = Arc::new(Condvar::new());
         let ingredients_cond = Arc::new(Condvar::new());
-        let orders_processed = Arc::new(RwLock::new(0));
+        let counters = Arc::new(OrderCounters::new());
+        let latencies = Arc::new(Mutex::new(LatencyHistograms::new()));
 
         let dispenser = Arc::new(Dispenser::new(
             1,
@@ -219,7 +459,14 @@ mod tests {
             replenisher_cond.clone(),
             ingredients_cond.clone(),
             resources.clone(),
-            orders_processed.clone(),
+            Arc::new(levels_of(&resources).expect("Error in test")),
+            counters.clone(),
+            latencies.clone(),
+            Arc::new(Journal::disabled()),
+            Arc::new(IngredientOrder::new(IngredientOrdering::Canonical)),
+            Arc::new(AdmissionControl::disabled()),
+            ConsumePath::LockFree,
+            Arc::new(VirtualClock::new()),
         ));
 
         let result = dispenser.process_order(Order::new(
@@ -227,21 +474,23 @@ mod tests {
             vec![(Ingredient::HotWater, 100), (Ingredient::GroundCoffee, 100)],
         ));
 
-        assert!(result.is_ok());
-        assert_eq!(
-            1,
-            *orders_processed
-                .read()
-                .expect("Error reading processed orders in test")
-        );
+        assert_eq!(Ok(true), result);
+        assert_eq!(1, counters.processed());
 
         let container = hot_water.lock().expect("Error in hot water lock in test");
-        assert_eq!(A_WATER_STORAGE - 100, container.remaining);
-        assert_eq!(100, container.consumed);
+        assert_eq!(A_WATER_STORAGE - 100, container.remaining());
+        assert_eq!(100, container.consumed());
 
         let container = ground_coffee.lock().expect("Error in coffee lock in test");
-        assert_eq!(M_COFFEE_STORAGE - 100, container.remaining);
-        assert_eq!(100, container.consumed);
+        assert_eq!(M_COFFEE_STORAGE - 100, container.remaining());
+        assert_eq!(100, container.consumed());
+
+        let summary = latencies
+            .lock()
+            .expect("Error in latencies lock in test")
+            .summary();
+        assert_eq!(1, summary.preparation.count);
+        assert_eq!(0, summary.replenishment_wait.count);
     }
 
     /// Las cantidades de los ingredientes fueron calculadas con valores iniciales de 5000
@@ -267,7 +516,7 @@ mod tests {
         let orders_cond = Arc::new(Condvar::new());
         let replenisher_cond = Arc::new(Condvar::new());
         let ingredients_cond = Arc::new(Condvar::new());
-        let orders_processed = Arc::new(RwLock::new(0));
+        let counters = Arc::new(OrderCounters::new());
 
         let dispenser = Arc::new(Dispenser::new(
             1,
@@ -276,41 +525,145 @@ mod tests {
             replenisher_cond.clone(),
             ingredients_cond.clone(),
             resources.clone(),
-            orders_processed.clone(),
+            Arc::new(levels_of(&resources).expect("Error in test")),
+            counters.clone(),
+            Arc::new(Mutex::new(LatencyHistograms::new())),
+            Arc::new(Journal::disabled()),
+            Arc::new(IngredientOrder::new(IngredientOrdering::Canonical)),
+            Arc::new(AdmissionControl::disabled()),
+            ConsumePath::Locked,
+            Arc::new(VirtualClock::new()),
         ));
         {
-            let mut container = cacao.lock().expect("Error in cacao lock in test");
-            container.remaining = 0;
-            container.consumed = C_CACAO_STORAGE;
+            let container = cacao.lock().expect("Error in cacao lock in test");
+            container.level.set(0, C_CACAO_STORAGE);
         }
 
-        let result = dispenser.process_order(Order::new(
+        let order = Order::new(
             1,
             vec![
                 (Ingredient::HotWater, 100),
                 (Ingredient::Cacao, 100),
                 (Ingredient::MilkFoam, 100),
             ],
-        ));
-
-        assert!(result.is_ok());
-        assert_eq!(
-            0,
-            *orders_processed
-                .read()
-                .expect("Error reading processed orders in test")
         );
+        dispenser.start_order(&order).expect("Error in test");
+        let result = dispenser.process_order(order);
+
+        assert_eq!(Ok(false), result);
+        assert_eq!(0, counters.processed());
+        assert_eq!(1, counters.skipped());
 
         let container = hot_water.lock().expect("Error in hot water lock in test");
-        assert_eq!(A_WATER_STORAGE - 100, container.remaining);
-        assert_eq!(100, container.consumed);
+        assert_eq!(A_WATER_STORAGE - 100, container.remaining());
+        assert_eq!(100, container.consumed());
+        assert_eq!(100, container.wasted);
 
         let container = cacao.lock().expect("Error in cacao lock in test");
-        assert_eq!(0, container.remaining);
-        assert_eq!(C_CACAO_STORAGE, container.consumed);
+        assert_eq!(0, container.remaining());
+        assert_eq!(C_CACAO_STORAGE, container.consumed());
 
         let container = milk_foam.lock().expect("Error in milk foam lock in test");
-        assert_eq!(E_FOAM_STORAGE, container.remaining);
-        assert_eq!(0, container.consumed);
+        assert_eq!(E_FOAM_STORAGE, container.remaining());
+        assert_eq!(0, container.consumed());
+    }
+
+    #[test]
+    fn should_skip_the_order_before_consuming_anything_when_using_the_scarcest_first() {
+        let mut resources = HashMap::with_capacity(TOTAL_INGREDIENTS);
+        let hot_water = Arc::new(Mutex::new(Container::new(A_WATER_STORAGE)));
+        let cacao = Arc::new(Mutex::new(Container::new(C_CACAO_STORAGE)));
+        resources.insert(Ingredient::HotWater, hot_water.clone());
+        resources.insert(Ingredient::Cacao, cacao.clone());
+        cacao
+            .lock()
+            .expect("Error in cacao lock in test")
+            .level
+            .set_remaining(50);
+
+        let counters = Arc::new(OrderCounters::new());
+        let levels = levels_of(&resources).expect("Error in test");
+        let dispenser = Dispenser::new(
+            1,
+            Arc::new(Mutex::new(OrdersQueue::new())),
+            Arc::new(Condvar::new()),
+            Arc::new(Condvar::new()),
+            Arc::new(Condvar::new()),
+            Arc::new(resources),
+            Arc::new(levels),
+            counters.clone(),
+            Arc::new(Mutex::new(LatencyHistograms::new())),
+            Arc::new(Journal::disabled()),
+            Arc::new(IngredientOrder::new(IngredientOrdering::ScarcestFirst)),
+            Arc::new(AdmissionControl::disabled()),
+            ConsumePath::LockFree,
+            Arc::new(VirtualClock::new()),
+        );
+
+        let result = dispenser.process_order(Order::new(
+            1,
+            vec![(Ingredient::HotWater, 100), (Ingredient::Cacao, 100)],
+        ));
+
+        assert_eq!(Ok(false), result);
+        let container = hot_water.lock().expect("Error in hot water lock in test");
+        assert_eq!(A_WATER_STORAGE, container.remaining());
+        assert_eq!(0, container.consumed());
+    }
+
+    #[test]
+    fn should_keep_the_order_in_preparation_on_the_snapshot() {
+        let mut resources = HashMap::with_capacity(TOTAL_INGREDIENTS);
+        let hot_water = Arc::new(Mutex::new(Container::new(A_WATER_STORAGE)));
+        resources.insert(Ingredient::HotWater, hot_water.clone());
+        resources.insert(
+            Ingredient::Cacao,
+            Arc::new(Mutex::new(Container::new(C_CACAO_STORAGE))),
+        );
+        let resources = Arc::new(resources);
+        let orders_queue = Arc::new(Mutex::new(OrdersQueue::new()));
+        let counters = Arc::new(OrderCounters::new());
+        let dispenser = Arc::new(Dispenser::new(
+            1,
+            orders_queue.clone(),
+            Arc::new(Condvar::new()),
+            Arc::new(Condvar::new()),
+            Arc::new(Condvar::new()),
+            resources.clone(),
+            Arc::new(levels_of(&resources).expect("Error in test")),
+            counters.clone(),
+            Arc::new(Mutex::new(LatencyHistograms::new())),
+            Arc::new(Journal::disabled()),
+            Arc::new(IngredientOrder::new(IngredientOrdering::Canonical)),
+            Arc::new(AdmissionControl::disabled()),
+            ConsumePath::LockFree,
+            Arc::new(VirtualClock::new()),
+        ));
+
+        let order = Order::new(
+            3,
+            vec![(Ingredient::HotWater, 100), (Ingredient::Cacao, 50)],
+        );
+        dispenser.start_order(&order).expect("Error in test");
+        assert_eq!(
+            Ok(true),
+            dispenser.take_ingredient(3, Ingredient::HotWater, 100)
+        );
+        let snapshot =
+            CoffeeMakerSnapshot::take(&resources, &orders_queue, &[dispenser], &counters)
+                .expect("Error in test");
+
+        assert!(snapshot.pending_orders.is_empty());
+        assert_eq!(1, snapshot.in_flight_orders.len());
+        let in_flight = &snapshot.in_flight_orders[0];
+        assert_eq!(3, in_flight.order.id);
+        assert_eq!(order.ingredients, in_flight.order.ingredients);
+        assert_eq!(vec![(Ingredient::HotWater, 100)], in_flight.used);
+        let water = snapshot
+            .containers
+            .iter()
+            .find(|container| container.ingredient == Ingredient::HotWater)
+            .expect("Error in test");
+        assert_eq!(100, water.consumed);
     }
 }
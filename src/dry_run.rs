@@ -0,0 +1,424 @@
+//! Analisis de factibilidad de un archivo de pedidos. Simula el consumo de los pedidos contra las capacidades
+//! y los reponedores configurados, sin hilos ni esperas, para saber de antemano si alcanza el stock
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+};
+
+use crate::{
+    admission::{supplies, Supply},
+    constants::MINIMUM_WAIT_TIME_REPLENISHER,
+    container::ContainerLevel,
+    errors::CoffeeMakerError,
+    ingredient_order::{IngredientOrder, IngredientOrdering},
+    latency::{Histogram, Percentiles},
+    order::{Ingredient, ALL_INGREDIENTS},
+    order_source::OrderIngredients,
+    scheduling::{Availability, Candidate, DispatchHistory, SchedulingPolicy},
+};
+
+/// Momento en el que un ingrediente no alcanzo por primera vez para un pedido
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depletion {
+    pub ingredient: Ingredient,
+    /// Primer pedido que se salteo por falta del ingrediente
+    pub order: usize,
+    /// Pedidos servidos hasta ese momento
+    pub orders_served: u64,
+    /// Tiempo estimado desde el inicio, con los dispensers trabajando en paralelo
+    pub elapsed_ms: u64,
+}
+
+/// Resultado de simular los pedidos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// Politica con la que se tomaron los pedidos
+    pub scheduling: SchedulingPolicy,
+    pub orders_served: u64,
+    pub orders_skipped: u64,
+    /// Ingredientes que se agotaron, en el orden en el que se agotaron
+    pub depletions: Vec<Depletion>,
+    /// Lo que queda en cada contenedor al final
+    pub containers: Vec<ContainerLevel>,
+    /// Tiempo estimado hasta terminar todos los pedidos
+    pub elapsed_ms: u64,
+    /// Tiempo estimado que espera cada pedido hasta que lo toma un dispenser, en microsegundos como los
+    /// histogramas de latencia de la cafetera
+    pub queue_wait: Percentiles,
+}
+
+impl DryRunReport {
+    pub fn print(&self) {
+        println!(
+            "[DRY RUN] Orders served={} skipped={} | Estimated time={}ms",
+            self.orders_served, self.orders_skipped, self.elapsed_ms
+        );
+        match self.depletions.first() {
+            Some(first) => println!(
+                "[DRY RUN] {:?} runs out first, at order {} after serving {} orders (around {}ms)",
+                first.ingredient, first.order, first.orders_served, first.elapsed_ms
+            ),
+            None => println!("[DRY RUN] The stock is enough for all the orders"),
+        }
+        for depletion in self.depletions.iter().skip(1) {
+            println!(
+                "[DRY RUN] {:?} runs out at order {} after serving {} orders (around {}ms)",
+                depletion.ingredient,
+                depletion.order,
+                depletion.orders_served,
+                depletion.elapsed_ms
+            );
+        }
+        let mut remaining = String::from("[DRY RUN] Remaining |");
+        for container in &self.containers {
+            remaining.push_str(&format!(
+                " {:?}={}/{} ",
+                container.ingredient, container.remaining, container.capacity
+            ));
+        }
+        println!("{}", remaining);
+        print_queue_wait(&self.queue_wait);
+    }
+
+    /// Imprime una linea por politica para comparar los resultados de simular los mismos pedidos
+    pub fn print_comparison(reports: &[DryRunReport]) {
+        for report in reports {
+            let first_depletion = report
+                .depletions
+                .first()
+                .map(|depletion| format!("{:?} at order {}", depletion.ingredient, depletion.order))
+                .unwrap_or_else(|| String::from("none"));
+            println!(
+                "[DRY RUN] {:?} | Orders served={} skipped={} | First depletion={} | Estimated time={}ms",
+                report.scheduling,
+                report.orders_served,
+                report.orders_skipped,
+                first_depletion,
+                report.elapsed_ms
+            );
+            print_queue_wait(&report.queue_wait);
+        }
+    }
+}
+
+fn print_queue_wait(queue_wait: &Percentiles) {
+    println!(
+        "[DRY RUN] Queue wait (us) | count={} p50={} p90={} p99={} max={}",
+        queue_wait.count, queue_wait.p50, queue_wait.p90, queue_wait.p99, queue_wait.max
+    );
+}
+
+#[derive(Debug, Clone)]
+struct SimulatedContainer {
+    remaining: u64,
+    capacity: u64,
+    supply: Option<Supply>,
+    /// Igual que en `Container`, indica que ya no se puede reponer porque se agoto la fuente
+    finished: bool,
+    /// Mientras se repone el contenedor queda tomado, y ningun dispenser lo puede usar hasta este momento
+    available_at: u64,
+}
+
+/// Estado de la simulacion
+struct Simulation {
+    containers: HashMap<Ingredient, SimulatedContainer>,
+    ingredient_order: IngredientOrder,
+    /// Momento en el que se libera cada dispenser
+    dispensers: BinaryHeap<Reverse<u64>>,
+    queue_wait: Histogram,
+    report: DryRunReport,
+}
+
+/// Simula `orders` como si los tomara el primer dispenser que se libera, en el orden que indica `scheduling`.
+/// Todos los pedidos estan en la cola desde el inicio, cada uno con su cliente.
+/// Los contenedores empiezan llenos. Igual que en la cafetera, un contenedor se repone cuando no alcanza para el
+/// ingrediente del pedido, y si el pedido se saltea lo que ya se habia usado se pierde. El tiempo es estimado:
+/// cada ingrediente tarda lo mismo que su cantidad en milisegundos y cada reposicion lo mismo que en el reponedor,
+/// durante la cual ningun dispenser puede usar el contenedor
+pub fn simulate(
+    capacities: &HashMap<Ingredient, u64>,
+    container_replenishers: &[(Ingredient, Ingredient)],
+    external_replenishers: &[Ingredient],
+    dispensers: usize,
+    ingredient_ordering: IngredientOrdering,
+    scheduling: SchedulingPolicy,
+    orders: Vec<(String, OrderIngredients)>,
+) -> Result<DryRunReport, CoffeeMakerError> {
+    let supplies = supplies(container_replenishers, external_replenishers);
+    let containers = capacities
+        .iter()
+        .map(|(ingredient, capacity)| {
+            (
+                *ingredient,
+                SimulatedContainer {
+                    remaining: *capacity,
+                    capacity: *capacity,
+                    supply: supplies.get(ingredient).copied(),
+                    finished: false,
+                    available_at: 0,
+                },
+            )
+        })
+        .collect();
+    let mut simulation = Simulation {
+        containers,
+        ingredient_order: IngredientOrder::new(ingredient_ordering),
+        dispensers: (0..dispensers.max(1)).map(|_| Reverse(0)).collect(),
+        queue_wait: Histogram::new(),
+        report: DryRunReport {
+            scheduling,
+            orders_served: 0,
+            orders_skipped: 0,
+            depletions: Vec::new(),
+            containers: Vec::new(),
+            elapsed_ms: 0,
+            queue_wait: Percentiles::from_histogram(&Histogram::new()),
+        },
+    };
+    let mut pending: VecDeque<(usize, (String, OrderIngredients))> =
+        orders.into_iter().enumerate().collect();
+    let mut history = DispatchHistory::default();
+    while !pending.is_empty() {
+        let index = scheduling.select(
+            pending
+                .iter()
+                .map(|(_, (customer, ingredients))| Candidate {
+                    customer,
+                    ingredients,
+                }),
+            &mut history,
+            || simulation.availability(),
+        );
+        if let Some((id, (_, ingredients))) = pending.remove(index) {
+            simulation.process_order(id, ingredients)?;
+        }
+    }
+    Ok(simulation.finish())
+}
+
+impl Simulation {
+    fn process_order(
+        &mut self,
+        id: usize,
+        mut ingredients: OrderIngredients,
+    ) -> Result<(), CoffeeMakerError> {
+        let Reverse(start) = self.dispensers.pop().unwrap_or(Reverse(0));
+        self.queue_wait.record(start * 1000);
+        let levels = self.levels();
+        self.ingredient_order
+            .sort_with(id, &mut ingredients, || Ok(levels))?;
+        let mut now = start;
+        let mut skipped_for = None;
+        for (ingredient, quantity) in ingredients {
+            now = self.replenish_until(ingredient, quantity, now)?;
+            let container = self.container(ingredient)?;
+            if container.remaining < quantity {
+                skipped_for = Some(ingredient);
+                break;
+            }
+            container.remaining -= quantity;
+            now += quantity;
+        }
+        match skipped_for {
+            Some(ingredient) => {
+                self.report.orders_skipped += 1;
+                if !self
+                    .report
+                    .depletions
+                    .iter()
+                    .any(|depletion| depletion.ingredient == ingredient)
+                {
+                    self.report.depletions.push(Depletion {
+                        ingredient,
+                        order: id,
+                        orders_served: self.report.orders_served,
+                        elapsed_ms: now,
+                    });
+                }
+            }
+            None => self.report.orders_served += 1,
+        }
+        self.report.elapsed_ms = self.report.elapsed_ms.max(now);
+        self.dispensers.push(Reverse(now));
+        Ok(())
+    }
+
+    /// Repone el contenedor mientras no alcance para `quantity` y se pueda reponer.
+    /// Devuelve el momento en el que el dispenser puede usar el contenedor, a partir de `now`
+    fn replenish_until(
+        &mut self,
+        ingredient: Ingredient,
+        quantity: u64,
+        now: u64,
+    ) -> Result<u64, CoffeeMakerError> {
+        loop {
+            let container = self.container(ingredient)?;
+            let now = now.max(container.available_at);
+            if container.remaining >= quantity || container.finished {
+                return Ok(now);
+            }
+            let missing = container.capacity - container.remaining;
+            let replenished = match container.supply {
+                None => return Ok(now),
+                Some(Supply::External) => missing,
+                Some(Supply::Container(source)) => {
+                    let source = self.container(source)?;
+                    let transferred = source.remaining.min(missing);
+                    source.remaining -= transferred;
+                    let source_is_empty = source.remaining == 0;
+                    self.container(ingredient)?.finished = source_is_empty;
+                    transferred
+                }
+            };
+            if replenished == 0 {
+                // Un contenedor que no llega a tener `quantity` ni lleno nunca va a alcanzar
+                return Ok(now);
+            }
+            let container = self.container(ingredient)?;
+            container.remaining += replenished;
+            container.available_at = now + MINIMUM_WAIT_TIME_REPLENISHER + replenished;
+        }
+    }
+
+    fn container(
+        &mut self,
+        ingredient: Ingredient,
+    ) -> Result<&mut SimulatedContainer, CoffeeMakerError> {
+        self.containers
+            .get_mut(&ingredient)
+            .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))
+    }
+
+    /// Igual que `Stock::availability`, sobre los contenedores simulados
+    fn availability(&self) -> Availability {
+        self.containers
+            .iter()
+            .filter_map(|(ingredient, container)| {
+                let available = match container.supply {
+                    None => container.remaining,
+                    Some(Supply::External) => return None,
+                    Some(Supply::Container(_)) if container.finished => container.remaining,
+                    Some(Supply::Container(source)) => {
+                        container.remaining + self.containers.get(&source)?.remaining
+                    }
+                };
+                Some((*ingredient, available))
+            })
+            .collect()
+    }
+
+    fn levels(&self) -> Vec<ContainerLevel> {
+        ALL_INGREDIENTS
+            .into_iter()
+            .filter_map(|ingredient| {
+                let container = self.containers.get(&ingredient)?;
+                Some(ContainerLevel {
+                    ingredient,
+                    remaining: container.remaining,
+                    capacity: container.capacity,
+                })
+            })
+            .collect()
+    }
+
+    fn finish(mut self) -> DryRunReport {
+        self.report.containers = self.levels();
+        self.report.queue_wait = Percentiles::from_histogram(&self.queue_wait);
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_simulation(orders: Vec<OrderIngredients>) -> DryRunReport {
+        simulation_with(SchedulingPolicy::Fifo, orders)
+    }
+
+    fn simulation_with(
+        scheduling: SchedulingPolicy,
+        orders: Vec<OrderIngredients>,
+    ) -> DryRunReport {
+        let orders = orders
+            .into_iter()
+            .map(|ingredients| (String::new(), ingredients))
+            .collect();
+        let capacities = ALL_INGREDIENTS
+            .into_iter()
+            .map(|ingredient| (ingredient, 100))
+            .collect();
+        simulate(
+            &capacities,
+            &[(Ingredient::GrainsToGrind, Ingredient::GroundCoffee)],
+            &[Ingredient::HotWater],
+            2,
+            IngredientOrdering::Canonical,
+            scheduling,
+            orders,
+        )
+        .expect("Error in test")
+    }
+
+    #[test]
+    fn should_serve_every_order_if_the_stock_is_enough() {
+        let report = default_simulation(vec![vec![(Ingredient::Cacao, 50)]; 2]);
+
+        assert_eq!(2, report.orders_served);
+        assert_eq!(0, report.orders_skipped);
+        assert!(report.depletions.is_empty());
+        assert_eq!(50, report.elapsed_ms);
+    }
+
+    #[test]
+    fn should_find_the_first_ingredient_that_runs_out() {
+        let order = vec![(Ingredient::GroundCoffee, 40), (Ingredient::Cacao, 30)];
+        let report = default_simulation(vec![order; 6]);
+
+        assert_eq!(3, report.orders_served);
+        assert_eq!(3, report.orders_skipped);
+        let first = &report.depletions[0];
+        assert_eq!(Ingredient::Cacao, first.ingredient);
+        assert_eq!(3, first.order);
+        assert_eq!(3, first.orders_served);
+        // Los pedidos salteados igual usaron cafe, que se repuso una vez con los granos y despues se agoto
+        assert_eq!(Ingredient::GroundCoffee, report.depletions[1].ingredient);
+        assert_eq!(5, report.depletions[1].order);
+    }
+
+    #[test]
+    fn should_never_run_out_of_an_external_ingredient() {
+        let report = default_simulation(vec![vec![(Ingredient::HotWater, 90)]; 20]);
+
+        assert_eq!(20, report.orders_served);
+        assert!(report.depletions.is_empty());
+    }
+
+    #[test]
+    fn should_serve_more_orders_when_the_small_ones_go_first() {
+        let mut orders = vec![vec![(Ingredient::Cacao, 100)]];
+        orders.extend(vec![vec![(Ingredient::Cacao, 10)]; 5]);
+
+        let fifo = simulation_with(SchedulingPolicy::Fifo, orders.clone());
+        let inventory_aware = simulation_with(SchedulingPolicy::InventoryAware, orders);
+
+        assert_eq!(1, fifo.orders_served);
+        assert_eq!(5, inventory_aware.orders_served);
+        assert_eq!(1, inventory_aware.orders_skipped);
+        // El pedido grande llego primero pero se toma al final
+        assert_eq!(0, inventory_aware.depletions[0].order);
+    }
+
+    #[test]
+    fn should_wait_less_in_the_queue_when_the_short_orders_go_first() {
+        let mut orders = vec![vec![(Ingredient::HotWater, 90)]; 4];
+        orders.extend(vec![vec![(Ingredient::HotWater, 1)]; 12]);
+
+        let fifo = simulation_with(SchedulingPolicy::Fifo, orders.clone());
+        let shortest_first = simulation_with(SchedulingPolicy::ShortestJobFirst, orders);
+
+        assert_eq!(16, shortest_first.queue_wait.count);
+        assert_eq!(fifo.orders_served, shortest_first.orders_served);
+        assert!(shortest_first.queue_wait.p50 < fifo.queue_wait.p50);
+    }
+}
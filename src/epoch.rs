@@ -0,0 +1,114 @@
+//! Epoca de las transferencias entre contenedores. Permite leer el estado de todos los contenedores
+//! de forma consistente sin tomar todos sus locks a la vez.
+use std::sync::{Condvar, Mutex};
+
+use crate::errors::CoffeeMakerError;
+
+struct EpochState {
+    in_flight: usize,
+    version: u64,
+}
+
+/// Lleva la cuenta de las transferencias entre contenedores en curso y de las que ya terminaron.
+/// Un `ContainerReplenisher` descuenta de la fuente y recien despues suma al destino, entre ambos
+/// pasos el recurso no esta en ningun contenedor. Una lectura es consistente si al empezar y al terminar
+/// no habia transferencias en curso y no termino ninguna en el medio.
+pub struct TransferEpoch {
+    state: Mutex<EpochState>,
+    idle: Condvar,
+}
+
+impl TransferEpoch {
+    pub fn new() -> TransferEpoch {
+        TransferEpoch {
+            state: Mutex::new(EpochState {
+                in_flight: 0,
+                version: 0,
+            }),
+            idle: Condvar::new(),
+        }
+    }
+
+    /// Indica que empieza una transferencia entre contenedores
+    pub fn begin(&self) -> Result<(), CoffeeMakerError> {
+        self.state.lock()?.in_flight += 1;
+        Ok(())
+    }
+
+    /// Indica que termino una transferencia, el recurso ya esta en el contenedor destino
+    pub fn end(&self) -> Result<(), CoffeeMakerError> {
+        let mut state = self.state.lock()?;
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.version += 1;
+        if state.in_flight == 0 {
+            self.idle.notify_all();
+        }
+        Ok(())
+    }
+
+    /// Ejecuta `read` hasta que no haya habido ninguna transferencia mientras se ejecutaba.
+    /// `read` no debe empezar transferencias, y puede tomar los locks de los contenedores de a uno.
+    pub fn read<T>(
+        &self,
+        mut read: impl FnMut() -> Result<T, CoffeeMakerError>,
+    ) -> Result<T, CoffeeMakerError> {
+        loop {
+            let started_at = self.wait_for_idle()?;
+            let value = read()?;
+            let state = self.state.lock()?;
+            if state.in_flight == 0 && state.version == started_at {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn wait_for_idle(&self) -> Result<u64, CoffeeMakerError> {
+        let state = self
+            .idle
+            .wait_while(self.state.lock()?, |state| state.in_flight > 0)?;
+        Ok(state.version)
+    }
+}
+
+impl Default for TransferEpoch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn should_never_read_a_transfer_halfway() {
+        let epoch = Arc::new(TransferEpoch::new());
+        let source = Arc::new(Mutex::new(1000_u64));
+        let dest = Arc::new(Mutex::new(0_u64));
+
+        let (epoch_clone, source_clone, dest_clone) = (epoch.clone(), source.clone(), dest.clone());
+        let transfers = thread::spawn(move || {
+            for _ in 0..1000 {
+                epoch_clone.begin().expect("Error in test");
+                *source_clone.lock().expect("Error in test") -= 1;
+                thread::yield_now();
+                *dest_clone.lock().expect("Error in test") += 1;
+                epoch_clone.end().expect("Error in test");
+            }
+        });
+
+        for _ in 0..200 {
+            let total = epoch
+                .read(|| Ok(*source.lock()? + *dest.lock()?))
+                .expect("Error in test");
+            assert_eq!(1000, total);
+        }
+        transfers.join().expect("Error in test");
+        assert_eq!(1000, *dest.lock().expect("Error in test"));
+    }
+}
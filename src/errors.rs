@@ -1,17 +1,247 @@
 //! Codigos de error de la cafetera
-#[derive(Debug, PartialEq, Eq)]
+use std::{error::Error, fmt};
+
+use crate::{admission::Rejection, order::Ingredient};
+
+/// Codigo de salida cuando los argumentos de la linea de comandos no son validos
+pub const USAGE_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CoffeeMakerError {
-    /// Indica que no se encontro un ingrediente requerido en el mapa de recursos
-    IngredientNotInMap,
+    /// Indica que no se encontro el contenedor del ingrediente en el mapa de recursos
+    IngredientNotInMap(Ingredient),
 
     /// Indica que ocurrio un error con un lock. Se puede dar en caso de que este envenenado
     LockError,
 
-    /// Indica que al buscar un pedido en la cola se encontro con que estaba vacia cuando no deberia
-    EmptyQueueWhenNotExpected,
+    /// Indica que al buscar un pedido en la cola el dispenser se encontro con que estaba vacia cuando no deberia
+    EmptyQueueWhenNotExpected { dispenser: usize },
+
+    /// No existe el archivo de pedidos indicado
+    FileNotFound { path: String },
+
+    /// No se pudo abrir, leer o escribir un archivo o un stream de pedidos
+    FileReaderError { path: String, reason: String },
+
+    /// Los pedidos no tienen el formato esperado. La linea y columna son las que indica serde
+    MalformedOrders {
+        source: String,
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+
+    /// Se intento agregar un pedido cuando la cola ya no acepta mas
+    QueueClosed,
+
+    /// Un pedido recibido no tiene el formato esperado
+    OrderFormatError { reason: String },
+
+    /// Ocurrio un error con el socket de control de la cafetera
+    SocketError { path: String, reason: String },
+
+    /// No se pudo guardar o leer la foto del estado de la cafetera
+    SnapshotError { path: String, reason: String },
+
+    /// No se pudo escribir, leer o reconstruir el journal de eventos
+    JournalError { path: String, reason: String },
+
+    /// La estrategia de orden de los ingredientes indicada no es valida
+    InvalidIngredientOrdering(String),
+
+    /// El tipo de reloj indicado no es valido
+    InvalidClock(String),
+
+    /// El motor indicado no es valido
+    InvalidEngine(String),
+
+    /// La politica de orden de los pedidos indicada no es valida
+    InvalidSchedulingPolicy(String),
+
+    /// Un actor del motor por canales dejo de responder
+    ChannelClosed,
+
+    /// No se pudo iniciar el runtime del motor asincronico
+    RuntimeError(String),
+
+    /// La forma de consumir los ingredientes indicada no es valida
+    InvalidConsumePath(String),
+
+    /// La configuracion de la cafetera no es valida. Por ejemplo, no tiene dispensers o un contenedor tiene dos reponedores
+    InvalidConfiguration(String),
+
+    /// El modo de depuracion encontro que la cafetera creo o perdio ingredientes o pedidos
+    InvariantViolation,
+
+    /// Un lock quedo envenenado y el estado que protege no es valido, por lo que no se puede recuperar
+    CorruptedState { state: &'static str, reason: String },
 
-    /// Ocurrio un error en la lectura del archivo, ya sea porque no existe o tiene un formato equivocado
-    FileReaderError,
+    /// Error de un dispenser, junto con su id
+    Dispenser {
+        id: usize,
+        source: Box<CoffeeMakerError>,
+    },
+
+    /// Un hilo supervisado se cayo mas veces que su presupuesto de reinicios
+    WorkerGaveUp { worker: String, cause: String },
+
+    /// El control de admision rechazo el pedido porque nunca va a alcanzar alguno de sus ingredientes
+    OrderRejected { order: usize, rejection: Rejection },
+}
+
+impl CoffeeMakerError {
+    /// Codigo de salida del proceso segun la clase del error:
+    /// * 2: argumentos invalidos (ver `USAGE_EXIT_CODE`)
+    /// * 3: no existe el archivo de pedidos
+    /// * 4: pedidos con formato invalido
+    /// * 5: configuracion invalida
+    /// * 6: error de entrada/salida (archivos, sockets, fotos o journal)
+    /// * 7: error interno de los hilos (locks, canales, dispensers o reponedores)
+    /// * 8: se violo una invariante o un estado quedo corrupto
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CoffeeMakerError::InvalidIngredientOrdering(_)
+            | CoffeeMakerError::InvalidClock(_)
+            | CoffeeMakerError::InvalidEngine(_)
+            | CoffeeMakerError::InvalidSchedulingPolicy(_)
+            | CoffeeMakerError::InvalidConsumePath(_) => USAGE_EXIT_CODE,
+            CoffeeMakerError::FileNotFound { .. } => 3,
+            CoffeeMakerError::MalformedOrders { .. }
+            | CoffeeMakerError::OrderFormatError { .. }
+            | CoffeeMakerError::OrderRejected { .. } => 4,
+            CoffeeMakerError::InvalidConfiguration(_) => 5,
+            CoffeeMakerError::FileReaderError { .. }
+            | CoffeeMakerError::SocketError { .. }
+            | CoffeeMakerError::SnapshotError { .. }
+            | CoffeeMakerError::JournalError { .. } => 6,
+            CoffeeMakerError::IngredientNotInMap(_)
+            | CoffeeMakerError::LockError
+            | CoffeeMakerError::EmptyQueueWhenNotExpected { .. }
+            | CoffeeMakerError::QueueClosed
+            | CoffeeMakerError::ChannelClosed
+            | CoffeeMakerError::RuntimeError(_)
+            | CoffeeMakerError::WorkerGaveUp { .. } => 7,
+            CoffeeMakerError::InvariantViolation | CoffeeMakerError::CorruptedState { .. } => 8,
+            CoffeeMakerError::Dispenser { source, .. } => source.exit_code(),
+        }
+    }
+
+    /// Arma el error de un archivo o stream con pedidos que serde no pudo leer
+    pub(crate) fn malformed_orders(source: &str, err: &serde_json::Error) -> CoffeeMakerError {
+        let reason = err.to_string();
+        // serde agrega la posicion al final del mensaje, que ya queda en line y column
+        let reason = match reason.rfind(" at line ") {
+            Some(position) => reason[..position].to_string(),
+            None => reason,
+        };
+        CoffeeMakerError::MalformedOrders {
+            source: source.to_string(),
+            line: err.line(),
+            column: err.column(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for CoffeeMakerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoffeeMakerError::IngredientNotInMap(ingredient) => {
+                write!(f, "there is no {:?} container", ingredient)
+            }
+            CoffeeMakerError::LockError => write!(f, "a lock was poisoned"),
+            CoffeeMakerError::EmptyQueueWhenNotExpected { dispenser } => write!(
+                f,
+                "dispenser {} found the orders queue empty when it should not",
+                dispenser
+            ),
+            CoffeeMakerError::FileNotFound { path } => write!(f, "{} does not exist", path),
+            CoffeeMakerError::FileReaderError { path, reason } => {
+                write!(f, "could not read or write {}: {}", path, reason)
+            }
+            CoffeeMakerError::MalformedOrders {
+                source,
+                line,
+                column,
+                reason,
+            } => write!(
+                f,
+                "invalid orders in {} at line {}, column {}: {}",
+                source, line, column, reason
+            ),
+            CoffeeMakerError::QueueClosed => write!(f, "the orders queue is closed"),
+            CoffeeMakerError::OrderFormatError { reason } => write!(f, "invalid order: {}", reason),
+            CoffeeMakerError::SocketError { path, reason } => {
+                write!(f, "socket error on {}: {}", path, reason)
+            }
+            CoffeeMakerError::SnapshotError { path, reason } => {
+                write!(f, "could not save or load the snapshot {}: {}", path, reason)
+            }
+            CoffeeMakerError::JournalError { path, reason } => {
+                write!(f, "could not write, read or replay the journal {}: {}", path, reason)
+            }
+            CoffeeMakerError::InvalidIngredientOrdering(value) => write!(
+                f,
+                "invalid ingredient ordering {:?}, expected random, seeded:<seed>, canonical or scarcest",
+                value
+            ),
+            CoffeeMakerError::InvalidClock(value) => write!(
+                f,
+                "invalid clock {:?}, expected real, scaled:<factor> or virtual",
+                value
+            ),
+            CoffeeMakerError::InvalidEngine(value) => write!(
+                f,
+                "invalid engine {:?}, expected shared, actors or async",
+                value
+            ),
+            CoffeeMakerError::InvalidSchedulingPolicy(value) => write!(
+                f,
+                "invalid scheduling policy {:?}, expected fifo, inventory-aware, sjf or fair-share",
+                value
+            ),
+            CoffeeMakerError::ChannelClosed => {
+                write!(f, "an actor of the channels engine stopped responding")
+            }
+            CoffeeMakerError::RuntimeError(reason) => {
+                write!(f, "could not start the async runtime: {}", reason)
+            }
+            CoffeeMakerError::InvalidConsumePath(value) => write!(
+                f,
+                "invalid consume path {:?}, expected locked or lock-free",
+                value
+            ),
+            CoffeeMakerError::InvalidConfiguration(reason) => {
+                write!(f, "invalid configuration: {}", reason)
+            }
+            CoffeeMakerError::InvariantViolation => {
+                write!(f, "the coffee maker created or lost ingredients or orders")
+            }
+            CoffeeMakerError::CorruptedState { state, reason } => write!(
+                f,
+                "the poisoned {} lock can not be recovered: {}",
+                state, reason
+            ),
+            CoffeeMakerError::Dispenser { id, source } => write!(f, "dispenser {}: {}", id, source),
+            CoffeeMakerError::OrderRejected { order, rejection } => {
+                write!(f, "order {} was rejected, it {}", order, rejection)
+            }
+            CoffeeMakerError::WorkerGaveUp { worker, cause } => write!(
+                f,
+                "{} ran out of restarts, last crash: {}",
+                worker, cause
+            ),
+        }
+    }
+}
+
+impl Error for CoffeeMakerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CoffeeMakerError::Dispenser { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
 }
 
 impl<T> From<std::sync::PoisonError<T>> for CoffeeMakerError {
@@ -19,3 +249,41 @@ impl<T> From<std::sync::PoisonError<T>> for CoffeeMakerError {
         CoffeeMakerError::LockError
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_the_position_of_a_malformed_orders_file() {
+        let err = serde_json::from_str::<serde_json::Value>("{\n  \"orders\": [,]\n}")
+            .expect_err("Fail test");
+        let error = CoffeeMakerError::malformed_orders("orders.json", &err);
+
+        assert_eq!(
+            CoffeeMakerError::MalformedOrders {
+                source: String::from("orders.json"),
+                line: 2,
+                column: 14,
+                reason: String::from("expected value"),
+            },
+            error
+        );
+        assert_eq!(4, error.exit_code());
+    }
+
+    #[test]
+    fn should_show_the_dispenser_and_its_cause() {
+        let error = CoffeeMakerError::Dispenser {
+            id: 3,
+            source: Box::new(CoffeeMakerError::IngredientNotInMap(Ingredient::Cacao)),
+        };
+
+        assert_eq!(
+            "dispenser 3: there is no Cacao container",
+            error.to_string()
+        );
+        assert!(error.source().is_some());
+        assert_eq!(7, error.exit_code());
+    }
+}
@@ -1,33 +1,22 @@
 //! Reponedor de la cafetera a partir de una fuente externa. Por ejemplo el agua.
 use std::{
-    sync::{Arc, Condvar, Mutex},
+    sync::{Arc, Condvar, Mutex, RwLock},
     time::Duration,
 };
 
 use log::{error, info};
 
 use crate::{
+    clock::Clock,
     constants::{MAX_OF_INGREDIENT_IN_AN_ORDER, MINIMUM_WAIT_TIME_REPLENISHER},
     container::Container,
     errors::CoffeeMakerError,
+    journal::{Journal, JournalEvent},
     order::Ingredient,
+    poison::{LockOrRecover, WaitOrRecover},
+    supervisor::Worker,
 };
 
-mod sync {
-    use std::thread;
-    use std::time::Duration;
-
-    #[cfg(not(test))]
-    pub(crate) fn sleep(d: Duration) {
-        thread::sleep(d);
-    }
-
-    #[cfg(test)]
-    pub(crate) fn sleep(_: Duration) {
-        thread::yield_now();
-    }
-}
-
 /// Representa a un reponedor de un contenedor a partir de una fuente externa. Esta fuente no se agota
 pub struct ExternalReplenisher {
     ingredient: Ingredient,
@@ -35,6 +24,9 @@ pub struct ExternalReplenisher {
     replenisher_cond: Arc<Condvar>,
     ingredients_cond: Arc<Condvar>,
     max_storage_of_container: u64,
+    replenishing: RwLock<bool>,
+    journal: Arc<Journal>,
+    clock: Arc<dyn Clock>,
 }
 
 impl ExternalReplenisher {
@@ -43,6 +35,8 @@ impl ExternalReplenisher {
         replenisher_cond: Arc<Condvar>,
         ingredients_cond: Arc<Condvar>,
         max_storage_of_container: u64,
+        journal: Arc<Journal>,
+        clock: Arc<dyn Clock>,
     ) -> ExternalReplenisher {
         let (ingredient, container_lock) = container;
         ExternalReplenisher {
@@ -51,12 +45,24 @@ impl ExternalReplenisher {
             replenisher_cond,
             ingredients_cond,
             max_storage_of_container,
+            replenishing: RwLock::new(false),
+            journal,
+            clock,
         }
     }
 
+    pub fn ingredient(&self) -> Ingredient {
+        self.ingredient
+    }
+
+    /// Indica si el reponedor se encuentra recargando su contenedor en este momento
+    pub fn is_replenishing(&self) -> Result<bool, CoffeeMakerError> {
+        Ok(*self.replenishing.read()?)
+    }
+
     pub fn finish(&self) {
-        if let Ok(mut container) = self.container_lock.lock() {
-            container.finished = true;
+        if let Ok(mut container) = self.container_lock.lock_or_recover() {
+            container.shutdown = true;
             self.replenisher_cond.notify_all();
             return;
         }
@@ -65,25 +71,35 @@ impl ExternalReplenisher {
 
     pub fn replenish_container(&self) -> Result<(), CoffeeMakerError> {
         loop {
-            let mut container = self
-                .replenisher_cond
-                .wait_while(self.container_lock.lock()?, |container| {
-                    container.remaining > MAX_OF_INGREDIENT_IN_AN_ORDER && !container.finished
-                })
-                .map_err(|_| CoffeeMakerError::LockError)?;
+            let mut container =
+                self.replenisher_cond
+                    .wait_while_or_recover(&self.container_lock, |container| {
+                        container.remaining() > MAX_OF_INGREDIENT_IN_AN_ORDER && !container.shutdown
+                    })?;
 
-            if container.finished {
+            if container.shutdown {
                 return Ok(());
             }
+            *self.replenishing.write()? = true;
             self.replenish(&mut container);
+            *self.replenishing.write()? = false;
             self.ingredients_cond.notify_all();
         }
     }
 
     fn replenish(&self, container: &mut std::sync::MutexGuard<Container>) {
-        let replenish_quantity = self.max_storage_of_container - container.remaining;
-        container.remaining += replenish_quantity;
-        sync::sleep(Duration::from_millis(
+        let replenish_quantity =
+            container
+                .level
+                .fill_announced(self.max_storage_of_container, |quantity| {
+                    self.journal.record(JournalEvent::Replenished {
+                        ingredient: self.ingredient,
+                        source: None,
+                        quantity,
+                    })
+                });
+        container.supplied += replenish_quantity;
+        self.clock.sleep(Duration::from_millis(
             MINIMUM_WAIT_TIME_REPLENISHER + replenish_quantity,
         ));
         info!(
@@ -93,10 +109,33 @@ impl ExternalReplenisher {
     }
 }
 
+impl Worker for ExternalReplenisher {
+    fn work(&self) -> Result<(), CoffeeMakerError> {
+        self.replenish_container()
+    }
+
+    fn recover(&self) -> Result<Option<usize>, CoffeeMakerError> {
+        *self.replenishing.write()? = false;
+        Ok(None)
+    }
+
+    /// Sin reponedor, los dispensers dejan de esperar a que se recargue el contenedor
+    fn give_up(&self) {
+        if let Ok(mut container) = self.container_lock.lock_or_recover() {
+            container.replenishable = false;
+            self.ingredients_cond.notify_all();
+            return;
+        }
+        error!("Error disabling the replenishment of the container");
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::thread;
 
+    use crate::clock::VirtualClock;
+
     use crate::constants::A_WATER_STORAGE;
 
     use super::*;
@@ -111,22 +150,24 @@ mod tests {
             replenisher_cond.clone(),
             ingredients_cond.clone(),
             A_WATER_STORAGE,
+            Arc::new(Journal::disabled()),
+            Arc::new(VirtualClock::new()),
         ));
         let water_clone = water_replenisher.clone();
         let handle = thread::spawn(move || water_clone.replenish_container());
 
         {
-            let mut container = hot_water.lock().expect("Lock error in test");
-            container.remaining = 0;
+            let container = hot_water.lock().expect("Lock error in test");
+            container.level.set_remaining(0);
         }
         replenisher_cond.notify_all();
         {
             let container = ingredients_cond
                 .wait_while(hot_water.lock().expect("Lock error in test"), |container| {
-                    container.remaining < A_WATER_STORAGE
+                    container.remaining() < A_WATER_STORAGE
                 })
                 .expect("Test error when returning from condvar");
-            assert_eq!(container.remaining, A_WATER_STORAGE);
+            assert_eq!(container.remaining(), A_WATER_STORAGE);
         }
         water_replenisher.finish();
         _ = handle.join().expect("Error when joining thread");
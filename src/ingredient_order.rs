@@ -0,0 +1,255 @@
+//! Estrategias para decidir en que orden un dispenser usa los ingredientes de un pedido
+use std::{str::FromStr, sync::RwLock};
+
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, SeedableRng};
+
+use crate::{
+    container::{get_levels, ContainerLevel, Levels},
+    errors::CoffeeMakerError,
+    order::{Ingredient, ALL_INGREDIENTS},
+};
+
+/// Orden en el que se usan los ingredientes de un pedido
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngredientOrdering {
+    /// Mezcla aleatoria, reparte la contencion entre los contenedores pero no es reproducible
+    Random,
+    /// Mezcla aleatoria a partir de la semilla y el id del pedido. Un mismo pedido siempre se prepara
+    /// en el mismo orden, sin importar que dispenser lo tome ni cuando
+    Seeded(u64),
+    /// Siempre el orden de `ALL_INGREDIENTS`
+    Canonical,
+    /// Primero el ingrediente al que menos le sobra en su contenedor al momento de tomar el pedido
+    /// (lo que queda menos lo que pide el pedido). Si el pedido se va a saltear, se saltea antes de
+    /// consumir el resto de los ingredientes
+    ScarcestFirst,
+}
+
+impl FromStr for IngredientOrdering {
+    type Err = CoffeeMakerError;
+
+    /// Acepta `random`, `seeded:<semilla>`, `canonical` y `scarcest`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "random" => Ok(IngredientOrdering::Random),
+            "canonical" => Ok(IngredientOrdering::Canonical),
+            "scarcest" => Ok(IngredientOrdering::ScarcestFirst),
+            _ => value
+                .strip_prefix("seeded:")
+                .and_then(|seed| seed.parse::<u64>().ok())
+                .map(IngredientOrdering::Seeded)
+                .ok_or_else(|| CoffeeMakerError::InvalidIngredientOrdering(value.to_string())),
+        }
+    }
+}
+
+/// Estrategia de orden de los ingredientes compartida por los dispensers. Se puede cambiar antes de iniciar la cafetera
+pub struct IngredientOrder {
+    ordering: RwLock<IngredientOrdering>,
+}
+
+impl IngredientOrder {
+    pub fn new(ordering: IngredientOrdering) -> IngredientOrder {
+        IngredientOrder {
+            ordering: RwLock::new(ordering),
+        }
+    }
+
+    pub fn set(&self, ordering: IngredientOrdering) -> Result<(), CoffeeMakerError> {
+        *self.ordering.write()? = ordering;
+        Ok(())
+    }
+
+    pub fn get(&self) -> Result<IngredientOrdering, CoffeeMakerError> {
+        Ok(*self.ordering.read()?)
+    }
+
+    /// Ordena los ingredientes del pedido `order_id` segun la estrategia actual
+    pub fn sort(
+        &self,
+        order_id: usize,
+        ingredients: &mut [(Ingredient, u64)],
+        levels: &Levels,
+    ) -> Result<(), CoffeeMakerError> {
+        self.sort_with(order_id, ingredients, || Ok(get_levels(levels)))
+    }
+
+    /// Igual que `sort`, pero el nivel de los contenedores se obtiene con `levels`, solo si la estrategia lo necesita
+    pub fn sort_with(
+        &self,
+        order_id: usize,
+        ingredients: &mut [(Ingredient, u64)],
+        levels: impl FnOnce() -> Result<Vec<ContainerLevel>, CoffeeMakerError>,
+    ) -> Result<(), CoffeeMakerError> {
+        match self.get()? {
+            IngredientOrdering::Random => ingredients.shuffle(&mut thread_rng()),
+            IngredientOrdering::Seeded(seed) => {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(order_id as u64));
+                ingredients.sort_by_key(|(ingredient, _)| canonical_position(ingredient));
+                ingredients.shuffle(&mut rng);
+            }
+            IngredientOrdering::Canonical => {
+                ingredients.sort_by_key(|(ingredient, _)| canonical_position(ingredient));
+            }
+            IngredientOrdering::ScarcestFirst => {
+                let levels = levels()?;
+                let surplus_of = |ingredient: &Ingredient, quantity: u64| {
+                    let remaining = levels
+                        .iter()
+                        .find(|level| level.ingredient == *ingredient)
+                        .map(|level| level.remaining)
+                        .unwrap_or(0);
+                    i128::from(remaining) - i128::from(quantity)
+                };
+                ingredients.sort_by(|(first, first_quantity), (second, second_quantity)| {
+                    surplus_of(first, *first_quantity)
+                        .cmp(&surplus_of(second, *second_quantity))
+                        .then(canonical_position(first).cmp(&canonical_position(second)))
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for IngredientOrder {
+    fn default() -> Self {
+        Self::new(IngredientOrdering::Random)
+    }
+}
+
+fn canonical_position(ingredient: &Ingredient) -> usize {
+    ALL_INGREDIENTS
+        .iter()
+        .position(|candidate| candidate == ingredient)
+        .unwrap_or(ALL_INGREDIENTS.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::container::{levels_of, Container};
+
+    use super::*;
+
+    fn ingredients() -> Vec<(Ingredient, u64)> {
+        vec![
+            (Ingredient::MilkFoam, 1),
+            (Ingredient::Cacao, 2),
+            (Ingredient::GroundCoffee, 3),
+            (Ingredient::HotWater, 4),
+        ]
+    }
+
+    #[test]
+    fn should_parse_the_orderings() {
+        assert_eq!(Ok(IngredientOrdering::Random), "random".parse());
+        assert_eq!(Ok(IngredientOrdering::Seeded(42)), "seeded:42".parse());
+        assert_eq!(Ok(IngredientOrdering::Canonical), "canonical".parse());
+        assert_eq!(Ok(IngredientOrdering::ScarcestFirst), "scarcest".parse());
+        assert_eq!(
+            Err(CoffeeMakerError::InvalidIngredientOrdering(String::from(
+                "seeded:x"
+            ))),
+            "seeded:x".parse::<IngredientOrdering>()
+        );
+    }
+
+    #[test]
+    fn should_sort_in_the_canonical_order() {
+        let order = IngredientOrder::new(IngredientOrdering::Canonical);
+        let mut sorted = ingredients();
+        order
+            .sort(0, &mut sorted, &HashMap::new())
+            .expect("Error in test");
+        let names: Vec<Ingredient> = sorted.iter().map(|(ingredient, _)| *ingredient).collect();
+        assert_eq!(
+            vec![
+                Ingredient::GroundCoffee,
+                Ingredient::HotWater,
+                Ingredient::Cacao,
+                Ingredient::MilkFoam
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn should_shuffle_the_same_order_always_in_the_same_way() {
+        let order = IngredientOrder::new(IngredientOrdering::Seeded(7));
+        let mut first = ingredients();
+        let mut second = ingredients();
+        second.reverse();
+        order
+            .sort(3, &mut first, &HashMap::new())
+            .expect("Error in test");
+        order
+            .sort(3, &mut second, &HashMap::new())
+            .expect("Error in test");
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn should_use_the_scarcest_ingredient_first() {
+        let mut resources = HashMap::new();
+        for (ingredient, remaining, capacity) in [
+            (Ingredient::MilkFoam, 500, 1000),
+            (Ingredient::Cacao, 100, 100),
+            (Ingredient::GroundCoffee, 10, 1000),
+            (Ingredient::HotWater, 300, 1000),
+        ] {
+            let container = Container::new(capacity);
+            container.level.set_remaining(remaining);
+            resources.insert(ingredient, Arc::new(Mutex::new(container)));
+        }
+        let order = IngredientOrder::new(IngredientOrdering::ScarcestFirst);
+        let mut sorted = ingredients();
+        order
+            .sort(
+                0,
+                &mut sorted,
+                &levels_of(&resources).expect("Error in test"),
+            )
+            .expect("Error in test");
+        let names: Vec<Ingredient> = sorted.iter().map(|(ingredient, _)| *ingredient).collect();
+        assert_eq!(
+            vec![
+                Ingredient::GroundCoffee,
+                Ingredient::Cacao,
+                Ingredient::HotWater,
+                Ingredient::MilkFoam
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn should_use_first_the_ingredient_that_does_not_reach_even_if_its_container_is_fuller() {
+        let mut resources = HashMap::new();
+        for (ingredient, remaining, capacity) in [
+            (Ingredient::Cacao, 1900, 2000),
+            (Ingredient::GroundCoffee, 100, 1000),
+        ] {
+            let container = Container::new(capacity);
+            container.level.set_remaining(remaining);
+            resources.insert(ingredient, Arc::new(Mutex::new(container)));
+        }
+        let order = IngredientOrder::new(IngredientOrdering::ScarcestFirst);
+        let mut sorted = vec![(Ingredient::GroundCoffee, 10), (Ingredient::Cacao, 2000)];
+        order
+            .sort(
+                0,
+                &mut sorted,
+                &levels_of(&resources).expect("Error in test"),
+            )
+            .expect("Error in test");
+        assert_eq!(
+            vec![(Ingredient::Cacao, 2000), (Ingredient::GroundCoffee, 10)],
+            sorted
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Modo de depuracion que verifica continuamente que la cafetera no cree ni pierda ingredientes ni pedidos
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info};
+
+use crate::{
+    clock::Clock,
+    constants::INVARIANT_CHECK_INTERVAL_IN_MS,
+    container::Resources,
+    counters::OrderCounters,
+    epoch::TransferEpoch,
+    errors::CoffeeMakerError,
+    order::{Ingredient, ALL_INGREDIENTS},
+    orders_queue::{OrderCounts, OrdersQueue},
+    poison::LockOrRecover,
+};
+
+/// Contadores de un contenedor que intervienen en el balance de su cadena
+#[derive(Debug, Clone, Copy)]
+struct ContainerLedger {
+    remaining: u64,
+    consumed: u64,
+    transferred: u64,
+    wasted: u64,
+    supplied: u64,
+    capacity: u64,
+}
+
+/// Estado de la cafetera leido sin ninguna recarga a medias
+#[derive(Debug, Clone)]
+struct Reading {
+    containers: HashMap<Ingredient, ContainerLedger>,
+    orders_processed: u64,
+    orders: OrderCounts,
+}
+
+/// Verifica las invariantes de conservacion contra el estado que tenia la cafetera al iniciar:
+/// * Por cada cadena de contenedores unidos por reponedores (por ejemplo granos y cafe molido),
+///   lo inicial mas lo cargado desde afuera es igual a lo que queda, mas lo usado en pedidos, mas el desperdicio.
+///   Lo que un reponedor pasa de un contenedor a otro de la cadena no cuenta como usado.
+/// * Los pedidos procesados, salteados y descartados nunca superan a los leidos, y al finalizar son iguales.
+///
+/// Ante una violacion imprime el estado inicial y el actual de cada contenedor y finaliza con un panic.
+pub struct InvariantChecker {
+    chains: Vec<Vec<Ingredient>>,
+    resources: Arc<Resources>,
+    orders_queue: Arc<Mutex<OrdersQueue>>,
+    counters: Arc<OrderCounters>,
+    epoch: Arc<TransferEpoch>,
+    clock: Arc<dyn Clock>,
+    baseline: Mutex<Option<Reading>>,
+    finish: Mutex<bool>,
+}
+
+impl InvariantChecker {
+    /// `replenishers` son los pares (fuente, destino) de los reponedores entre contenedores, definen las cadenas
+    pub fn new(
+        resources: Arc<Resources>,
+        replenishers: &[(Ingredient, Ingredient)],
+        orders_queue: Arc<Mutex<OrdersQueue>>,
+        counters: Arc<OrderCounters>,
+        epoch: Arc<TransferEpoch>,
+        clock: Arc<dyn Clock>,
+    ) -> InvariantChecker {
+        InvariantChecker {
+            chains: build_chains(replenishers),
+            resources,
+            orders_queue,
+            counters,
+            epoch,
+            clock,
+            baseline: Mutex::new(None),
+            finish: Mutex::new(false),
+        }
+    }
+
+    /// Guarda el estado contra el que se comparan las verificaciones. Se debe llamar antes de iniciar
+    /// los lectores y dispensers
+    pub fn start(&self) -> Result<(), CoffeeMakerError> {
+        *self.baseline.lock()? = Some(self.read()?);
+        *self.finish.lock()? = false;
+        Ok(())
+    }
+
+    /// Indica que ya finalizaron los dispensers y los reponedores. La ultima verificacion exige que no falte ningun pedido
+    pub fn finish(&self) {
+        if let Ok(mut finish) = self.finish.lock() {
+            *finish = true;
+            return;
+        }
+        error!("Error setting invariant checker to finish");
+    }
+
+    pub fn check_invariants(&self) -> Result<(), CoffeeMakerError> {
+        loop {
+            let finished = *self.finish.lock()?;
+            if let Some(dump) = self.find_violations(finished)? {
+                error!("{}", dump);
+                panic!("{}", dump);
+            }
+            if finished {
+                info!("[INVARIANT] All the invariants held");
+                return Ok(());
+            }
+            self.clock
+                .wait(Duration::from_millis(INVARIANT_CHECK_INTERVAL_IN_MS));
+        }
+    }
+
+    /// Verifica las invariantes contra el estado inicial. `all_done` indica que ya no queda ningun pedido por procesar.
+    /// Si alguna no se cumple registra el diagnostico y devuelve `InvariantViolation`
+    pub fn verify(&self, all_done: bool) -> Result<(), CoffeeMakerError> {
+        match self.find_violations(all_done)? {
+            Some(dump) => {
+                error!("{}", dump);
+                Err(CoffeeMakerError::InvariantViolation)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Devuelve el diagnostico completo si alguna invariante no se cumple
+    fn find_violations(&self, all_done: bool) -> Result<Option<String>, CoffeeMakerError> {
+        let baseline = self
+            .baseline
+            .lock()?
+            .clone()
+            .ok_or(CoffeeMakerError::InvariantViolation)?;
+        let current = self.read()?;
+        let mut violations = self.check_chains(&baseline, &current);
+        violations.extend(check_orders(&baseline, &current, all_done));
+        if violations.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.dump(&baseline, &current, &violations)))
+    }
+
+    fn read(&self) -> Result<Reading, CoffeeMakerError> {
+        self.epoch.read(|| {
+            let mut containers = HashMap::new();
+            for ingredient in ALL_INGREDIENTS {
+                if let Some(container_lock) = self.resources.get(&ingredient) {
+                    let container = container_lock.lock_or_recover()?;
+                    let (remaining, consumed) = container.level.load();
+                    containers.insert(
+                        ingredient,
+                        ContainerLedger {
+                            remaining,
+                            consumed,
+                            transferred: container.transferred,
+                            wasted: container.wasted,
+                            supplied: container.supplied,
+                            capacity: container.capacity(),
+                        },
+                    );
+                }
+            }
+            Ok(Reading {
+                containers,
+                orders_processed: self.counters.processed(),
+                orders: self.orders_queue.lock_or_recover()?.counts(),
+            })
+        })
+    }
+
+    fn check_chains(&self, baseline: &Reading, current: &Reading) -> Vec<String> {
+        let mut violations = Vec::new();
+        for chain in &self.chains {
+            let (mut inputs, mut outputs) = (0_i128, 0_i128);
+            for ingredient in chain {
+                let (Some(before), Some(now)) = (
+                    baseline.containers.get(ingredient),
+                    current.containers.get(ingredient),
+                ) else {
+                    continue;
+                };
+                let used = delta(now.consumed, before.consumed)
+                    - delta(now.transferred, before.transferred);
+                let wasted = delta(now.wasted, before.wasted);
+                inputs += before.remaining as i128 + delta(now.supplied, before.supplied);
+                outputs += now.remaining as i128 + used;
+                if now.remaining > now.capacity {
+                    violations.push(format!(
+                        "{:?} holds {} over its capacity of {}",
+                        ingredient, now.remaining, now.capacity
+                    ));
+                }
+                if used < 0 || wasted > used {
+                    violations.push(format!(
+                        "{:?} wasted {} out of {} used by the orders",
+                        ingredient, wasted, used
+                    ));
+                }
+            }
+            if inputs != outputs {
+                violations.push(format!(
+                    "Chain {:?} had {} between initial stock and external inputs, but accounts for {}",
+                    chain, inputs, outputs
+                ));
+            }
+        }
+        violations
+    }
+
+    fn dump(&self, baseline: &Reading, current: &Reading, violations: &[String]) -> String {
+        let mut lines = vec![String::from(
+            "[INVARIANT] ===== Conservation invariant violated =====",
+        )];
+        lines.extend(
+            violations
+                .iter()
+                .map(|violation| format!("[INVARIANT] {}", violation)),
+        );
+        for chain in &self.chains {
+            lines.push(format!("[INVARIANT] Chain {:?}", chain));
+            for ingredient in chain {
+                if let (Some(before), Some(now)) = (
+                    baseline.containers.get(ingredient),
+                    current.containers.get(ingredient),
+                ) {
+                    lines.push(format!(
+                        "[INVARIANT]   {:?}: initial {}, supplied {}, remaining {}/{}, consumed {}, transferred {}, wasted {}",
+                        ingredient,
+                        before.remaining,
+                        delta(now.supplied, before.supplied),
+                        now.remaining,
+                        now.capacity,
+                        delta(now.consumed, before.consumed),
+                        delta(now.transferred, before.transferred),
+                        delta(now.wasted, before.wasted)
+                    ));
+                }
+            }
+        }
+        lines.push(format!(
+            "[INVARIANT] Orders: initial {:?} with {} processed, now {:?} with {} processed",
+            baseline.orders, baseline.orders_processed, current.orders, current.orders_processed
+        ));
+        lines.join("\n")
+    }
+}
+
+/// Los pedidos leidos desde el inicio son los que entraron a la cola mas los que ya estaban pendientes
+fn check_orders(baseline: &Reading, current: &Reading, all_done: bool) -> Vec<String> {
+    let read =
+        delta(current.orders.enqueued, baseline.orders.enqueued) + baseline.orders.pending as i128;
+    let finished = delta(current.orders_processed, baseline.orders_processed)
+        + delta(current.orders.skipped, baseline.orders.skipped)
+        + delta(current.orders.discarded, baseline.orders.discarded)
+        + delta(current.orders.rejected, baseline.orders.rejected);
+    let mut violations = Vec::new();
+    if finished > read || (all_done && finished != read) {
+        violations.push(format!(
+            "{} orders read but {} processed, skipped, discarded or rejected",
+            read, finished
+        ));
+    }
+    if all_done && current.orders.pending > 0 {
+        violations.push(format!(
+            "{} orders still pending after finishing",
+            current.orders.pending
+        ));
+    }
+    violations
+}
+
+fn delta(now: u64, before: u64) -> i128 {
+    now as i128 - before as i128
+}
+
+/// Agrupa los ingredientes en cadenas: dos ingredientes estan en la misma cadena si un reponedor pasa recurso de uno al otro
+fn build_chains(replenishers: &[(Ingredient, Ingredient)]) -> Vec<Vec<Ingredient>> {
+    let mut chains: Vec<Vec<Ingredient>> = ALL_INGREDIENTS
+        .into_iter()
+        .map(|ingredient| vec![ingredient])
+        .collect();
+    for (source, dest) in replenishers {
+        let source_chain = chains.iter().position(|chain| chain.contains(source));
+        let dest_chain = chains.iter().position(|chain| chain.contains(dest));
+        if let (Some(source_chain), Some(dest_chain)) = (source_chain, dest_chain) {
+            if source_chain != dest_chain {
+                let merged = chains.remove(source_chain.max(dest_chain));
+                chains[source_chain.min(dest_chain)].extend(merged);
+            }
+        }
+    }
+    chains
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        clock::VirtualClock,
+        container::Container,
+        order::{Order, OrderStatus},
+    };
+
+    use super::*;
+
+    fn create_checker() -> InvariantChecker {
+        let mut resources = HashMap::new();
+        for ingredient in [Ingredient::GrainsToGrind, Ingredient::GroundCoffee] {
+            resources.insert(ingredient, Arc::new(Mutex::new(Container::new(100))));
+        }
+        let checker = InvariantChecker::new(
+            Arc::new(resources),
+            &[(Ingredient::GrainsToGrind, Ingredient::GroundCoffee)],
+            Arc::new(Mutex::new(OrdersQueue::new())),
+            Arc::new(OrderCounters::new()),
+            Arc::new(TransferEpoch::new()),
+            Arc::new(VirtualClock::new()),
+        );
+        checker.start().expect("Error in test");
+        checker
+    }
+
+    fn update(
+        checker: &InvariantChecker,
+        ingredient: Ingredient,
+        update: impl FnOnce(&mut Container),
+    ) {
+        update(
+            &mut checker
+                .resources
+                .get(&ingredient)
+                .expect("Error in test")
+                .lock()
+                .expect("Error in test"),
+        );
+    }
+
+    #[test]
+    fn should_accept_transfers_consumption_and_waste_within_a_chain() {
+        let checker = create_checker();
+        update(&checker, Ingredient::GroundCoffee, |coffee| {
+            coffee.level.consume_up_to(80);
+            coffee.wasted += 30;
+        });
+        update(&checker, Ingredient::GrainsToGrind, |grains| {
+            grains.level.consume_up_to(60);
+            grains.transferred += 60;
+        });
+        update(&checker, Ingredient::GroundCoffee, |coffee| {
+            coffee.level.fill(60);
+        });
+
+        assert_eq!(Ok(()), checker.verify(true));
+    }
+
+    #[test]
+    fn should_detect_ingredients_created_out_of_nothing() {
+        let checker = create_checker();
+        update(&checker, Ingredient::GroundCoffee, |coffee| {
+            coffee.level.consume_up_to(80);
+        });
+        update(&checker, Ingredient::GrainsToGrind, |grains| {
+            grains.level.consume_up_to(60);
+        });
+        update(&checker, Ingredient::GroundCoffee, |coffee| {
+            coffee.level.fill(60);
+        });
+
+        assert_eq!(
+            Err(CoffeeMakerError::InvariantViolation),
+            checker.verify(false)
+        );
+    }
+
+    #[test]
+    fn should_detect_an_order_that_was_never_finished() {
+        let checker = create_checker();
+        {
+            let mut queue = checker.orders_queue.lock().expect("Error in test");
+            queue.push(Order::new(0, vec![(Ingredient::GroundCoffee, 10)]));
+            queue.push(Order::new(1, vec![(Ingredient::GroundCoffee, 10)]));
+            queue.pop();
+            queue.set_status(0, OrderStatus::Skipped);
+            queue.pop();
+            queue.set_status(1, OrderStatus::Served);
+        }
+
+        assert_eq!(Ok(()), checker.verify(false));
+        assert_eq!(
+            Err(CoffeeMakerError::InvariantViolation),
+            checker.verify(true)
+        );
+        checker.counters.increase_processed();
+        assert_eq!(Ok(()), checker.verify(true));
+    }
+}
@@ -0,0 +1,487 @@
+//! Registro de eventos de la cafetera. Cada evento significativo se agrega al final de un archivo
+//! (una linea JSON por evento) para poder reconstruir una ejecucion a posteriori.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::Clock,
+    errors::CoffeeMakerError,
+    order::Ingredient,
+    snapshot::{CoffeeMakerSnapshot, ContainerSnapshot},
+};
+
+/// Eventos que se registran en el journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalEvent {
+    /// Estado de la cafetera al momento de abrir el journal, del que parte la reconstruccion
+    Started {
+        state: CoffeeMakerSnapshot,
+    },
+    /// Se agrego un pedido a la cola
+    OrderEnqueued {
+        order: usize,
+        ingredients: Vec<(Ingredient, u64)>,
+    },
+    /// Un dispenser uso un ingrediente para un pedido
+    IngredientConsumed {
+        order: usize,
+        dispenser: usize,
+        ingredient: Ingredient,
+        quantity: u64,
+    },
+    /// Un reponedor recargo un contenedor, a partir de otro contenedor o de una fuente externa
+    Replenished {
+        ingredient: Ingredient,
+        source: Option<Ingredient>,
+        quantity: u64,
+    },
+    /// Un operador recargo un contenedor. `quantity` es lo que efectivamente entro de lo `requested`
+    Refilled {
+        ingredient: Ingredient,
+        quantity: u64,
+        #[serde(default)]
+        requested: u64,
+    },
+    OrderServed {
+        order: usize,
+        dispenser: usize,
+    },
+    /// Se salteo un pedido por falta del ingrediente indicado
+    OrderSkipped {
+        order: usize,
+        dispenser: usize,
+        ingredient: Ingredient,
+    },
+    /// Se devolvio a la cola un pedido que se estaba preparando, porque se cayo su dispenser
+    OrderRequeued {
+        order: usize,
+    },
+    /// Se descarto un pedido de la cola al finalizar la cafetera
+    OrderDiscarded {
+        order: usize,
+    },
+    /// El control de admision rechazo un pedido porque nunca iba a alcanzar el ingrediente indicado
+    OrderRejected {
+        order: usize,
+        ingredient: Ingredient,
+        required: u64,
+        obtainable: u64,
+    },
+}
+
+/// Linea del journal. `sequence` da el orden total de los eventos y `elapsed_us` el momento
+/// en el que ocurrieron desde que se abrio el journal, segun el reloj de la cafetera
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub elapsed_us: u64,
+    pub event: JournalEvent,
+}
+
+struct JournalWriter {
+    file: LineWriter<File>,
+    next_sequence: u64,
+    clock: Arc<dyn Clock>,
+    opened_at: Duration,
+}
+
+/// Journal compartido por todos los hilos de la cafetera. Mientras no se lo abra no registra nada.
+/// Los eventos se numeran y escriben bajo el mismo lock, por lo que el orden del archivo es el orden en el que ocurrieron.
+pub struct Journal {
+    writer: Mutex<Option<JournalWriter>>,
+}
+
+impl Journal {
+    pub fn disabled() -> Journal {
+        Journal {
+            writer: Mutex::new(None),
+        }
+    }
+
+    /// Empieza a registrar los eventos al final del archivo `path`, partiendo del estado `state`.
+    /// Los momentos de los eventos se miden con `clock`
+    pub fn open(
+        &self,
+        path: &Path,
+        state: CoffeeMakerSnapshot,
+        clock: Arc<dyn Clock>,
+    ) -> Result<(), CoffeeMakerError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| {
+                error!("[JOURNAL] Error opening {:?}: {:?}", path, err);
+                journal_error(path, err.to_string())
+            })?;
+        *self.writer.lock()? = Some(JournalWriter {
+            file: LineWriter::new(file),
+            next_sequence: 0,
+            opened_at: clock.now(),
+            clock,
+        });
+        self.record(JournalEvent::Started { state });
+        Ok(())
+    }
+
+    /// Agrega el evento al journal si esta habilitado. Un error al escribir no detiene a la cafetera
+    pub fn record(&self, event: JournalEvent) {
+        let mut writer = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => {
+                error!("[JOURNAL] Error taking the journal lock");
+                return;
+            }
+        };
+        let Some(writer) = writer.as_mut() else {
+            return;
+        };
+        let entry = JournalEntry {
+            sequence: writer.next_sequence,
+            elapsed_us: u64::try_from(
+                writer
+                    .clock
+                    .now()
+                    .saturating_sub(writer.opened_at)
+                    .as_micros(),
+            )
+            .unwrap_or(u64::MAX),
+            event,
+        };
+        writer.next_sequence += 1;
+        let result = serde_json::to_string(&entry)
+            .map_err(|err| err.to_string())
+            .and_then(|line| writeln!(writer.file, "{}", line).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            error!("[JOURNAL] Error recording event: {}", err);
+        }
+    }
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+/// Resultado de reconstruir una ejecucion a partir de su journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub containers: Vec<ContainerSnapshot>,
+    pub orders_processed: u64,
+    pub orders_enqueued: u64,
+    pub orders_skipped: u64,
+    pub orders_discarded: u64,
+    pub orders_rejected: u64,
+    pub events: u64,
+}
+
+impl ReplaySummary {
+    pub fn print(&self) {
+        let mut statistics = format!(
+            "[REPLAY] Events={} | Orders enqueued={} processed={} skipped={} discarded={} rejected={} | Ingredient=(remaining, consumed) |",
+            self.events,
+            self.orders_enqueued,
+            self.orders_processed,
+            self.orders_skipped,
+            self.orders_discarded,
+            self.orders_rejected
+        );
+        for container in &self.containers {
+            statistics.push_str(&format!(
+                " {:?}=({},{}) ",
+                container.ingredient, container.remaining, container.consumed
+            ));
+        }
+        println!("{}", statistics);
+    }
+}
+
+/// Lee el journal de `path` y reconstruye el estado final de los contenedores y el resumen de la ejecucion
+pub fn replay_file(path: &Path) -> Result<ReplaySummary, CoffeeMakerError> {
+    let file = File::open(path).map_err(|err| {
+        error!("[JOURNAL] Error opening {:?}: {:?}", path, err);
+        journal_error(path, err.to_string())
+    })?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|err| journal_error(path, err.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry = serde_json::from_str(&line).map_err(|err| {
+            error!("[JOURNAL] Invalid entry {:?}: {:?}", line, err);
+            journal_error(path, format!("invalid entry: {}", err))
+        })?;
+        entries.push(entry);
+    }
+    replay(path, &entries)
+}
+
+fn journal_error(path: &Path, reason: String) -> CoffeeMakerError {
+    CoffeeMakerError::JournalError {
+        path: path.display().to_string(),
+        reason,
+    }
+}
+
+/// Reconstruye la ejecucion a partir de la ultima vez que se abrio el journal.
+/// Falla si un evento no es consistente con el estado reconstruido hasta ese momento.
+/// `path` es el archivo del que se leyeron los eventos, solo se usa para reportar el error.
+pub fn replay(path: &Path, entries: &[JournalEntry]) -> Result<ReplaySummary, CoffeeMakerError> {
+    let start = entries
+        .iter()
+        .rposition(|entry| matches!(entry.event, JournalEvent::Started { .. }))
+        .ok_or_else(|| journal_error(path, String::from("the journal was never opened")))?;
+
+    let mut containers: HashMap<Ingredient, ContainerSnapshot> = HashMap::new();
+    let mut order = Vec::new();
+    let mut summary = ReplaySummary {
+        containers: Vec::new(),
+        orders_processed: 0,
+        orders_enqueued: 0,
+        orders_skipped: 0,
+        orders_discarded: 0,
+        orders_rejected: 0,
+        events: 0,
+    };
+
+    for entry in &entries[start..] {
+        summary.events += 1;
+        match &entry.event {
+            JournalEvent::Started { state } => {
+                for container in &state.containers {
+                    order.push(container.ingredient);
+                    containers.insert(container.ingredient, *container);
+                }
+                summary.orders_processed = state.orders_processed;
+                summary.orders_enqueued =
+                    (state.pending_orders.len() + state.in_flight_orders.len()) as u64;
+            }
+            JournalEvent::OrderEnqueued { .. } => summary.orders_enqueued += 1,
+            JournalEvent::IngredientConsumed {
+                ingredient,
+                quantity,
+                ..
+            } => take(path, &mut containers, *ingredient, *quantity)?,
+            JournalEvent::Replenished {
+                ingredient,
+                source,
+                quantity,
+            } => {
+                let mut source_is_empty = false;
+                if let Some(source) = source {
+                    take(path, &mut containers, *source, *quantity)?;
+                    source_is_empty = get_container(&mut containers, *source)?.remaining == 0;
+                }
+                let container = get_container(&mut containers, *ingredient)?;
+                container.remaining += quantity;
+                if source.is_some() {
+                    container.finished = source_is_empty;
+                }
+            }
+            JournalEvent::Refilled {
+                ingredient,
+                quantity,
+                ..
+            } => {
+                let container = get_container(&mut containers, *ingredient)?;
+                container.remaining += quantity;
+                container.finished = false;
+            }
+            JournalEvent::OrderServed { .. } => summary.orders_processed += 1,
+            JournalEvent::OrderSkipped { .. } => summary.orders_skipped += 1,
+            JournalEvent::OrderDiscarded { .. } => summary.orders_discarded += 1,
+            JournalEvent::OrderRejected { .. } => summary.orders_rejected += 1,
+            // El pedido vuelve a quedar pendiente, lo que ya habia usado se desconto con cada `IngredientConsumed`
+            JournalEvent::OrderRequeued { .. } => {}
+        }
+    }
+
+    summary.containers = order
+        .iter()
+        .filter_map(|ingredient| containers.get(ingredient).copied())
+        .collect();
+    Ok(summary)
+}
+
+fn get_container(
+    containers: &mut HashMap<Ingredient, ContainerSnapshot>,
+    ingredient: Ingredient,
+) -> Result<&mut ContainerSnapshot, CoffeeMakerError> {
+    containers
+        .get_mut(&ingredient)
+        .ok_or(CoffeeMakerError::IngredientNotInMap(ingredient))
+}
+
+fn take(
+    path: &Path,
+    containers: &mut HashMap<Ingredient, ContainerSnapshot>,
+    ingredient: Ingredient,
+    quantity: u64,
+) -> Result<(), CoffeeMakerError> {
+    let container = get_container(containers, ingredient)?;
+    container.remaining = container.remaining.checked_sub(quantity).ok_or_else(|| {
+        let reason = format!(
+            "used {} of {:?} but only {} remained",
+            quantity, ingredient, container.remaining
+        );
+        error!("[JOURNAL] {}", reason);
+        journal_error(path, reason)
+    })?;
+    container.consumed += quantity;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    fn container(ingredient: Ingredient, remaining: u64) -> ContainerSnapshot {
+        ContainerSnapshot {
+            ingredient,
+            remaining,
+            consumed: 0,
+            finished: false,
+        }
+    }
+
+    fn entries(events: Vec<JournalEvent>) -> Vec<JournalEntry> {
+        events
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, event)| JournalEntry {
+                sequence: sequence as u64,
+                elapsed_us: 0,
+                event,
+            })
+            .collect()
+    }
+
+    fn started() -> JournalEvent {
+        JournalEvent::Started {
+            state: CoffeeMakerSnapshot {
+                orders_processed: 0,
+                containers: vec![
+                    container(Ingredient::GrainsToGrind, 100),
+                    container(Ingredient::GroundCoffee, 50),
+                ],
+                pending_orders: Vec::new(),
+                in_flight_orders: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn should_rebuild_the_containers_and_the_summary() {
+        let summary = replay(
+            Path::new("journal.jsonl"),
+            &entries(vec![
+                started(),
+                JournalEvent::OrderEnqueued {
+                    order: 0,
+                    ingredients: vec![(Ingredient::GroundCoffee, 50)],
+                },
+                JournalEvent::OrderEnqueued {
+                    order: 1,
+                    ingredients: vec![(Ingredient::GroundCoffee, 100)],
+                },
+                JournalEvent::IngredientConsumed {
+                    order: 0,
+                    dispenser: 0,
+                    ingredient: Ingredient::GroundCoffee,
+                    quantity: 50,
+                },
+                JournalEvent::OrderServed {
+                    order: 0,
+                    dispenser: 0,
+                },
+                JournalEvent::Replenished {
+                    ingredient: Ingredient::GroundCoffee,
+                    source: Some(Ingredient::GrainsToGrind),
+                    quantity: 100,
+                },
+                JournalEvent::OrderSkipped {
+                    order: 1,
+                    dispenser: 1,
+                    ingredient: Ingredient::GroundCoffee,
+                },
+            ]),
+        )
+        .expect("Error in test");
+
+        assert_eq!(7, summary.events);
+        assert_eq!(2, summary.orders_enqueued);
+        assert_eq!(1, summary.orders_processed);
+        assert_eq!(1, summary.orders_skipped);
+        let grains = summary.containers[0];
+        assert_eq!((0, 100), (grains.remaining, grains.consumed));
+        let coffee = summary.containers[1];
+        assert_eq!(
+            (100, 50, true),
+            (coffee.remaining, coffee.consumed, coffee.finished)
+        );
+    }
+
+    #[test]
+    fn should_fail_if_more_than_the_remaining_was_consumed() {
+        let result = replay(
+            Path::new("journal.jsonl"),
+            &entries(vec![
+                started(),
+                JournalEvent::IngredientConsumed {
+                    order: 0,
+                    dispenser: 0,
+                    ingredient: Ingredient::GroundCoffee,
+                    quantity: 51,
+                },
+            ]),
+        );
+        assert_eq!(
+            Err(CoffeeMakerError::JournalError {
+                path: String::from("journal.jsonl"),
+                reason: String::from("used 51 of GroundCoffee but only 50 remained"),
+            }),
+            result
+        );
+    }
+
+    #[test]
+    fn should_write_the_events_in_order_and_replay_them_from_the_file() {
+        let path = env::temp_dir().join(format!("coffee-maker-journal-{}.jsonl", process::id()));
+        _ = fs::remove_file(&path);
+        let journal = Journal::disabled();
+        journal.record(JournalEvent::OrderDiscarded { order: 9 });
+        let JournalEvent::Started { state } = started() else {
+            panic!("Error in test");
+        };
+        journal
+            .open(&path, state, Arc::new(VirtualClock::new()))
+            .expect("Error in test");
+        journal.record(JournalEvent::Refilled {
+            ingredient: Ingredient::GrainsToGrind,
+            quantity: 10,
+            requested: 10,
+        });
+        journal.record(JournalEvent::OrderDiscarded { order: 2 });
+
+        let summary = replay_file(&path).expect("Error in test");
+        _ = fs::remove_file(&path);
+        assert_eq!(3, summary.events);
+        assert_eq!(1, summary.orders_discarded);
+        assert_eq!(110, summary.containers[0].remaining);
+    }
+}
@@ -0,0 +1,233 @@
+//! Histogramas de latencia de los pedidos
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Cantidad de bits de precision de cada rango del histograma. Con 7 bits el error relativo es menor al 1%
+const SUB_BUCKET_BITS: u32 = 7;
+
+/// Cantidad de valores que se guardan de forma exacta
+const SUB_BUCKET_COUNT: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Cantidad de divisiones de cada rango potencia de dos (a partir de `SUB_BUCKET_COUNT`)
+const SUB_BUCKET_HALF_COUNT: u64 = SUB_BUCKET_COUNT / 2;
+
+/// Cantidad total de posiciones necesarias para cubrir todo el rango de `u64`
+const TOTAL_BUCKETS: usize =
+    (SUB_BUCKET_COUNT + (u64::BITS - SUB_BUCKET_BITS) as u64 * SUB_BUCKET_HALF_COUNT) as usize;
+
+/// Histograma al estilo HDR. Los valores menores a `SUB_BUCKET_COUNT` se guardan de forma exacta,
+/// el resto se agrupa en rangos potencia de dos divididos en `SUB_BUCKET_HALF_COUNT` partes iguales.
+/// De esta forma se mantiene un error relativo acotado sin importar la magnitud del valor.
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            counts: vec![0; TOTAL_BUCKETS],
+            total: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        self.counts[bucket_index(value)] += 1;
+        self.total += 1;
+        self.max = self.max.max(value);
+    }
+
+    pub fn len(&self) -> u64 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Devuelve el valor por debajo del cual se encuentra el `percentile` (entre 0 y 100) de las muestras.
+    /// El valor devuelto es el mayor equivalente del rango donde cae el percentil.
+    pub fn value_at_percentile(&self, percentile: f64) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let percentile = percentile.clamp(0.0, 100.0);
+        let target = ((percentile / 100.0) * self.total as f64).ceil().max(1.0) as u64;
+        let mut accumulated = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            accumulated += count;
+            if accumulated >= target {
+                return highest_equivalent_value(index).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKET_COUNT {
+        return value as usize;
+    }
+    let magnitude = u64::BITS - 1 - value.leading_zeros();
+    let shift = magnitude - (SUB_BUCKET_BITS - 1);
+    let range = (magnitude - SUB_BUCKET_BITS) as u64;
+    let offset = (value >> shift) - SUB_BUCKET_HALF_COUNT;
+    (SUB_BUCKET_COUNT + range * SUB_BUCKET_HALF_COUNT + offset) as usize
+}
+
+fn highest_equivalent_value(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKET_COUNT {
+        return index;
+    }
+    let range = (index - SUB_BUCKET_COUNT) / SUB_BUCKET_HALF_COUNT;
+    let offset = (index - SUB_BUCKET_COUNT) % SUB_BUCKET_HALF_COUNT;
+    let shift = range as u32 + 1;
+    let lowest = (SUB_BUCKET_HALF_COUNT + offset) << shift;
+    lowest + ((1 << shift) - 1)
+}
+
+/// Percentiles de uno de los histogramas, en microsegundos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Percentiles {
+    pub(crate) fn from_histogram(histogram: &Histogram) -> Percentiles {
+        Percentiles {
+            count: histogram.len(),
+            p50: histogram.value_at_percentile(50.0),
+            p90: histogram.value_at_percentile(90.0),
+            p99: histogram.value_at_percentile(99.0),
+            max: histogram.max(),
+        }
+    }
+}
+
+/// Resumen de las latencias medidas en la cafetera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LatencySummary {
+    pub queue_wait: Percentiles,
+    pub preparation: Percentiles,
+    pub replenishment_wait: Percentiles,
+}
+
+/// Histogramas de latencia de la cafetera. Se mide cuanto espera un pedido en la cola,
+/// cuanto tarda en prepararse, y cuanto tiempo se estuvo bloqueado esperando una reposicion.
+pub struct LatencyHistograms {
+    queue_wait: Histogram,
+    preparation: Histogram,
+    replenishment_wait: Histogram,
+}
+
+impl LatencyHistograms {
+    pub fn new() -> LatencyHistograms {
+        LatencyHistograms {
+            queue_wait: Histogram::new(),
+            preparation: Histogram::new(),
+            replenishment_wait: Histogram::new(),
+        }
+    }
+
+    pub fn record_queue_wait(&mut self, duration: Duration) {
+        self.queue_wait.record(as_micros(duration));
+    }
+
+    pub fn record_preparation(&mut self, duration: Duration) {
+        self.preparation.record(as_micros(duration));
+    }
+
+    pub fn record_replenishment_wait(&mut self, duration: Duration) {
+        self.replenishment_wait.record(as_micros(duration));
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            queue_wait: Percentiles::from_histogram(&self.queue_wait),
+            preparation: Percentiles::from_histogram(&self.preparation),
+            replenishment_wait: Percentiles::from_histogram(&self.replenishment_wait),
+        }
+    }
+}
+
+impl Default for LatencyHistograms {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn as_micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_return_zero_for_an_empty_histogram() {
+        let histogram = Histogram::new();
+        assert!(histogram.is_empty());
+        assert_eq!(0, histogram.value_at_percentile(50.0));
+        assert_eq!(0, histogram.value_at_percentile(99.0));
+    }
+
+    #[test]
+    fn should_keep_small_values_exact() {
+        let mut histogram = Histogram::new();
+        for value in 1..=100 {
+            histogram.record(value);
+        }
+        assert_eq!(100, histogram.len());
+        assert_eq!(50, histogram.value_at_percentile(50.0));
+        assert_eq!(90, histogram.value_at_percentile(90.0));
+        assert_eq!(99, histogram.value_at_percentile(99.0));
+        assert_eq!(100, histogram.max());
+    }
+
+    #[test]
+    fn should_keep_the_relative_error_of_big_values_below_one_percent() {
+        let mut histogram = Histogram::new();
+        for value in 1..=100_000 {
+            histogram.record(value);
+        }
+        let p50 = histogram.value_at_percentile(50.0);
+        let p99 = histogram.value_at_percentile(99.0);
+        assert!(p50.abs_diff(50_000) * 100 <= 50_000);
+        assert!(p99.abs_diff(99_000) * 100 <= 99_000);
+        assert_eq!(100_000, histogram.value_at_percentile(100.0));
+    }
+
+    #[test]
+    fn should_summarize_the_recorded_latencies() {
+        let mut latencies = LatencyHistograms::new();
+        latencies.record_queue_wait(Duration::from_micros(10));
+        latencies.record_queue_wait(Duration::from_micros(20));
+        latencies.record_preparation(Duration::from_micros(30));
+
+        let summary = latencies.summary();
+        assert_eq!(2, summary.queue_wait.count);
+        assert_eq!(20, summary.queue_wait.max);
+        assert_eq!(1, summary.preparation.count);
+        assert_eq!(30, summary.preparation.p50);
+        assert_eq!(0, summary.replenishment_wait.count);
+    }
+}
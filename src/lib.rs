@@ -0,0 +1,48 @@
+//! Simulacion de una cafetera con varios dispensers que preparan pedidos en paralelo.
+//! Se puede embeber en otra aplicacion armando la cafetera con `CoffeeMakerBuilder`:
+//!
+//! ```no_run
+//! use brunograssano_tp1_2023_1c::CoffeeMakerBuilder;
+//!
+//! let coffee_maker = CoffeeMakerBuilder::new()
+//!     .dispensers(4)
+//!     .orders_file("orders.json")
+//!     .build()
+//!     .expect("Invalid configuration");
+//! coffee_maker.run().expect("The coffee maker failed");
+//! ```
+pub mod actors;
+pub mod admission;
+pub mod async_engine;
+pub mod builder;
+pub mod clock;
+pub mod coffee_maker;
+pub mod constants;
+pub mod container;
+pub mod container_source_replenisher;
+pub mod control;
+pub mod control_socket;
+pub mod counters;
+pub mod dashboard;
+pub mod dispenser;
+pub mod dry_run;
+pub mod epoch;
+pub mod errors;
+pub mod external_source_replenisher;
+pub mod ingredient_order;
+pub mod invariants;
+pub mod journal;
+pub mod latency;
+pub mod order;
+pub mod order_source;
+pub mod orders_queue;
+pub mod orders_reader;
+pub mod poison;
+pub mod scheduling;
+pub mod snapshot;
+pub mod statistics;
+pub mod statistics_sink;
+pub mod supervisor;
+
+pub use builder::CoffeeMakerBuilder;
+pub use coffee_maker::CoffeeMaker;
@@ -1,32 +1,263 @@
-pub mod coffee_maker;
-pub mod constants;
-pub mod container;
-pub mod container_source_replenisher;
-pub mod dispenser;
-pub mod errors;
-pub mod external_source_replenisher;
-pub mod order;
-pub mod orders_queue;
-pub mod orders_reader;
-pub mod statistics;
-
-use coffee_maker::CoffeeMaker;
-use std::env;
+use brunograssano_tp1_2023_1c::{
+    actors::Engine,
+    clock::ClockKind,
+    constants::{DEFAULT_RESTART_BUDGET, DEFAULT_SNAPSHOT_PATH},
+    container::ConsumePath,
+    control::{ControlHandle, ShutdownMode},
+    dry_run::DryRunReport,
+    errors::{CoffeeMakerError, USAGE_EXIT_CODE},
+    ingredient_order::IngredientOrdering,
+    journal, order_source,
+    scheduling::SchedulingPolicy,
+    statistics_sink::{JsonFileSink, MetricsSink},
+    CoffeeMakerBuilder,
+};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+
+/// Opciones con las que se ejecuta la cafetera.
+/// Al retomar desde una foto solo se leen pedidos nuevos si se indica explicitamente alguna fuente.
+struct Arguments {
+    sources: Vec<String>,
+    dashboard: bool,
+    check_invariants: bool,
+    admission_control: bool,
+    dry_run: bool,
+    compare_scheduling: bool,
+    scheduling_policy: SchedulingPolicy,
+    shutdown_mode: ShutdownMode,
+    control_socket: Option<PathBuf>,
+    snapshot: Option<PathBuf>,
+    resume: bool,
+    journal: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    ingredient_ordering: Option<IngredientOrdering>,
+    clock: ClockKind,
+    engine: Engine,
+    consume_path: ConsumePath,
+    restart_budget: usize,
+    statistics_json: Option<PathBuf>,
+    metrics: Option<String>,
+}
 
 fn main() {
     set_logger_config();
-    let path = get_orders_path();
-    let coffee_maker = CoffeeMaker::new();
-    coffee_maker.manage_orders(path);
+    let mut arguments = get_arguments();
+    if let Some(path) = arguments.replay {
+        replay_journal(&path);
+        return;
+    }
+    let mut builder = CoffeeMakerBuilder::new()
+        .clock(arguments.clock.create())
+        .engine(arguments.engine)
+        .consume_path(arguments.consume_path)
+        .restart_budget(arguments.restart_budget);
+    if arguments.resume || arguments.snapshot.is_some() {
+        let path = arguments
+            .snapshot
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_SNAPSHOT_PATH));
+        if arguments.resume {
+            builder = builder.resume_from(path.clone());
+        }
+        builder = builder.snapshots(path);
+    }
+    if arguments.dashboard {
+        builder = builder.dashboard();
+    }
+    if arguments.check_invariants {
+        builder = builder.check_invariants();
+    }
+    if arguments.admission_control {
+        builder = builder.admission_control();
+    }
+    builder = builder.scheduling_policy(arguments.scheduling_policy);
+    if let Some(ordering) = arguments.ingredient_ordering {
+        builder = builder.ingredient_ordering(ordering);
+    }
+    if let Some(path) = arguments.journal {
+        builder = builder.journal(path);
+    }
+    if let Some(path) = arguments.control_socket {
+        builder = builder.control_socket(path);
+    }
+    if let Some(path) = &arguments.statistics_json {
+        match JsonFileSink::create(path) {
+            Ok(sink) => builder = builder.statistics_sink(Arc::new(sink)),
+            Err(err) => exit_with(&format!("Could not create {:?}", path), err),
+        }
+    }
+    if let Some(address) = &arguments.metrics {
+        let sink = Arc::new(MetricsSink::new());
+        match sink.serve(address) {
+            Ok(address) => println!("[METRICS] Serving metrics on http://{}", address),
+            Err(err) => exit_with(&format!("Could not serve metrics on {:?}", address), err),
+        }
+        builder = builder.statistics_sink(sink);
+    }
+    if arguments.sources.is_empty() && !arguments.resume {
+        arguments.sources.push(String::from("orders.json"));
+    }
+    for location in &arguments.sources {
+        match order_source::from_location(location) {
+            Ok(source) => builder = builder.order_source(source),
+            Err(err) => exit_with(
+                &format!("Could not open the orders source {:?}", location),
+                err,
+            ),
+        }
+    }
+    if arguments.compare_scheduling {
+        match builder.compare_scheduling() {
+            Ok(reports) => DryRunReport::print_comparison(&reports),
+            Err(err) => exit_with("Could not simulate the orders", err),
+        }
+        return;
+    }
+    if arguments.dry_run {
+        match builder.dry_run() {
+            Ok(report) => report.print(),
+            Err(err) => exit_with("Could not simulate the orders", err),
+        }
+        return;
+    }
+    let coffee_maker = match builder.build() {
+        Ok(coffee_maker) => coffee_maker,
+        Err(err) => exit_with("Could not start the coffee maker", err),
+    };
+    set_signal_handler(coffee_maker.control_handle(), arguments.shutdown_mode);
+    let result = coffee_maker.run();
+    if arguments.clock != ClockKind::Real {
+        println!("[CLOCK] Simulated time: {:?}", coffee_maker.elapsed());
+    }
+    if let Err(err) = result {
+        exit_with("The coffee maker finished with an error", err);
+    }
+}
+
+/// Imprime el error y finaliza con el codigo de salida de su clase (ver `CoffeeMakerError::exit_code`)
+fn exit_with(context: &str, err: CoffeeMakerError) -> ! {
+    println!("[ERROR] {}: {}", context, err);
+    process::exit(err.exit_code())
+}
+
+/// Finaliza con el codigo de salida de los argumentos invalidos
+fn exit_with_usage(message: &str) -> ! {
+    println!("[ERROR] {}", message);
+    process::exit(USAGE_EXIT_CODE)
+}
+
+fn get_arguments() -> Arguments {
+    let mut arguments = Arguments {
+        sources: Vec::new(),
+        dashboard: false,
+        check_invariants: false,
+        admission_control: false,
+        dry_run: false,
+        compare_scheduling: false,
+        scheduling_policy: SchedulingPolicy::Fifo,
+        shutdown_mode: ShutdownMode::Drain,
+        control_socket: None,
+        snapshot: None,
+        resume: false,
+        journal: None,
+        replay: None,
+        ingredient_ordering: None,
+        clock: ClockKind::Real,
+        engine: Engine::Shared,
+        consume_path: ConsumePath::Locked,
+        restart_budget: DEFAULT_RESTART_BUDGET,
+        statistics_json: None,
+        metrics: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--control-socket" => arguments.control_socket = args.next().map(PathBuf::from),
+            "--snapshot" => arguments.snapshot = args.next().map(PathBuf::from),
+            "--resume" => arguments.resume = true,
+            "--journal" => arguments.journal = args.next().map(PathBuf::from),
+            "--replay" => arguments.replay = args.next().map(PathBuf::from),
+            "--ingredient-order" => {
+                match args
+                    .next()
+                    .map(|ordering| ordering.parse::<IngredientOrdering>())
+                {
+                    Some(Ok(ordering)) => arguments.ingredient_ordering = Some(ordering),
+                    Some(Err(err)) => exit_with("Invalid argument", err),
+                    None => exit_with_usage("Missing ingredient order, expected random, seeded:<seed>, canonical or scarcest"),
+                }
+            }
+            "--clock" => match args.next().map(|clock| clock.parse::<ClockKind>()) {
+                Some(Ok(clock)) => arguments.clock = clock,
+                Some(Err(err)) => exit_with("Invalid argument", err),
+                None => exit_with_usage("Missing clock, expected real, scaled:<factor> or virtual"),
+            },
+            "--engine" => match args.next().map(|engine| engine.parse::<Engine>()) {
+                Some(Ok(engine)) => arguments.engine = engine,
+                Some(Err(err)) => exit_with("Invalid argument", err),
+                None => exit_with_usage("Missing engine, expected shared, actors or async"),
+            },
+            "--scheduling" => match args.next().map(|policy| policy.parse::<SchedulingPolicy>()) {
+                Some(Ok(policy)) => arguments.scheduling_policy = policy,
+                Some(Err(err)) => exit_with("Invalid argument", err),
+                None => exit_with_usage(
+                    "Missing scheduling policy, expected fifo, inventory-aware, sjf or fair-share",
+                ),
+            },
+            "--consume-path" => match args.next().map(|path| path.parse::<ConsumePath>()) {
+                Some(Ok(consume_path)) => arguments.consume_path = consume_path,
+                Some(Err(err)) => exit_with("Invalid argument", err),
+                None => exit_with_usage("Missing consume path, expected locked or lock-free"),
+            },
+            "--restart-budget" => match args.next().map(|budget| budget.parse::<usize>()) {
+                Some(Ok(restart_budget)) => arguments.restart_budget = restart_budget,
+                _ => exit_with_usage("Invalid restart budget, expected a number of restarts"),
+            },
+            "--statistics-json" => arguments.statistics_json = args.next().map(PathBuf::from),
+            "--metrics" => arguments.metrics = args.next(),
+            "--dashboard" => arguments.dashboard = true,
+            "--check-invariants" => arguments.check_invariants = true,
+            "--admission-control" => arguments.admission_control = true,
+            "--dry-run" => arguments.dry_run = true,
+            "--compare-scheduling" => arguments.compare_scheduling = true,
+            "--abort-on-signal" => arguments.shutdown_mode = ShutdownMode::Abort,
+            _ => arguments.sources.push(arg),
+        }
+    }
+    arguments
 }
 
-fn get_orders_path() -> String {
-    let args: Vec<String> = env::args().collect();
-    let mut path = "orders.json";
-    if args.len() == 2 {
-        path = &args[1];
+/// Reconstruye una ejecucion anterior a partir de su journal e imprime el resumen, sin iniciar la cafetera
+fn replay_journal(path: &Path) {
+    match journal::replay_file(path) {
+        Ok(summary) => summary.print(),
+        Err(err) => exit_with(&format!("Could not replay {:?}", path), err),
+    }
+}
+
+/// Al recibir SIGINT o SIGTERM se finaliza la cafetera con el modo indicado.
+/// Si se recibe una segunda señal se descartan los pedidos que queden en la cola.
+fn set_signal_handler(control_handle: ControlHandle, mode: ShutdownMode) {
+    let mut signals_received = 0;
+    let result = ctrlc::set_handler(move || {
+        signals_received += 1;
+        let mode = if signals_received > 1 {
+            ShutdownMode::Abort
+        } else {
+            mode
+        };
+        println!("[SHUTDOWN] Signal received, finishing with mode {:?}", mode);
+        if let Err(err) = control_handle.shutdown(mode) {
+            println!("[ERROR ON SHUTDOWN] {:?}", err);
+        }
+    });
+    if let Err(err) = result {
+        println!("Error setting signal handler. Error: {:?}", err);
     }
-    String::from(path)
 }
 
 fn set_logger_config() {
@@ -1,10 +1,16 @@
 //! Representacion de un pedido
+use std::{str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::admission::Rejection;
 
 /// Cantidad total de ingredientes unicos que maneja la cafetera. Debe de coincidir con la cantidad en el `enum Ingredient`
 pub const TOTAL_INGREDIENTS: usize = 6;
 
 /// Recursos que puede manejar la cafetera
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Ingredient {
     GroundCoffee,
     HotWater,
@@ -14,16 +20,84 @@ pub enum Ingredient {
     ColdMilk,
 }
 
+/// Todos los ingredientes de la cafetera en un orden fijo. Util para mostrarlos siempre de la misma forma
+pub const ALL_INGREDIENTS: [Ingredient; TOTAL_INGREDIENTS] = [
+    Ingredient::GroundCoffee,
+    Ingredient::HotWater,
+    Ingredient::Cacao,
+    Ingredient::MilkFoam,
+    Ingredient::GrainsToGrind,
+    Ingredient::ColdMilk,
+];
+
+impl Ingredient {
+    /// Nombre del ingrediente con el mismo formato que en los archivos de pedidos
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ingredient::GroundCoffee => "ground_coffee",
+            Ingredient::HotWater => "hot_water",
+            Ingredient::Cacao => "cacao",
+            Ingredient::MilkFoam => "milk_foam",
+            Ingredient::GrainsToGrind => "grains_to_grind",
+            Ingredient::ColdMilk => "cold_milk",
+        }
+    }
+}
+
+impl FromStr for Ingredient {
+    type Err = ();
+
+    /// Obtiene el ingrediente a partir de su nombre, con el mismo formato que en los archivos de pedidos (por ejemplo, `ground_coffee`)
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        ALL_INGREDIENTS
+            .into_iter()
+            .find(|ingredient| ingredient.name() == name)
+            .ok_or(())
+    }
+}
+
+/// Estado en el que se encuentra un pedido
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// Esperando en la cola a que lo tome un dispenser
+    Queued,
+    /// Lo esta preparando el dispenser indicado
+    InProgress(usize),
+    /// Se termino de preparar
+    Served,
+    /// Se salteo porque no alcanzaba alguno de los ingredientes
+    Skipped,
+    /// Se descarto de la cola sin prepararse, por ejemplo al abortar la cafetera
+    Discarded,
+    /// El control de admision lo rechazo porque nunca iba a alcanzar alguno de los ingredientes
+    Rejected(Rejection),
+}
+
 /// Estructura para representar un pedido.
-/// Esta compuesta por un id y un vector con los ingredientes y cantidades a usar. El vector no sigue un orden en particular
-#[derive(Debug)]
+/// Esta compuesta por un id y un vector con los ingredientes y cantidades a usar. El vector no sigue un orden en particular.
+/// Tambien se guarda el momento en el que entro a la cola, segun el reloj de la cafetera, para poder medir cuanto espera.
+/// El cliente es el del campo opcional `customer` del pedido o, si no lo indica, la fuente de la que llego
+/// (`SourceOrder::customer_or`). Lo usa la politica de reparto justo de la cola.
+#[derive(Debug, Clone)]
 pub struct Order {
     pub id: usize,
     pub ingredients: Vec<(Ingredient, u64)>,
+    pub created_at: Duration,
+    pub customer: String,
 }
 
 impl Order {
     pub fn new(id: usize, ingredients: Vec<(Ingredient, u64)>) -> Order {
-        Order { id, ingredients }
+        Order {
+            id,
+            ingredients,
+            created_at: Duration::ZERO,
+            customer: String::new(),
+        }
+    }
+
+    pub fn with_customer(mut self, customer: impl Into<String>) -> Order {
+        self.customer = customer.into();
+        self
     }
 }
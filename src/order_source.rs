@@ -0,0 +1,540 @@
+//! Fuentes de pedidos de la cafetera. Cada fuente es leida por su propio hilo lector,
+//! y la cola de pedidos se cierra recien cuando se agotaron todas.
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    mem,
+    net::TcpStream,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+    vec,
+};
+
+use log::error;
+
+use crate::{
+    constants::SOURCE_POLL_IN_MS,
+    errors::CoffeeMakerError,
+    order::Ingredient,
+    orders_reader::{open_error, parse_json_source_order, parse_orders, read_orders_from_file},
+};
+
+/// Ingredientes y cantidades de un pedido
+pub type OrderIngredients = Vec<(Ingredient, u64)>;
+
+/// Pedido leido de una fuente. Si el pedido no indica su cliente, se toma como cliente a la fuente
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceOrder {
+    pub ingredients: OrderIngredients,
+    pub customer: Option<String>,
+}
+
+impl SourceOrder {
+    /// Cliente del pedido, o `source` si el pedido no lo indica
+    pub fn customer_or(&self, source: &str) -> String {
+        self.customer.clone().unwrap_or_else(|| source.to_string())
+    }
+}
+
+impl From<OrderIngredients> for SourceOrder {
+    fn from(ingredients: OrderIngredients) -> SourceOrder {
+        SourceOrder {
+            ingredients,
+            customer: None,
+        }
+    }
+}
+
+/// Fuente de pedidos. El hilo lector le pide pedidos hasta que devuelve `None` o un error,
+/// en ambos casos se considera que la fuente se agoto.
+pub trait OrderSource: Send {
+    /// Nombre con el que se identifica a la fuente en los mensajes
+    fn name(&self) -> String;
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError>;
+
+    /// Igual que `next_order`, pero las fuentes que pueden quedar bloqueadas esperando datos revisan
+    /// cada `SOURCE_POLL_IN_MS` si `stopped` indica que ya no se aceptan pedidos, y en ese caso devuelven `None`
+    fn next_order_until(
+        &mut self,
+        _stopped: &dyn Fn() -> bool,
+    ) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        self.next_order()
+    }
+}
+
+impl<T: OrderSource + ?Sized> OrderSource for Box<T> {
+    fn name(&self) -> String {
+        (**self).name()
+    }
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        (**self).next_order()
+    }
+
+    fn next_order_until(
+        &mut self,
+        stopped: &dyn Fn() -> bool,
+    ) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        (**self).next_order_until(stopped)
+    }
+}
+
+/// Pedidos en memoria, util para tests o para aplicaciones que arman los pedidos por su cuenta
+pub struct VecSource {
+    orders: vec::IntoIter<SourceOrder>,
+}
+
+impl VecSource {
+    pub fn new(orders: Vec<OrderIngredients>) -> VecSource {
+        VecSource::with_customers(orders.into_iter().map(SourceOrder::from).collect())
+    }
+
+    /// Pedidos que pueden indicar su cliente
+    pub fn with_customers(orders: Vec<SourceOrder>) -> VecSource {
+        VecSource {
+            orders: orders.into_iter(),
+        }
+    }
+}
+
+impl OrderSource for VecSource {
+    fn name(&self) -> String {
+        String::from("memory")
+    }
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        Ok(self.orders.next())
+    }
+}
+
+/// Archivo JSON con el formato de `orders.json`. Se lee completo al pedir el primer pedido
+pub struct JsonFileSource {
+    path: String,
+    orders: Option<vec::IntoIter<SourceOrder>>,
+}
+
+impl JsonFileSource {
+    pub fn new(path: impl Into<String>) -> JsonFileSource {
+        JsonFileSource {
+            path: path.into(),
+            orders: None,
+        }
+    }
+}
+
+impl OrderSource for JsonFileSource {
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        if self.orders.is_none() {
+            let orders = read_orders_from_file(&self.path)?;
+            self.orders = Some(orders.into_iter());
+        }
+        Ok(self.orders.as_mut().and_then(|orders| orders.next()))
+    }
+}
+
+/// Flujo de pedidos con un pedido JSON por linea (JSONL), por ejemplo un archivo, la entrada estandar o un socket TCP.
+/// Las lineas vacias se ignoran y las que no tienen el formato esperado se descartan sin cortar el flujo.
+/// La entrada estandar y el socket se leen con un tiempo de espera, para poder dejar de leer aunque no lleguen mas lineas.
+pub struct JsonLinesSource {
+    name: String,
+    lines: Box<dyn BufRead + Send>,
+    /// Lo que se leyo de la linea actual antes de que se venciera el tiempo de espera
+    line: String,
+}
+
+impl JsonLinesSource {
+    pub fn from_reader(
+        name: impl Into<String>,
+        reader: impl Read + Send + 'static,
+    ) -> JsonLinesSource {
+        JsonLinesSource {
+            name: name.into(),
+            lines: Box::new(BufReader::new(reader)),
+            line: String::new(),
+        }
+    }
+
+    pub fn open(path: &str) -> Result<JsonLinesSource, CoffeeMakerError> {
+        let file = File::open(path).map_err(|err| {
+            error!("[READER] Error opening {}: {:?}", path, err);
+            open_error(path, &err)
+        })?;
+        Ok(JsonLinesSource::from_reader(path, file))
+    }
+
+    pub fn stdin() -> JsonLinesSource {
+        JsonLinesSource::from_reader("stdin", PollingReader::spawn(io::stdin()))
+    }
+
+    /// Se conecta a `address` (`host:puerto`) y lee los pedidos hasta que el otro extremo cierre la conexion
+    pub fn connect(address: &str) -> Result<JsonLinesSource, CoffeeMakerError> {
+        let stream = TcpStream::connect(address).map_err(|err| {
+            error!("[READER] Error connecting to {}: {:?}", address, err);
+            CoffeeMakerError::SocketError {
+                path: address.to_string(),
+                reason: err.to_string(),
+            }
+        })?;
+        stream
+            .set_read_timeout(Some(Duration::from_millis(SOURCE_POLL_IN_MS)))
+            .map_err(|err| CoffeeMakerError::SocketError {
+                path: address.to_string(),
+                reason: err.to_string(),
+            })?;
+        Ok(JsonLinesSource::from_reader(
+            format!("tcp://{}", address),
+            stream,
+        ))
+    }
+}
+
+impl OrderSource for JsonLinesSource {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        self.next_order_until(&|| false)
+    }
+
+    fn next_order_until(
+        &mut self,
+        stopped: &dyn Fn() -> bool,
+    ) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        loop {
+            match self.lines.read_line(&mut self.line) {
+                Ok(0) if self.line.is_empty() => return Ok(None),
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if stopped() {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    error!("[READER] Error reading from {}: {:?}", self.name, err);
+                    return Err(CoffeeMakerError::FileReaderError {
+                        path: self.name.clone(),
+                        reason: err.to_string(),
+                    });
+                }
+            }
+            let line = mem::take(&mut self.line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_json_source_order(line.trim()) {
+                Ok(order) => return Ok(Some(order)),
+                Err(_) => error!("[READER] Invalid order on {}: {}", self.name, line.trim()),
+            }
+        }
+    }
+}
+
+/// Lee de `reader` desde otro hilo y entrega lo leido con un tiempo de espera de `SOURCE_POLL_IN_MS`.
+/// Sirve para lectores que no tienen tiempo de espera propio, como la entrada estandar.
+/// Si se deja de leer, el hilo queda bloqueado en `reader` hasta que este termine o finalice el proceso.
+struct PollingReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl PollingReader {
+    fn spawn(mut reader: impl Read + Send + 'static) -> PollingReader {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => return,
+                    Ok(read) => Ok(buffer[..read].to_vec()),
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                if sender.send(chunk).is_err() || failed {
+                    return;
+                }
+            }
+        });
+        PollingReader {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for PollingReader {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            match self
+                .chunks
+                .recv_timeout(Duration::from_millis(SOURCE_POLL_IN_MS))
+            {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(ErrorKind::TimedOut.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let read = buffer.len().min(self.chunk.len() - self.position);
+        buffer[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Pedidos que se obtienen con un `GET` a una URL `http://`, con el mismo formato que `orders.json`.
+/// Se hace el pedido al pedir el primer pedido. No soporta HTTPS.
+pub struct HttpSource {
+    url: String,
+    orders: Option<vec::IntoIter<SourceOrder>>,
+}
+
+impl HttpSource {
+    pub fn new(url: impl Into<String>) -> HttpSource {
+        HttpSource {
+            url: url.into(),
+            orders: None,
+        }
+    }
+
+    fn socket_error(&self, reason: impl ToString) -> CoffeeMakerError {
+        CoffeeMakerError::SocketError {
+            path: self.url.clone(),
+            reason: reason.to_string(),
+        }
+    }
+
+    fn fetch(&self) -> Result<String, CoffeeMakerError> {
+        let location = self
+            .url
+            .strip_prefix("http://")
+            .ok_or_else(|| self.socket_error("only http:// is supported"))?;
+        let (host, path) = match location.find('/') {
+            Some(index) => location.split_at(index),
+            None => (location, "/"),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        let mut stream = TcpStream::connect(&address).map_err(|err| self.socket_error(err))?;
+        // Se manda en una sola escritura, `write!` sobre el stream escribe cada parte por separado
+        let request = format!(
+            "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, host
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| self.socket_error(err))?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .map_err(|err| self.socket_error(err))?;
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .ok_or_else(|| self.socket_error("malformed HTTP response"))?;
+        let status = head.lines().next().unwrap_or_default();
+        if status.split_whitespace().nth(1) != Some("200") {
+            error!("[READER] {} answered {}", self.url, status);
+            return Err(self.socket_error(format!("answered {}", status)));
+        }
+        Ok(body.to_string())
+    }
+}
+
+impl OrderSource for HttpSource {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn next_order(&mut self) -> Result<Option<SourceOrder>, CoffeeMakerError> {
+        if self.orders.is_none() {
+            let body = self.fetch()?;
+            let orders = parse_orders(&self.url, &body)?;
+            self.orders = Some(orders.into_iter());
+        }
+        Ok(self.orders.as_mut().and_then(|orders| orders.next()))
+    }
+}
+
+/// Crea la fuente que corresponde a `location`:
+/// * `-` lee pedidos JSONL de la entrada estandar
+/// * `tcp://<host>:<puerto>` lee pedidos JSONL de una conexion TCP
+/// * `http://...` obtiene un archivo de pedidos por HTTP
+/// * Un archivo terminado en `.jsonl` se lee como JSONL, cualquier otro como un archivo con el formato de `orders.json`
+pub fn from_location(location: &str) -> Result<Box<dyn OrderSource>, CoffeeMakerError> {
+    if location == "-" {
+        return Ok(Box::new(JsonLinesSource::stdin()));
+    }
+    if let Some(address) = location.strip_prefix("tcp://") {
+        return Ok(Box::new(JsonLinesSource::connect(address)?));
+    }
+    if location.starts_with("http://") {
+        return Ok(Box::new(HttpSource::new(location)));
+    }
+    if location.ends_with(".jsonl") {
+        return Ok(Box::new(JsonLinesSource::open(location)?));
+    }
+    Ok(Box::new(JsonFileSource::new(location)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, net::TcpListener, thread};
+
+    use super::*;
+
+    fn drain(source: &mut dyn OrderSource) -> Vec<OrderIngredients> {
+        let mut orders = Vec::new();
+        while let Some(order) = source.next_order().expect("Error in test") {
+            orders.push(order.ingredients);
+        }
+        orders
+    }
+
+    #[test]
+    fn should_read_the_orders_from_a_file() {
+        let mut source = JsonFileSource::new("tests/multiple_orders.json");
+        assert_eq!(41, drain(&mut source).len());
+    }
+
+    #[test]
+    fn should_skip_the_invalid_lines_of_a_json_lines_stream() {
+        let lines = "{\"ground_coffee\": 10, \"hot_water\": 0, \"cacao\": 0, \"milk_foam\": 0}\n\
+                     \n\
+                     not an order\n\
+                     {\"ground_coffee\": 0, \"hot_water\": 20, \"cacao\": 0, \"milk_foam\": 0}\n";
+        let mut source = JsonLinesSource::from_reader("test", Cursor::new(lines));
+        assert_eq!(
+            vec![
+                vec![(Ingredient::GroundCoffee, 10)],
+                vec![(Ingredient::HotWater, 20)]
+            ],
+            drain(&mut source)
+        );
+    }
+
+    #[test]
+    fn should_read_the_orders_from_a_tcp_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error in test");
+        let address = listener.local_addr().expect("Error in test").to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Error in test");
+            stream
+                .write_all(
+                    b"{\"ground_coffee\": 0, \"hot_water\": 0, \"cacao\": 5, \"milk_foam\": 0}\n",
+                )
+                .expect("Error in test");
+        });
+        let mut source = from_location(&format!("tcp://{}", address)).expect("Error in test");
+        assert_eq!(vec![vec![(Ingredient::Cacao, 5)]], drain(source.as_mut()));
+        server.join().expect("Error in test");
+    }
+
+    /// Lector que no devuelve nada hasta que se cierra el canal
+    struct SilentReader(Receiver<()>);
+
+    impl Read for SilentReader {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            _ = self.0.recv();
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn should_stop_waiting_for_a_reader_without_timeout_once_stopped() {
+        let (_open, closed) = mpsc::channel();
+        let mut source =
+            JsonLinesSource::from_reader("test", PollingReader::spawn(SilentReader(closed)));
+        assert_eq!(Ok(None), source.next_order_until(&|| true));
+    }
+
+    #[test]
+    fn should_stop_waiting_for_the_tcp_connection_once_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error in test");
+        let address = listener.local_addr().expect("Error in test").to_string();
+        let (finished, finish) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Error in test");
+            // Manda media linea y deja la conexion abierta
+            stream
+                .write_all(b"{\"ground_coffee\": 0, \"hot_water\": 0,")
+                .expect("Error in test");
+            _ = finish.recv();
+            stream
+                .write_all(b" \"cacao\": 5, \"milk_foam\": 0}\n")
+                .expect("Error in test");
+        });
+        let mut source = JsonLinesSource::connect(&address).expect("Error in test");
+        assert_eq!(Ok(None), source.next_order_until(&|| true));
+
+        finished.send(()).expect("Error in test");
+        assert_eq!(
+            Ok(Some(SourceOrder::from(vec![(Ingredient::Cacao, 5)]))),
+            source.next_order_until(&|| false)
+        );
+        server.join().expect("Error in test");
+    }
+
+    #[test]
+    fn should_fetch_the_orders_over_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error in test");
+        let address = listener.local_addr().expect("Error in test").to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Error in test");
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).expect("Error in test");
+            assert!(String::from_utf8_lossy(&request[..read]).starts_with("GET /orders HTTP/1.0"));
+            let body = "{\"orders\": [{\"ground_coffee\": 0, \"hot_water\": 0, \"cacao\": 0, \"milk_foam\": 30}]}";
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .expect("Error in test");
+        });
+        let mut source = HttpSource::new(format!("http://{}/orders", address));
+        assert_eq!(vec![vec![(Ingredient::MilkFoam, 30)]], drain(&mut source));
+        server.join().expect("Error in test");
+    }
+
+    #[test]
+    fn should_fail_if_the_http_server_does_not_answer_ok() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error in test");
+        let address = listener.local_addr().expect("Error in test").to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Error in test");
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request).expect("Error in test");
+            stream
+                .write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")
+                .expect("Error in test");
+        });
+        let url = format!("http://{}/orders", address);
+        let mut source = HttpSource::new(url.clone());
+        assert_eq!(
+            Err(CoffeeMakerError::SocketError {
+                path: url,
+                reason: String::from("answered HTTP/1.0 404 Not Found"),
+            }),
+            source.next_order()
+        );
+        server.join().expect("Error in test");
+    }
+}
@@ -1,33 +1,294 @@
 //! Representacion de la cola de pedidos
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    sync::Arc,
+};
 
-use crate::order::Order;
+use crate::{
+    admission::Rejection,
+    clock::{default_clock, Clock},
+    constants::MAX_RECENT_STATUSES,
+    journal::{Journal, JournalEvent},
+    order::{Order, OrderStatus},
+    poison::Recoverable,
+    scheduling::{Scheduler, SchedulingPolicy},
+};
+
+/// Cantidad de pedidos que pasaron por la cola segun su estado
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OrderCounts {
+    /// Pedidos que entraron a la cola
+    pub enqueued: u64,
+    /// Pedidos en la cola o en preparacion
+    pub pending: u64,
+    pub skipped: u64,
+    pub discarded: u64,
+    pub rejected: u64,
+}
 
 /// Cola de pedidos a realizar. Se le agrega el campo `finished` para indicar que no se van a estar cargando más pedidos a la cola.
+/// Tambien guarda que dispensers se encuentran pausados, ya que estos esperan sobre la misma variable condicional que la cola.
+/// Los dispensers pausados son los que difieren de `paused_all` segun `pause_exceptions`.
+/// Se lleva el estado de los pedidos pendientes y de los ultimos `MAX_RECENT_STATUSES` terminados, junto con
+/// los totales de todos los que pasaron por la cola, y cuantas fuentes de pedidos siguen abiertas.
+/// Los pedidos que entran y los que se descartan quedan registrados en el journal.
+/// Los dispensers toman los pedidos en el orden que indica el `Scheduler`, por defecto en el que llegaron.
 pub struct OrdersQueue {
     orders: VecDeque<Order>,
     pub finished: bool,
+    paused_all: bool,
+    pause_exceptions: HashSet<usize>,
+    statuses: HashMap<usize, OrderStatus>,
+    /// Pedidos terminados que siguen en `statuses`, del mas viejo al mas nuevo
+    recent: VecDeque<usize>,
+    recent_limit: usize,
+    counts: OrderCounts,
+    next_id: usize,
+    open_sources: usize,
+    journal: Arc<Journal>,
+    scheduler: Scheduler,
+    clock: Arc<dyn Clock>,
 }
 
 impl OrdersQueue {
     pub fn new() -> OrdersQueue {
+        OrdersQueue::with_journal(Arc::new(Journal::disabled()))
+    }
+
+    pub fn with_journal(journal: Arc<Journal>) -> OrdersQueue {
         OrdersQueue {
             orders: VecDeque::new(),
             finished: false,
+            paused_all: false,
+            pause_exceptions: HashSet::new(),
+            statuses: HashMap::new(),
+            recent: VecDeque::new(),
+            recent_limit: MAX_RECENT_STATUSES,
+            counts: OrderCounts::default(),
+            next_id: 0,
+            open_sources: 0,
+            journal,
+            scheduler: Scheduler::fifo(),
+            clock: default_clock(),
         }
     }
 
-    pub fn push(&mut self, order: Order) {
+    /// Reloj con el que se marca el momento en el que entra cada pedido
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn push(&mut self, mut order: Order) {
+        order.created_at = self.clock.local_now();
+        self.update_status(order.id, OrderStatus::Queued);
+        self.next_id = self.next_id.max(order.id + 1);
+        self.journal.record(JournalEvent::OrderEnqueued {
+            order: order.id,
+            ingredients: order.ingredients.clone(),
+        });
         self.orders.push_back(order);
     }
 
+    /// Vuelve a poner al principio de la cola un pedido que ya se habia tomado, por ejemplo si se cayo el dispenser
+    /// que lo estaba preparando. Se acepta aunque la cola ya este cerrada
+    pub fn requeue(&mut self, order: Order) {
+        self.update_status(order.id, OrderStatus::Queued);
+        self.journal
+            .record(JournalEvent::OrderRequeued { order: order.id });
+        self.orders.push_front(order);
+    }
+
+    /// Registra que el control de admision rechazo al pedido `id`, ya sea al llegar (sin entrar a la cola)
+    /// o cuando lo tomo un dispenser
+    pub fn reject(&mut self, id: usize, rejection: Rejection) {
+        self.update_status(id, OrderStatus::Rejected(rejection));
+        self.next_id = self.next_id.max(id + 1);
+        self.journal.record(JournalEvent::OrderRejected {
+            order: id,
+            ingredient: rejection.ingredient,
+            required: rejection.required,
+            obtainable: rejection.obtainable,
+        });
+    }
+
+    /// Devuelve un id que todavia no fue usado por ningun pedido de la cola
+    pub fn next_id(&self) -> usize {
+        self.next_id
+    }
+
+    pub fn status(&self, id: usize) -> Option<OrderStatus> {
+        self.statuses.get(&id).copied()
+    }
+
+    pub fn set_status(&mut self, id: usize, status: OrderStatus) {
+        self.update_status(id, status);
+    }
+
+    /// Actualiza los totales con el cambio de estado. Al terminar un pedido, si ya hay `recent_limit` terminados
+    /// se olvida el estado del mas viejo
+    fn update_status(&mut self, id: usize, status: OrderStatus) {
+        match self.statuses.insert(id, status) {
+            Some(previous) => {
+                if let Some(count) = count_of(&mut self.counts, previous) {
+                    *count -= 1;
+                }
+            }
+            None => self.counts.enqueued += 1,
+        }
+        if let Some(count) = count_of(&mut self.counts, status) {
+            *count += 1;
+        }
+        if !matches!(status, OrderStatus::Queued | OrderStatus::InProgress(_)) {
+            self.recent.push_back(id);
+        }
+        while self.recent.len() > self.recent_limit {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.statuses.remove(&oldest);
+            }
+        }
+    }
+
+    /// Registra una fuente adicional de pedidos. La cola no se cierra hasta que todas las fuentes finalicen
+    pub fn register_source(&mut self) {
+        self.open_sources += 1;
+    }
+
+    /// Indica que una fuente de pedidos no va a agregar mas pedidos. Si era la ultima, se cierra la cola
+    pub fn finish_source(&mut self) {
+        self.open_sources = self.open_sources.saturating_sub(1);
+        if self.open_sources == 0 {
+            self.close();
+        }
+    }
+
+    /// Cambia el orden en el que se toman los pedidos. Se debe llamar antes de iniciar la cafetera
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        self.scheduler = scheduler;
+    }
+
+    pub fn scheduling_policy(&self) -> SchedulingPolicy {
+        self.scheduler.policy()
+    }
+
     pub fn pop(&mut self) -> Option<Order> {
-        self.orders.pop_front()
+        let index = self.scheduler.next(&self.orders);
+        self.orders.remove(index)
     }
 
     pub fn is_empty(&self) -> bool {
         self.orders.is_empty()
     }
+
+    /// Pedidos que todavia no fueron tomados por ningun dispenser, en el orden en el que llegaron.
+    /// Con una politica distinta de FIFO se pueden tomar en otro orden
+    pub fn pending(&self) -> impl Iterator<Item = &Order> {
+        self.orders.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Indica que no se van a aceptar mas pedidos. Los que ya estan en la cola se siguen procesando
+    pub fn close(&mut self) {
+        self.finished = true;
+    }
+
+    /// Pausa a todos los dispensers, o al indicado. No van a tomar nuevos pedidos hasta que se los reanude
+    pub fn pause(&mut self, dispenser: Option<usize>) {
+        match dispenser {
+            None => {
+                self.paused_all = true;
+                self.pause_exceptions.clear();
+            }
+            Some(id) if self.paused_all => {
+                self.pause_exceptions.remove(&id);
+            }
+            Some(id) => {
+                self.pause_exceptions.insert(id);
+            }
+        }
+    }
+
+    /// Reanuda a todos los dispensers, o al indicado
+    pub fn resume(&mut self, dispenser: Option<usize>) {
+        match dispenser {
+            None => {
+                self.paused_all = false;
+                self.pause_exceptions.clear();
+            }
+            Some(id) if self.paused_all => {
+                self.pause_exceptions.insert(id);
+            }
+            Some(id) => {
+                self.pause_exceptions.remove(&id);
+            }
+        }
+    }
+
+    pub fn is_paused(&self, dispenser: usize) -> bool {
+        self.paused_all != self.pause_exceptions.contains(&dispenser)
+    }
+
+    /// Cuenta los pedidos que pasaron por la cola segun su estado, incluidos los que ya no se recuerdan
+    pub fn counts(&self) -> OrderCounts {
+        self.counts
+    }
+
+    /// Devuelve los ids de los dispensers pausados entre los `total_dispensers` de la cafetera
+    pub fn paused_dispensers(&self, total_dispensers: usize) -> Vec<usize> {
+        (0..total_dispensers)
+            .filter(|id| self.is_paused(*id))
+            .collect()
+    }
+
+    /// Cierra la cola y descarta los pedidos pendientes. Devuelve la cantidad de pedidos descartados
+    pub fn abort(&mut self) -> usize {
+        let discarded = self.orders.len();
+        for order in mem::take(&mut self.orders) {
+            self.update_status(order.id, OrderStatus::Discarded);
+            self.journal
+                .record(JournalEvent::OrderDiscarded { order: order.id });
+        }
+        self.finished = true;
+        discarded
+    }
+}
+
+/// Total de `counts` en el que se cuentan los pedidos con `status`. Los servidos no se cuentan aparte
+fn count_of(counts: &mut OrderCounts, status: OrderStatus) -> Option<&mut u64> {
+    match status {
+        OrderStatus::Queued | OrderStatus::InProgress(_) => Some(&mut counts.pending),
+        OrderStatus::Skipped => Some(&mut counts.skipped),
+        OrderStatus::Discarded => Some(&mut counts.discarded),
+        OrderStatus::Rejected(_) => Some(&mut counts.rejected),
+        OrderStatus::Served => None,
+    }
+}
+
+/// Cada pedido de la cola tiene que estar registrado como encolado y con un id ya entregado
+impl Recoverable for OrdersQueue {
+    const NAME: &'static str = "orders queue";
+
+    fn validate(&self) -> Result<(), String> {
+        for order in &self.orders {
+            if order.id >= self.next_id {
+                return Err(format!(
+                    "order {} has an id that was not given yet",
+                    order.id
+                ));
+            }
+            if self.status(order.id) != Some(OrderStatus::Queued) {
+                return Err(format!(
+                    "order {} is in the queue with status {:?}",
+                    order.id,
+                    self.status(order.id)
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Default for OrdersQueue {
@@ -37,7 +298,10 @@ impl Default for OrdersQueue {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
+    use crate::{order::Ingredient, scheduling::Stock};
+
     use super::*;
 
     #[test]
@@ -64,6 +328,152 @@ mod tests {
         assert_eq!(true, queue.is_empty());
     }
 
+    #[test]
+    fn should_discard_the_orders_when_aborted() {
+        let mut queue = OrdersQueue::new();
+        queue.push(Order::new(1, Vec::new()));
+        queue.push(Order::new(2, Vec::new()));
+        assert_eq!(2, queue.abort());
+        assert!(queue.finished);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn should_requeue_an_order_at_the_front_even_if_closed() {
+        let mut queue = OrdersQueue::new();
+        queue.push(Order::new(1, Vec::new()));
+        queue.push(Order::new(2, Vec::new()));
+        let order = queue.pop().expect("Error in test");
+        queue.set_status(order.id, OrderStatus::InProgress(0));
+        queue.close();
+        queue.requeue(order);
+        assert_eq!(Some(OrderStatus::Queued), queue.status(1));
+        assert_eq!(Some(1), queue.pop().map(|order| order.id));
+    }
+
+    #[test]
+    fn should_pause_and_resume_a_single_dispenser() {
+        let mut queue = OrdersQueue::new();
+        queue.pause(Some(1));
+        assert!(queue.is_paused(1));
+        assert!(!queue.is_paused(2));
+        queue.resume(Some(1));
+        assert!(!queue.is_paused(1));
+    }
+
+    #[test]
+    fn should_resume_a_single_dispenser_when_all_are_paused() {
+        let mut queue = OrdersQueue::new();
+        queue.pause(None);
+        queue.resume(Some(2));
+        assert_eq!(vec![0, 1, 3], queue.paused_dispensers(4));
+        queue.resume(None);
+        assert!(queue.paused_dispensers(4).is_empty());
+    }
+
+    #[test]
+    fn should_track_the_status_of_the_orders() {
+        let mut queue = OrdersQueue::new();
+        queue.push(Order::new(queue.next_id(), Vec::new()));
+        queue.push(Order::new(queue.next_id(), Vec::new()));
+        assert_eq!(Some(OrderStatus::Queued), queue.status(1));
+        queue.set_status(0, OrderStatus::InProgress(3));
+        assert_eq!(Some(OrderStatus::InProgress(3)), queue.status(0));
+        queue.abort();
+        assert_eq!(Some(OrderStatus::Discarded), queue.status(1));
+        assert_eq!(None, queue.status(2));
+    }
+
+    #[test]
+    fn should_count_the_rejected_orders_without_queueing_them() {
+        let mut queue = OrdersQueue::new();
+        let rejection = Rejection {
+            ingredient: Ingredient::Cacao,
+            required: 10,
+            obtainable: 0,
+        };
+        queue.reject(queue.next_id(), rejection);
+        queue.push(Order::new(queue.next_id(), Vec::new()));
+        assert_eq!(Some(OrderStatus::Rejected(rejection)), queue.status(0));
+        assert_eq!(Some(OrderStatus::Queued), queue.status(1));
+        assert_eq!(1, queue.orders.len());
+        let counts = queue.counts();
+        assert_eq!(2, counts.enqueued);
+        assert_eq!(1, counts.rejected);
+        assert_eq!(1, counts.pending);
+    }
+
+    #[test]
+    fn should_remember_only_the_last_finished_orders_but_count_all_of_them() {
+        let mut queue = OrdersQueue::new();
+        queue.recent_limit = 2;
+        for id in 0..4 {
+            queue.push(Order::new(id, Vec::new()));
+        }
+        for id in 0..3 {
+            queue.set_status(id, OrderStatus::InProgress(0));
+            queue.set_status(id, OrderStatus::Skipped);
+        }
+        assert_eq!(None, queue.status(0));
+        assert_eq!(Some(OrderStatus::Skipped), queue.status(1));
+        assert_eq!(Some(OrderStatus::Skipped), queue.status(2));
+        assert_eq!(Some(OrderStatus::Queued), queue.status(3));
+        assert_eq!(3, queue.statuses.len());
+
+        let counts = queue.counts();
+        assert_eq!(4, counts.enqueued);
+        assert_eq!(3, counts.skipped);
+        assert_eq!(1, counts.pending);
+    }
+
+    fn pop_all(policy: SchedulingPolicy, orders: Vec<Order>) -> Vec<usize> {
+        let mut queue = OrdersQueue::new();
+        queue.set_scheduler(Scheduler::new(
+            policy,
+            Stock::new(Arc::new(HashMap::new()), &[], &[]),
+        ));
+        for order in orders {
+            queue.push(order);
+        }
+        std::iter::from_fn(|| queue.pop())
+            .map(|order| order.id)
+            .collect()
+    }
+
+    #[test]
+    fn should_take_the_orders_in_the_order_of_the_scheduling_policy() {
+        let orders = vec![
+            Order::new(0, vec![(Ingredient::Cacao, 300)]).with_customer("a"),
+            Order::new(1, vec![(Ingredient::Cacao, 200)]).with_customer("a"),
+            Order::new(2, vec![(Ingredient::Cacao, 100)]).with_customer("a"),
+            Order::new(3, vec![(Ingredient::Cacao, 150)]).with_customer("b"),
+        ];
+
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            pop_all(SchedulingPolicy::Fifo, orders.clone())
+        );
+        assert_eq!(
+            vec![2, 3, 1, 0],
+            pop_all(SchedulingPolicy::ShortestJobFirst, orders.clone())
+        );
+        assert_eq!(
+            vec![0, 3, 1, 2],
+            pop_all(SchedulingPolicy::FairShare, orders)
+        );
+    }
+
+    #[test]
+    fn should_close_only_after_all_the_sources_finish() {
+        let mut queue = OrdersQueue::new();
+        queue.register_source();
+        queue.register_source();
+        queue.finish_source();
+        assert!(!queue.finished);
+        queue.finish_source();
+        assert!(queue.finished);
+    }
+
     #[test]
     fn should_pop_and_return_none_from_the_queue() {
         let mut queue = OrdersQueue::new();
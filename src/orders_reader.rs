@@ -1,20 +1,19 @@
-//! Lector de los pedidos a partir de un archivo
+//! Lector de los pedidos. Lleva a la cola los pedidos de una fuente de pedidos
 use log::{debug, error, info};
 use serde::Deserialize;
-use std::error::Error;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{self, BufReader};
 use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex};
 
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-
+use crate::admission::AdmissionControl;
 use crate::constants::MAX_OF_INGREDIENT_IN_AN_ORDER;
 use crate::errors::CoffeeMakerError;
 use crate::order::{Ingredient, Order};
+use crate::order_source::{OrderSource, SourceOrder};
 
 use crate::orders_queue::OrdersQueue;
+use crate::poison::LockOrRecover;
 
 /// Representacion de un pedido cuando viene en el archivo JSON. Tiene los ingredientes que se pueden usar y las cantidades de cada uno.
 #[derive(Deserialize, Debug)]
@@ -25,44 +24,126 @@ struct JsonOrder {
     milk_foam: u64,
 }
 
+/// Pedido JSON que puede indicar el cliente que lo hace, por ejemplo `{"customer": "mesa 4", "ground_coffee": 10, ...}`
+#[derive(Deserialize, Debug)]
+struct JsonCustomerOrder {
+    #[serde(flatten)]
+    order: JsonOrder,
+    #[serde(default)]
+    customer: Option<String>,
+}
+
 /// Representa la lista de pedidos en el archivo JSON. Con esta estructura el crate serde realiza el parseo.
 #[derive(Deserialize)]
 struct OrdersConfiguration {
-    orders: Vec<JsonOrder>,
+    orders: Vec<JsonCustomerOrder>,
 }
 
-fn read_orders_from_file(path: String) -> Result<Vec<JsonOrder>, Box<dyn Error>> {
-    let file = File::open(Path::new(&path))?;
+pub(crate) fn read_orders_from_file(path: &str) -> Result<Vec<SourceOrder>, CoffeeMakerError> {
+    let file = File::open(Path::new(path)).map_err(|err| {
+        error!("[READER] Error opening {}: {:?}", path, err);
+        open_error(path, &err)
+    })?;
     let reader = BufReader::new(file);
-    let orders_config: OrdersConfiguration = serde_json::from_reader(reader)?;
-    Ok(orders_config.orders)
+    let orders_config: OrdersConfiguration = serde_json::from_reader(reader).map_err(|err| {
+        error!("[READER] Invalid orders file {}: {:?}", path, err);
+        CoffeeMakerError::malformed_orders(path, &err)
+    })?;
+    Ok(into_source_orders(orders_config))
+}
+
+/// Diferencia un archivo que no existe de uno que no se pudo abrir
+pub(crate) fn open_error(path: &str, err: &io::Error) -> CoffeeMakerError {
+    if err.kind() == io::ErrorKind::NotFound {
+        return CoffeeMakerError::FileNotFound {
+            path: path.to_string(),
+        };
+    }
+    CoffeeMakerError::FileReaderError {
+        path: path.to_string(),
+        reason: err.to_string(),
+    }
+}
+
+/// Parsea una lista de pedidos con el formato de `orders.json`. `source` indica de donde vienen, para los errores
+pub(crate) fn parse_orders(source: &str, json: &str) -> Result<Vec<SourceOrder>, CoffeeMakerError> {
+    let orders_config: OrdersConfiguration = serde_json::from_str(json)
+        .map_err(|err| CoffeeMakerError::malformed_orders(source, &err))?;
+    Ok(into_source_orders(orders_config))
+}
+
+fn into_source_orders(orders_config: OrdersConfiguration) -> Vec<SourceOrder> {
+    orders_config
+        .orders
+        .into_iter()
+        .map(into_source_order)
+        .collect()
+}
+
+fn into_source_order(order: JsonCustomerOrder) -> SourceOrder {
+    SourceOrder {
+        ingredients: get_ingredients_from_json_order(order.order),
+        customer: order.customer,
+    }
 }
 
+/// El control de admision se consulta antes de tomar el lock de la cola, para no tomar los contenedores con la cola tomada.
+/// El cliente de cada pedido es el que indica el pedido, o la fuente si no lo indica
 fn add_orders_to_queue(
-    json_orders: Vec<JsonOrder>,
-    orders_queue_lock: Arc<Mutex<OrdersQueue>>,
-    orders_cond: Arc<Condvar>,
+    source: &mut dyn OrderSource,
+    orders_queue_lock: &Mutex<OrdersQueue>,
+    orders_cond: &Condvar,
+    admission: &AdmissionControl,
 ) -> Result<(), CoffeeMakerError> {
-    let mut id = 0;
-    for order in json_orders {
-        let ingredients = get_ingredients_from_json_order(order);
-        if let Ok(mut queue) = orders_queue_lock.lock() {
-            queue.push(Order::new(id, ingredients));
-            debug!("[READER] Added order {}", id);
-            id += 1;
+    let source_name = source.name();
+    let closed = || {
+        orders_queue_lock
+            .lock_or_recover()
+            .map(|queue| queue.finished)
+            .unwrap_or(true)
+    };
+    while let Some(order) = source.next_order_until(&closed)? {
+        let customer = order.customer_or(&source_name);
+        let rejection = admission.check(&order.ingredients)?;
+        if let Ok(mut queue) = orders_queue_lock.lock_or_recover() {
+            if queue.finished {
+                info!("[READER] The queue was closed, stopping");
+                return Ok(());
+            }
+            let id = queue.next_id();
+            if let Some(rejection) = rejection {
+                queue.reject(id, rejection);
+                info!("[READER] Rejected order {}: {}", id, rejection);
+                continue;
+            }
+            queue.push(Order::new(id, order.ingredients).with_customer(customer.as_str()));
+            debug!("[READER] Added order {} from {}", id, customer);
             orders_cond.notify_all();
         } else {
             error!("[READER] Error while taking the queue lock");
             return Err(CoffeeMakerError::LockError);
         }
     }
-    info!("[READER] No more orders left");
-    if let Ok(mut queue) = orders_queue_lock.lock() {
-        queue.finished = true;
-        orders_cond.notify_all();
-        return Ok(());
-    }
-    Err(CoffeeMakerError::LockError)
+    info!("[READER] No more orders left on {}", source_name);
+    Ok(())
+}
+
+/// Parsea un unico pedido en formato JSON (por ejemplo, `{"ground_coffee": 10, "hot_water": 20, "cacao": 0, "milk_foam": 0}`)
+pub fn parse_json_order(json: &str) -> Result<Vec<(Ingredient, u64)>, CoffeeMakerError> {
+    let order: JsonOrder =
+        serde_json::from_str(json).map_err(|err| CoffeeMakerError::OrderFormatError {
+            reason: err.to_string(),
+        })?;
+    Ok(get_ingredients_from_json_order(order))
+}
+
+/// Como `parse_json_order`, pero conserva el cliente si el pedido lo indica
+pub(crate) fn parse_json_source_order(json: &str) -> Result<SourceOrder, CoffeeMakerError> {
+    let order: JsonCustomerOrder =
+        serde_json::from_str(json).map_err(|err| CoffeeMakerError::OrderFormatError {
+            reason: err.to_string(),
+        })?;
+    Ok(into_source_order(order))
 }
 
 fn get_ingredients_from_json_order(order: JsonOrder) -> Vec<(Ingredient, u64)> {
@@ -79,38 +160,49 @@ fn get_ingredients_from_json_order(order: JsonOrder) -> Vec<(Ingredient, u64)> {
     if 0 < order.milk_foam && order.milk_foam <= MAX_OF_INGREDIENT_IN_AN_ORDER {
         ingredients.push((Ingredient::MilkFoam, order.milk_foam));
     }
-    ingredients.shuffle(&mut thread_rng());
     ingredients
 }
 
+/// Lleva a la cola los pedidos de `source` hasta que se agote o se cierre la cola.
+/// La fuente ya tiene que estar registrada en la cola, al terminar (incluso con error) se la da de baja.
 pub fn read_and_add_orders(
     orders_queue: Arc<Mutex<OrdersQueue>>,
     orders_cond: Arc<Condvar>,
-    path: String,
-) -> Result<(), CoffeeMakerError> {
-    let result = read_orders_from_file(path);
-    match result {
-        Ok(json_orders) => add_orders_to_queue(json_orders, orders_queue, orders_cond),
-        Err(_) => handle_error_with_file(orders_queue, orders_cond),
-    }
-}
-
-fn handle_error_with_file(
-    orders_queue_lock: Arc<Mutex<OrdersQueue>>,
-    orders_cond: Arc<Condvar>,
+    admission: Arc<AdmissionControl>,
+    mut source: Box<dyn OrderSource>,
 ) -> Result<(), CoffeeMakerError> {
-    if let Ok(mut queue) = orders_queue_lock.lock() {
-        queue.finished = true;
-        orders_cond.notify_all();
+    let result = add_orders_to_queue(source.as_mut(), &orders_queue, &orders_cond, &admission);
+    if let Err(err) = &result {
+        error!("[READER] Error reading {}: {:?}", source.name(), err);
     }
-    error!("File reader error");
-    Err(CoffeeMakerError::FileReaderError)
+    let mut queue = orders_queue.lock_or_recover()?;
+    queue.finish_source();
+    orders_cond.notify_all();
+    result
 }
 
 #[cfg(test)]
+#[allow(
+    clippy::bool_assert_comparison,
+    clippy::err_expect,
+    clippy::vec_init_then_push
+)]
 mod tests {
+    use std::net::TcpListener;
+
+    use crate::order_source::{JsonFileSource, JsonLinesSource, VecSource};
+
     use super::*;
 
+    fn into_source(json_orders: Vec<JsonOrder>) -> Box<dyn OrderSource> {
+        Box::new(VecSource::new(
+            json_orders
+                .into_iter()
+                .map(get_ingredients_from_json_order)
+                .collect(),
+        ))
+    }
+
     #[test]
     fn should_get_the_ingredients_from_the_json_order() {
         let ingredients = get_ingredients_from_json_order(JsonOrder {
@@ -183,10 +275,16 @@ mod tests {
             milk_foam: 400,
         });
 
-        let queue = OrdersQueue::new();
+        let mut queue = OrdersQueue::new();
+        queue.register_source();
         let mutex = Arc::new(Mutex::new(queue));
         let cond = Arc::new(Condvar::new());
-        let result = add_orders_to_queue(json_orders, mutex.clone(), cond);
+        let result = read_and_add_orders(
+            mutex.clone(),
+            cond,
+            Arc::new(AdmissionControl::disabled()),
+            into_source(json_orders),
+        );
         assert!(result.is_ok());
 
         let mut queue = mutex.lock().expect("Test error");
@@ -196,16 +294,66 @@ mod tests {
         assert!(queue.pop().is_none());
     }
 
+    #[test]
+    fn should_stop_adding_orders_if_the_queue_was_closed() {
+        let json_orders = vec![JsonOrder {
+            ground_coffee: 10,
+            hot_water: 0,
+            cacao: 30,
+            milk_foam: 0,
+        }];
+
+        let mut queue = OrdersQueue::new();
+        queue.close();
+        let mutex = Arc::new(Mutex::new(queue));
+        let cond = Arc::new(Condvar::new());
+        let result = read_and_add_orders(
+            mutex.clone(),
+            cond,
+            Arc::new(AdmissionControl::disabled()),
+            into_source(json_orders),
+        );
+        assert!(result.is_ok());
+
+        let queue = mutex.lock().expect("Test error");
+        assert!(queue.finished);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn should_stop_waiting_for_a_silent_source_once_the_queue_is_closed() {
+        // La conexion queda pendiente en el listener, que nunca manda nada
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Error in test");
+        let address = listener.local_addr().expect("Error in test").to_string();
+        let source = JsonLinesSource::connect(&address).expect("Error in test");
+
+        let mut queue = OrdersQueue::new();
+        queue.register_source();
+        queue.close();
+        let mutex = Arc::new(Mutex::new(queue));
+        let result = read_and_add_orders(
+            mutex.clone(),
+            Arc::new(Condvar::new()),
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(source),
+        );
+        assert!(result.is_ok());
+        assert!(mutex.lock().expect("Error in test").is_empty());
+    }
+
     #[test]
     fn should_return_file_error_if_the_file_does_not_exists() {
         let result = read_and_add_orders(
             Arc::new(Mutex::new(OrdersQueue::new())),
             Arc::new(Condvar::new()),
-            String::from("not-a-file.json"),
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(JsonFileSource::new("not-a-file.json")),
         );
         assert!(result.is_err());
         assert_eq!(
-            CoffeeMakerError::FileReaderError,
+            CoffeeMakerError::FileNotFound {
+                path: String::from("not-a-file.json")
+            },
             result.err().expect("Fail test")
         )
     }
@@ -215,12 +363,76 @@ mod tests {
         let result = read_and_add_orders(
             Arc::new(Mutex::new(OrdersQueue::new())),
             Arc::new(Condvar::new()),
-            String::from("tests/wrong_format.json"),
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(JsonFileSource::new("tests/wrong_format.json")),
         );
         assert!(result.is_err());
+        assert!(matches!(
+            result.expect_err("Fail test"),
+            CoffeeMakerError::MalformedOrders { source, line, .. }
+                if source == "tests/wrong_format.json" && line > 0
+        ))
+    }
+
+    #[test]
+    fn should_finish_the_queue_only_when_all_the_sources_are_exhausted() {
+        let mut queue = OrdersQueue::new();
+        queue.register_source();
+        queue.register_source();
+        let mutex = Arc::new(Mutex::new(queue));
+        let cond = Arc::new(Condvar::new());
+
+        let result = read_and_add_orders(
+            mutex.clone(),
+            cond.clone(),
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(VecSource::new(vec![vec![(Ingredient::Cacao, 10)]])),
+        );
+        assert!(result.is_ok());
+        assert!(!mutex.lock().expect("Test error").finished);
+
+        let result = read_and_add_orders(
+            mutex.clone(),
+            cond,
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(JsonFileSource::new("tests/simple_order.json")),
+        );
+        assert!(result.is_ok());
+        let queue = mutex.lock().expect("Test error");
+        assert!(queue.finished);
+        assert_eq!(2, queue.len());
+    }
+
+    #[test]
+    fn should_take_the_customer_from_the_order_or_else_from_the_source() {
+        let mut queue = OrdersQueue::new();
+        queue.register_source();
+        let mutex = Arc::new(Mutex::new(queue));
+        let result = read_and_add_orders(
+            mutex.clone(),
+            Arc::new(Condvar::new()),
+            Arc::new(AdmissionControl::disabled()),
+            Box::new(JsonFileSource::new("tests/customer_orders.json")),
+        );
+        assert!(result.is_ok());
+
+        let mut queue = mutex.lock().expect("Test error");
+        let customers: Vec<String> = std::iter::from_fn(|| queue.pop())
+            .map(|order| order.customer)
+            .collect();
         assert_eq!(
-            CoffeeMakerError::FileReaderError,
-            result.err().expect("Fail test")
+            vec!["mesa 1", "mesa 2", "tests/customer_orders.json"],
+            customers
+        );
+    }
+
+    #[test]
+    fn should_parse_the_customer_of_a_json_line() {
+        let order = parse_json_source_order(
+            "{\"customer\": \"mesa 3\", \"ground_coffee\": 0, \"hot_water\": 0, \"cacao\": 5, \"milk_foam\": 0}",
         )
+        .expect("Test error");
+        assert_eq!(Some(String::from("mesa 3")), order.customer);
+        assert_eq!(vec![(Ingredient::Cacao, 5)], order.ingredients);
     }
 }
@@ -0,0 +1,178 @@
+//! Recuperacion de los locks envenenados. Un lock queda envenenado cuando un hilo se cae mientras lo tiene tomado,
+//! y a partir de ahi cualquier otro hilo que lo tome recibe un error. Para los contenedores y la cola de pedidos
+//! se valida el estado que quedo y, si es valido, se sigue usando en lugar de finalizar el hilo.
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use log::{error, warn};
+
+use crate::errors::CoffeeMakerError;
+
+/// Estado protegido por un lock que se puede seguir usando despues de que un hilo se cayo con el lock tomado
+pub trait Recoverable {
+    /// Nombre con el que se identifica el estado en los mensajes
+    const NAME: &'static str;
+
+    /// Verifica que el estado que dejo el hilo que se cayo sea valido. Si no lo es, devuelve el motivo
+    fn validate(&self) -> Result<(), String>;
+}
+
+/// Toma un lock recuperandolo si esta envenenado
+pub trait LockOrRecover<T> {
+    fn lock_or_recover(&self) -> Result<MutexGuard<'_, T>, CoffeeMakerError>;
+}
+
+impl<T: Recoverable> LockOrRecover<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> Result<MutexGuard<'_, T>, CoffeeMakerError> {
+        match self.lock() {
+            Ok(guard) => Ok(guard),
+            Err(poisoned) => recover(self, poisoned.into_inner()),
+        }
+    }
+}
+
+/// Igual que `Condvar::wait_while`, pero si el lock se envenena mientras se espera se lo recupera y se sigue esperando
+pub trait WaitOrRecover {
+    fn wait_while_or_recover<'a, T: Recoverable>(
+        &self,
+        mutex: &'a Mutex<T>,
+        condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<MutexGuard<'a, T>, CoffeeMakerError>;
+}
+
+impl WaitOrRecover for Condvar {
+    fn wait_while_or_recover<'a, T: Recoverable>(
+        &self,
+        mutex: &'a Mutex<T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<MutexGuard<'a, T>, CoffeeMakerError> {
+        let mut guard = mutex.lock_or_recover()?;
+        loop {
+            match self.wait_while(guard, &mut condition) {
+                Ok(guard) => return Ok(guard),
+                Err(poisoned) => guard = recover(mutex, poisoned.into_inner())?,
+            }
+        }
+    }
+}
+
+/// Valida el estado de un lock envenenado. Si es valido le quita el veneno, asi el resto de los hilos lo toma sin error
+fn recover<'a, T: Recoverable>(
+    mutex: &Mutex<T>,
+    guard: MutexGuard<'a, T>,
+) -> Result<MutexGuard<'a, T>, CoffeeMakerError> {
+    if let Err(reason) = guard.validate() {
+        error!(
+            "[RECOVERY] Poisoned {} lock can not be recovered: {}",
+            T::NAME,
+            reason
+        );
+        return Err(CoffeeMakerError::CorruptedState {
+            state: T::NAME,
+            reason,
+        });
+    }
+    mutex.clear_poison();
+    warn!(
+        "[RECOVERY] Recovered a poisoned {} lock, its state is valid",
+        T::NAME
+    );
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::*;
+
+    struct Counter {
+        value: u64,
+        max: u64,
+    }
+
+    impl Recoverable for Counter {
+        const NAME: &'static str = "counter";
+
+        fn validate(&self) -> Result<(), String> {
+            if self.value > self.max {
+                return Err(format!("{} is above {}", self.value, self.max));
+            }
+            Ok(())
+        }
+    }
+
+    /// Cambia el valor y se cae con el lock tomado
+    fn poison(mutex: &Mutex<Counter>, value: u64) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut counter = mutex.lock().expect("Error in test");
+            counter.value = value;
+            panic!("injected crash");
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_recover_a_poisoned_lock_with_a_valid_state() {
+        let mutex = Mutex::new(Counter { value: 0, max: 10 });
+        poison(&mutex, 5);
+        assert!(mutex.is_poisoned());
+
+        assert_eq!(5, mutex.lock_or_recover().expect("Error in test").value);
+        assert!(!mutex.is_poisoned());
+    }
+
+    #[test]
+    fn should_not_recover_a_poisoned_lock_with_an_invalid_state() {
+        let mutex = Mutex::new(Counter { value: 0, max: 10 });
+        poison(&mutex, 50);
+        assert!(mutex.is_poisoned());
+
+        assert_eq!(
+            Err(CoffeeMakerError::CorruptedState {
+                state: "counter",
+                reason: String::from("50 is above 10")
+            }),
+            mutex.lock_or_recover().map(|counter| counter.value)
+        );
+        assert!(mutex.is_poisoned());
+    }
+
+    #[test]
+    fn should_keep_waiting_after_recovering_a_lock_poisoned_while_waiting() {
+        let mutex = Arc::new(Mutex::new(Counter { value: 0, max: 10 }));
+        let cond = Arc::new(Condvar::new());
+        let waiting = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let mutex = mutex.clone();
+            let cond = cond.clone();
+            let waiting = waiting.clone();
+            thread::spawn(move || {
+                cond.wait_while_or_recover(&mutex, |counter| {
+                    waiting.store(true, Ordering::SeqCst);
+                    counter.value < 3
+                })
+                .map(|counter| counter.value)
+            })
+        };
+        while !waiting.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        // El lock recien se puede tomar cuando el otro hilo ya esta esperando
+        poison(&mutex, 1);
+        cond.notify_all();
+        {
+            let mut counter = mutex.lock_or_recover().expect("Error in test");
+            counter.value = 3;
+        }
+        cond.notify_all();
+
+        assert_eq!(Ok(3), waiter.join().expect("Error in test"));
+    }
+}
@@ -0,0 +1,393 @@
+//! Politicas para decidir que pedido de la cola toma el proximo dispenser
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde::Serialize;
+
+use crate::{
+    admission::{supplies, Supply},
+    container::Levels,
+    errors::CoffeeMakerError,
+    order::{Ingredient, Order, TOTAL_INGREDIENTS},
+};
+
+/// Orden en el que los dispensers toman los pedidos de la cola. Ante un empate siempre se respeta el orden de llegada
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchedulingPolicy {
+    /// En el orden en el que llegaron
+    Fifo,
+    /// Mientras alcance el stock para todos los pedidos pendientes, en el orden en el que llegaron.
+    /// Si algun ingrediente no alcanza, primero los pedidos que menos usan de los ingredientes escasos,
+    /// para servir la mayor cantidad de pedidos posible
+    InventoryAware,
+    /// Primero el pedido con menor cantidad total de ingredientes. Como preparar un ingrediente tarda lo mismo
+    /// que su cantidad, los pedidos grandes no demoran a los chicos que llegaron despues
+    #[serde(rename = "sjf")]
+    ShortestJobFirst,
+    /// Se turnan los clientes: primero el cliente al que hace mas tiempo que no se le toma un pedido,
+    /// asi un cliente con muchos pedidos no demora al resto
+    FairShare,
+}
+
+/// Politicas que se comparan en el analisis de factibilidad
+pub const ALL_SCHEDULING_POLICIES: [SchedulingPolicy; 4] = [
+    SchedulingPolicy::Fifo,
+    SchedulingPolicy::InventoryAware,
+    SchedulingPolicy::ShortestJobFirst,
+    SchedulingPolicy::FairShare,
+];
+
+impl FromStr for SchedulingPolicy {
+    type Err = CoffeeMakerError;
+
+    /// Acepta `fifo`, `inventory-aware`, `sjf` y `fair-share`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ALL_SCHEDULING_POLICIES
+            .into_iter()
+            .find(|policy| policy.name() == value)
+            .ok_or_else(|| CoffeeMakerError::InvalidSchedulingPolicy(value.to_string()))
+    }
+}
+
+/// Stock que se puede llegar a obtener de cada ingrediente entre todos los pedidos pendientes.
+/// Los ingredientes que no estan no se agotan
+pub type Availability = HashMap<Ingredient, u64>;
+
+/// Pedido pendiente, con lo que necesitan las politicas para elegir
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    pub customer: &'a str,
+    pub ingredients: &'a [(Ingredient, u64)],
+}
+
+/// Turno en el que se le tomo el ultimo pedido a cada cliente
+#[derive(Debug, Default)]
+pub struct DispatchHistory {
+    last_turn: HashMap<String, u64>,
+    turns: u64,
+}
+
+impl DispatchHistory {
+    fn record(&mut self, customer: &str) {
+        self.turns += 1;
+        match self.last_turn.get_mut(customer) {
+            Some(turn) => *turn = self.turns,
+            None => {
+                self.last_turn.insert(customer.to_string(), self.turns);
+            }
+        }
+    }
+
+    /// Los clientes que todavia no tuvieron turno van primero
+    fn last_turn(&self, customer: &str) -> u64 {
+        self.last_turn.get(customer).copied().unwrap_or(0)
+    }
+}
+
+impl SchedulingPolicy {
+    /// Nombre de la politica con el mismo formato que en la linea de comandos
+    pub fn name(&self) -> &'static str {
+        match self {
+            SchedulingPolicy::Fifo => "fifo",
+            SchedulingPolicy::InventoryAware => "inventory-aware",
+            SchedulingPolicy::ShortestJobFirst => "sjf",
+            SchedulingPolicy::FairShare => "fair-share",
+        }
+    }
+
+    /// Posicion en `pending` del proximo pedido a tomar, y lo registra en `history`.
+    /// `availability` solo se consulta si la politica lo necesita
+    pub fn select<'a>(
+        &self,
+        pending: impl Iterator<Item = Candidate<'a>> + Clone,
+        history: &mut DispatchHistory,
+        availability: impl FnOnce() -> Availability,
+    ) -> usize {
+        let index = match self {
+            SchedulingPolicy::Fifo => 0,
+            SchedulingPolicy::InventoryAware => select_under_scarcity(
+                pending.clone().map(|candidate| candidate.ingredients),
+                availability(),
+            ),
+            SchedulingPolicy::ShortestJobFirst => min_position(pending.clone(), |candidate| {
+                candidate
+                    .ingredients
+                    .iter()
+                    .map(|(_, quantity)| quantity)
+                    .sum::<u64>()
+            }),
+            SchedulingPolicy::FairShare => min_position(pending.clone(), |candidate| {
+                history.last_turn(candidate.customer)
+            }),
+        };
+        if let Some(candidate) = pending.clone().nth(index) {
+            history.record(candidate.customer);
+        }
+        index
+    }
+}
+
+/// Posicion del primer pedido con el menor `key`
+fn min_position<'a, K: Ord>(
+    pending: impl Iterator<Item = Candidate<'a>>,
+    key: impl Fn(&Candidate<'a>) -> K,
+) -> usize {
+    pending
+        .enumerate()
+        .min_by_key(|(index, candidate)| (key(candidate), *index))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+/// Busca los ingredientes de los que se piden mas de lo que hay, y elige el pedido que usa la menor proporcion
+/// de ellos. Es una heuristica: con un unico ingrediente escaso servir primero los pedidos mas chicos maximiza
+/// la cantidad de pedidos servidos. Ante un empate se respeta el orden de llegada.
+/// Se llama con la cola tomada, por eso se cuenta con arreglos indexados por ingrediente en lugar de mapas
+fn select_under_scarcity<'a>(
+    pending: impl Iterator<Item = &'a [(Ingredient, u64)]> + Clone,
+    availability: Availability,
+) -> usize {
+    let mut demand = [0; TOTAL_INGREDIENTS];
+    for ingredients in pending.clone() {
+        for (ingredient, quantity) in ingredients {
+            demand[*ingredient as usize] += quantity;
+        }
+    }
+    let mut scarce = [None; TOTAL_INGREDIENTS];
+    for (ingredient, available) in availability {
+        if demand[ingredient as usize] > available {
+            scarce[ingredient as usize] = Some(available);
+        }
+    }
+    if scarce.iter().all(Option::is_none) {
+        return 0;
+    }
+    let cost = |ingredients: &[(Ingredient, u64)]| -> f64 {
+        ingredients
+            .iter()
+            .filter_map(|(ingredient, quantity)| {
+                let available = scarce[*ingredient as usize]?;
+                Some(if available == 0 {
+                    f64::INFINITY
+                } else {
+                    *quantity as f64 / available as f64
+                })
+            })
+            .sum()
+    };
+    let mut selected = (0, f64::INFINITY);
+    for (index, ingredients) in pending.enumerate() {
+        let cost = cost(ingredients);
+        if cost < selected.1 {
+            selected = (index, cost);
+        }
+    }
+    selected.0
+}
+
+/// Stock de la cafetera visto desde la cola de pedidos. Se lee de los niveles compartidos, sin tomar el lock de
+/// ningun contenedor, ya que la cola esta tomada mientras se elige el pedido
+pub struct Stock {
+    levels: Arc<Levels>,
+    supplies: HashMap<Ingredient, Supply>,
+}
+
+impl Stock {
+    pub fn new(
+        levels: Arc<Levels>,
+        container_replenishers: &[(Ingredient, Ingredient)],
+        external_replenishers: &[Ingredient],
+    ) -> Stock {
+        Stock {
+            levels,
+            supplies: supplies(container_replenishers, external_replenishers),
+        }
+    }
+
+    /// Lo que queda en cada contenedor, mas lo que queda en su fuente si lo repone otro contenedor.
+    /// Los que tienen una fuente externa no se agotan
+    pub fn availability(&self) -> Availability {
+        let remaining = |ingredient: &Ingredient| {
+            self.levels
+                .get(ingredient)
+                .map(|level| level.remaining())
+                .unwrap_or(0)
+        };
+        self.levels
+            .keys()
+            .filter_map(|ingredient| match self.supplies.get(ingredient) {
+                None => Some((*ingredient, remaining(ingredient))),
+                Some(Supply::External) => None,
+                Some(Supply::Container(source)) => {
+                    Some((*ingredient, remaining(ingredient) + remaining(source)))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Politica de la cola junto con el stock y los turnos de los clientes que necesita para elegir
+pub struct Scheduler {
+    policy: SchedulingPolicy,
+    stock: Option<Stock>,
+    history: DispatchHistory,
+}
+
+impl Scheduler {
+    pub fn new(policy: SchedulingPolicy, stock: Stock) -> Scheduler {
+        Scheduler {
+            policy,
+            stock: Some(stock),
+            history: DispatchHistory::default(),
+        }
+    }
+
+    pub fn fifo() -> Scheduler {
+        Scheduler {
+            policy: SchedulingPolicy::Fifo,
+            stock: None,
+            history: DispatchHistory::default(),
+        }
+    }
+
+    pub fn policy(&self) -> SchedulingPolicy {
+        self.policy
+    }
+
+    /// Posicion en `orders` del proximo pedido a tomar
+    pub fn next(&mut self, orders: &VecDeque<Order>) -> usize {
+        let stock = &self.stock;
+        self.policy.select(
+            orders.iter().map(|order| Candidate {
+                customer: &order.customer,
+                ingredients: &order.ingredients,
+            }),
+            &mut self.history,
+            || stock.as_ref().map(Stock::availability).unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::container::AtomicLevel;
+
+    use super::*;
+
+    fn select(policy: SchedulingPolicy, pending: &[Vec<(Ingredient, u64)>], cacao: u64) -> usize {
+        policy.select(
+            pending.iter().map(|ingredients| Candidate {
+                customer: "",
+                ingredients,
+            }),
+            &mut DispatchHistory::default(),
+            || HashMap::from([(Ingredient::Cacao, cacao)]),
+        )
+    }
+
+    #[test]
+    fn should_take_the_orders_in_arrival_order_while_the_stock_is_enough() {
+        let pending = vec![
+            vec![(Ingredient::Cacao, 100), (Ingredient::HotWater, 500)],
+            vec![(Ingredient::Cacao, 10)],
+        ];
+
+        assert_eq!(0, select(SchedulingPolicy::InventoryAware, &pending, 110));
+        assert_eq!(0, select(SchedulingPolicy::Fifo, &pending, 0));
+    }
+
+    #[test]
+    fn should_take_first_the_order_that_uses_less_of_the_scarce_ingredient() {
+        let pending = vec![
+            vec![(Ingredient::Cacao, 100)],
+            vec![(Ingredient::Cacao, 20), (Ingredient::HotWater, 500)],
+            vec![(Ingredient::Cacao, 10)],
+            vec![(Ingredient::Cacao, 10)],
+        ];
+
+        assert_eq!(2, select(SchedulingPolicy::InventoryAware, &pending, 100));
+    }
+
+    #[test]
+    fn should_take_first_the_order_with_the_least_total_quantity() {
+        let pending = vec![
+            vec![(Ingredient::Cacao, 100)],
+            vec![(Ingredient::Cacao, 20), (Ingredient::HotWater, 30)],
+            vec![(Ingredient::GroundCoffee, 40)],
+            vec![(Ingredient::HotWater, 40)],
+        ];
+
+        assert_eq!(2, select(SchedulingPolicy::ShortestJobFirst, &pending, 0));
+    }
+
+    #[test]
+    fn should_take_turns_between_the_customers() {
+        let mut pending: VecDeque<(&str, usize)> =
+            [("a", 0), ("a", 1), ("a", 2), ("b", 3), ("c", 4)]
+                .into_iter()
+                .collect();
+        let mut history = DispatchHistory::default();
+        let mut taken = Vec::new();
+        while !pending.is_empty() {
+            let index = SchedulingPolicy::FairShare.select(
+                pending.iter().map(|(customer, _)| Candidate {
+                    customer,
+                    ingredients: &[],
+                }),
+                &mut history,
+                Availability::new,
+            );
+            taken.extend(pending.remove(index).map(|(_, order)| order));
+        }
+
+        assert_eq!(vec![0, 3, 4, 1, 2], taken);
+    }
+
+    #[test]
+    fn should_count_the_source_container_and_ignore_the_external_ones() {
+        let levels: Levels = [
+            (Ingredient::GroundCoffee, 10),
+            (Ingredient::GrainsToGrind, 30),
+            (Ingredient::HotWater, 0),
+            (Ingredient::Cacao, 5),
+        ]
+        .into_iter()
+        .map(|(ingredient, remaining)| {
+            let level = AtomicLevel::new(100);
+            level.set_remaining(remaining);
+            (ingredient, Arc::new(level))
+        })
+        .collect();
+        let stock = Stock::new(
+            Arc::new(levels),
+            &[(Ingredient::GrainsToGrind, Ingredient::GroundCoffee)],
+            &[Ingredient::HotWater],
+        );
+
+        let availability = stock.availability();
+        assert_eq!(Some(&40), availability.get(&Ingredient::GroundCoffee));
+        assert_eq!(Some(&5), availability.get(&Ingredient::Cacao));
+        assert_eq!(None, availability.get(&Ingredient::HotWater));
+    }
+
+    #[test]
+    fn should_parse_the_scheduling_policies() {
+        assert_eq!(Ok(SchedulingPolicy::Fifo), "fifo".parse());
+        assert_eq!(
+            Ok(SchedulingPolicy::InventoryAware),
+            "inventory-aware".parse()
+        );
+        assert_eq!(Ok(SchedulingPolicy::ShortestJobFirst), "sjf".parse());
+        assert_eq!(Ok(SchedulingPolicy::FairShare), "fair-share".parse());
+        assert_eq!(
+            Err(CoffeeMakerError::InvalidSchedulingPolicy(String::from(
+                "lifo"
+            ))),
+            "lifo".parse::<SchedulingPolicy>()
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! Fotos del estado de la cafetera que se guardan en disco para poder retomar luego de un reinicio
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::Clock,
+    constants::SNAPSHOT_INTERVAL_IN_MS,
+    container::Resources,
+    counters::OrderCounters,
+    dispenser::Dispenser,
+    epoch::TransferEpoch,
+    errors::CoffeeMakerError,
+    order::{Ingredient, Order, ALL_INGREDIENTS},
+    orders_queue::OrdersQueue,
+    poison::LockOrRecover,
+};
+
+/// Estado de un contenedor al momento de la foto
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerSnapshot {
+    pub ingredient: Ingredient,
+    pub remaining: u64,
+    pub consumed: u64,
+    pub finished: bool,
+}
+
+/// Pedido que seguia en la cola al momento de la foto
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingOrder {
+    pub id: usize,
+    pub ingredients: Vec<(Ingredient, u64)>,
+    /// Las fotos anteriores a que se guardara el cliente no lo tienen
+    #[serde(default)]
+    pub customer: String,
+}
+
+/// Pedido que se estaba preparando al momento de la foto, con lo que ya habia usado de cada contenedor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InFlightOrderSnapshot {
+    pub order: PendingOrder,
+    pub used: Vec<(Ingredient, u64)>,
+}
+
+/// Foto del estado de la cafetera. Los pedidos que estaban siendo preparados se guardan aparte de la cola,
+/// ya que lo que usaron de los contenedores ya figura como consumido.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoffeeMakerSnapshot {
+    pub orders_processed: u64,
+    pub containers: Vec<ContainerSnapshot>,
+    pub pending_orders: Vec<PendingOrder>,
+    /// Las fotos anteriores a que se guardaran los pedidos en preparacion no los tienen
+    #[serde(default)]
+    pub in_flight_orders: Vec<InFlightOrderSnapshot>,
+}
+
+impl CoffeeMakerSnapshot {
+    /// Toma una foto de los contenedores, la cola de pedidos, los pedidos que preparan los `dispensers`
+    /// y la cantidad de pedidos procesados.
+    /// Los pedidos en preparacion se leen antes que los contenedores, asi lo que usaron ya figura como consumido
+    pub fn take(
+        resources: &Resources,
+        orders_queue: &Mutex<OrdersQueue>,
+        dispensers: &[Arc<Dispenser>],
+        counters: &OrderCounters,
+    ) -> Result<CoffeeMakerSnapshot, CoffeeMakerError> {
+        let (pending_orders, in_flight_orders) = {
+            // Con la cola tomada ningun dispenser puede estar pasando un pedido de la cola a preparacion
+            let queue = orders_queue.lock_or_recover()?;
+            let pending_orders = queue.pending().map(PendingOrder::from).collect();
+            let mut in_flight_orders = Vec::new();
+            for dispenser in dispensers {
+                if let Some((order, used)) = dispenser.in_flight_order()? {
+                    in_flight_orders.push(InFlightOrderSnapshot {
+                        order: PendingOrder::from(&order),
+                        used,
+                    });
+                }
+            }
+            (pending_orders, in_flight_orders)
+        };
+
+        let mut containers = Vec::with_capacity(ALL_INGREDIENTS.len());
+        for ingredient in ALL_INGREDIENTS {
+            if let Some(container_lock) = resources.get(&ingredient) {
+                let container = container_lock.lock_or_recover()?;
+                containers.push(ContainerSnapshot {
+                    ingredient,
+                    remaining: container.remaining(),
+                    consumed: container.consumed(),
+                    finished: container.finished,
+                });
+            }
+        }
+
+        Ok(CoffeeMakerSnapshot {
+            orders_processed: counters.processed(),
+            containers,
+            pending_orders,
+            in_flight_orders,
+        })
+    }
+
+    /// Vuelca el estado de la foto sobre los contenedores y la cola de pedidos. Los pedidos que se estaban
+    /// preparando vuelven a la cola antes que los pendientes, y lo que ya habian usado se registra como desperdicio
+    pub fn restore(
+        &self,
+        resources: &Resources,
+        orders_queue: &Mutex<OrdersQueue>,
+        counters: &OrderCounters,
+    ) -> Result<(), CoffeeMakerError> {
+        for snapshot in &self.containers {
+            let mut container = resources
+                .get(&snapshot.ingredient)
+                .ok_or(CoffeeMakerError::IngredientNotInMap(snapshot.ingredient))?
+                .lock_or_recover()?;
+            container.level.set(snapshot.remaining, snapshot.consumed);
+            container.finished = snapshot.finished;
+        }
+        for in_flight in &self.in_flight_orders {
+            for (ingredient, quantity) in &in_flight.used {
+                resources
+                    .get(ingredient)
+                    .ok_or(CoffeeMakerError::IngredientNotInMap(*ingredient))?
+                    .lock_or_recover()?
+                    .wasted += quantity;
+            }
+        }
+
+        let mut queue = orders_queue.lock_or_recover()?;
+        let in_flight_orders = self
+            .in_flight_orders
+            .iter()
+            .map(|in_flight| &in_flight.order);
+        for order in in_flight_orders.chain(&self.pending_orders) {
+            queue.push(
+                Order::new(order.id, order.ingredients.clone())
+                    .with_customer(order.customer.as_str()),
+            );
+        }
+        counters.set_processed(self.orders_processed);
+        Ok(())
+    }
+
+    /// Guarda la foto en `path`. Se escribe primero a un archivo temporal y luego se renombra,
+    /// asi una caida a mitad de la escritura no deja una foto corrupta.
+    pub fn save(&self, path: &Path) -> Result<(), CoffeeMakerError> {
+        let json = serde_json::to_string(self).map_err(|err| snapshot_error(path, err))?;
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, json).map_err(|err| {
+            error!("[SNAPSHOT] Error writing {:?}: {:?}", temporary_path, err);
+            snapshot_error(&temporary_path, err)
+        })?;
+        fs::rename(&temporary_path, path).map_err(|err| {
+            error!("[SNAPSHOT] Error renaming to {:?}: {:?}", path, err);
+            snapshot_error(path, err)
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<CoffeeMakerSnapshot, CoffeeMakerError> {
+        let json = fs::read_to_string(path).map_err(|err| {
+            error!("[SNAPSHOT] Error reading {:?}: {:?}", path, err);
+            snapshot_error(path, err)
+        })?;
+        serde_json::from_str(&json).map_err(|err| {
+            error!("[SNAPSHOT] Invalid snapshot {:?}: {:?}", path, err);
+            snapshot_error(path, err)
+        })
+    }
+}
+
+impl From<&Order> for PendingOrder {
+    fn from(order: &Order) -> PendingOrder {
+        PendingOrder {
+            id: order.id,
+            ingredients: order.ingredients.clone(),
+            customer: order.customer.clone(),
+        }
+    }
+}
+
+fn snapshot_error(path: &Path, reason: impl ToString) -> CoffeeMakerError {
+    CoffeeMakerError::SnapshotError {
+        path: path.display().to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Guarda periodicamente la foto del estado de la cafetera hasta que se le indica que finalice,
+/// momento en el que guarda una ultima foto.
+pub struct SnapshotWriter {
+    path: PathBuf,
+    resources: Arc<Resources>,
+    orders_queue: Arc<Mutex<OrdersQueue>>,
+    dispensers: Vec<Arc<Dispenser>>,
+    counters: Arc<OrderCounters>,
+    epoch: Arc<TransferEpoch>,
+    clock: Arc<dyn Clock>,
+    finish: Mutex<bool>,
+}
+
+impl SnapshotWriter {
+    pub fn new(
+        path: PathBuf,
+        resources: Arc<Resources>,
+        orders_queue: Arc<Mutex<OrdersQueue>>,
+        dispensers: Vec<Arc<Dispenser>>,
+        counters: Arc<OrderCounters>,
+        epoch: Arc<TransferEpoch>,
+        clock: Arc<dyn Clock>,
+    ) -> SnapshotWriter {
+        SnapshotWriter {
+            path,
+            resources,
+            orders_queue,
+            dispensers,
+            counters,
+            epoch,
+            clock,
+            finish: Mutex::new(false),
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Ok(mut finish) = self.finish.lock() {
+            *finish = true;
+            return;
+        }
+        error!("Error setting snapshot thread to finish");
+    }
+
+    pub fn write_snapshots(&self) -> Result<(), CoffeeMakerError> {
+        loop {
+            let finished = *self.finish.lock()?;
+            if let Err(err) = self.write_snapshot() {
+                println!("[ERROR ON SNAPSHOT] {:?}", err);
+            }
+            if finished {
+                info!("[SNAPSHOT] Last snapshot saved on {:?}", self.path);
+                return Ok(());
+            }
+            self.clock
+                .wait(Duration::from_millis(SNAPSHOT_INTERVAL_IN_MS));
+        }
+    }
+
+    /// Guarda una foto tomada sin ninguna recarga a medias, asi al retomar no se pierde ni se duplica ningun recurso
+    pub fn write_snapshot(&self) -> Result<(), CoffeeMakerError> {
+        self.epoch
+            .read(|| {
+                CoffeeMakerSnapshot::take(
+                    &self.resources,
+                    &self.orders_queue,
+                    &self.dispensers,
+                    &self.counters,
+                )
+            })?
+            .save(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env, process};
+
+    use crate::container::Container;
+
+    use super::*;
+
+    fn create_resources(remaining: u64) -> Resources {
+        let mut resources = HashMap::new();
+        for ingredient in [Ingredient::Cacao, Ingredient::HotWater] {
+            let container = Container::new(100);
+            container.level.set(remaining, 100 - remaining);
+            resources.insert(ingredient, Arc::new(Mutex::new(container)));
+        }
+        resources
+    }
+
+    #[test]
+    fn should_restore_the_containers_and_the_pending_orders() {
+        let resources = create_resources(40);
+        resources
+            .get(&Ingredient::Cacao)
+            .expect("Error in test")
+            .lock()
+            .expect("Error in test")
+            .finished = true;
+        let orders_queue = Mutex::new(OrdersQueue::new());
+        orders_queue
+            .lock()
+            .expect("Error in test")
+            .push(Order::new(7, vec![(Ingredient::HotWater, 10)]));
+        let counters = OrderCounters::new();
+        counters.set_processed(3);
+        let snapshot = CoffeeMakerSnapshot::take(&resources, &orders_queue, &[], &counters)
+            .expect("Error in test");
+
+        let restored_resources = create_resources(100);
+        let restored_queue = Mutex::new(OrdersQueue::new());
+        let restored_counters = OrderCounters::new();
+        snapshot
+            .restore(&restored_resources, &restored_queue, &restored_counters)
+            .expect("Error in test");
+
+        let cacao = restored_resources
+            .get(&Ingredient::Cacao)
+            .expect("Error in test")
+            .lock()
+            .expect("Error in test");
+        assert_eq!(40, cacao.remaining());
+        assert_eq!(60, cacao.consumed());
+        assert!(cacao.finished);
+        let mut queue = restored_queue.lock().expect("Error in test");
+        let order = queue.pop().expect("Error in test");
+        assert_eq!(7, order.id);
+        assert_eq!(vec![(Ingredient::HotWater, 10)], order.ingredients);
+        assert_eq!(8, queue.next_id());
+        assert_eq!(3, restored_counters.processed());
+    }
+
+    #[test]
+    fn should_requeue_the_orders_in_preparation_first_and_waste_what_they_used() {
+        let snapshot = CoffeeMakerSnapshot {
+            orders_processed: 0,
+            containers: Vec::new(),
+            pending_orders: vec![PendingOrder {
+                id: 5,
+                ingredients: vec![(Ingredient::Cacao, 10)],
+                customer: String::new(),
+            }],
+            in_flight_orders: vec![InFlightOrderSnapshot {
+                order: PendingOrder {
+                    id: 4,
+                    ingredients: vec![(Ingredient::HotWater, 30), (Ingredient::Cacao, 20)],
+                    customer: String::new(),
+                },
+                used: vec![(Ingredient::HotWater, 30)],
+            }],
+        };
+        let resources = create_resources(100);
+        let orders_queue = Mutex::new(OrdersQueue::new());
+        snapshot
+            .restore(&resources, &orders_queue, &OrderCounters::new())
+            .expect("Error in test");
+
+        let water = resources
+            .get(&Ingredient::HotWater)
+            .expect("Error in test")
+            .lock()
+            .expect("Error in test");
+        assert_eq!(30, water.wasted);
+        let mut queue = orders_queue.lock().expect("Error in test");
+        assert_eq!(Some(4), queue.pop().map(|order| order.id));
+        assert_eq!(Some(5), queue.pop().map(|order| order.id));
+        assert_eq!(6, queue.next_id());
+    }
+
+    #[test]
+    fn should_save_and_load_a_snapshot() {
+        let path = env::temp_dir().join(format!("coffee-maker-snapshot-{}.json", process::id()));
+        let snapshot = CoffeeMakerSnapshot {
+            orders_processed: 2,
+            containers: vec![ContainerSnapshot {
+                ingredient: Ingredient::GroundCoffee,
+                remaining: 10,
+                consumed: 20,
+                finished: false,
+            }],
+            pending_orders: vec![PendingOrder {
+                id: 3,
+                ingredients: vec![(Ingredient::MilkFoam, 5)],
+                customer: String::from("tcp://localhost:7000"),
+            }],
+            in_flight_orders: vec![InFlightOrderSnapshot {
+                order: PendingOrder {
+                    id: 2,
+                    ingredients: vec![(Ingredient::Cacao, 5), (Ingredient::HotWater, 10)],
+                    customer: String::from("stdin"),
+                },
+                used: vec![(Ingredient::Cacao, 5)],
+            }],
+        };
+        snapshot.save(&path).expect("Error in test");
+        let loaded = CoffeeMakerSnapshot::load(&path).expect("Error in test");
+        _ = fs::remove_file(&path);
+        assert_eq!(snapshot, loaded);
+    }
+
+    #[test]
+    fn should_fail_to_load_a_missing_snapshot() {
+        let result = CoffeeMakerSnapshot::load(Path::new("not-a-snapshot.json"));
+        assert!(matches!(
+            result,
+            Err(CoffeeMakerError::SnapshotError { path, .. }) if path == "not-a-snapshot.json"
+        ));
+    }
+}
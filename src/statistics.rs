@@ -1,52 +1,97 @@
-//! Impresion de las estadisticas de la cafetera
+//! Recoleccion periodica de las estadisticas de la cafetera. El formato y el destino de cada
+//! reporte lo define cada `StatisticsSink`
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, RwLock},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use log::error;
+use serde::Serialize;
 
 use crate::{
-    constants::{
-        C_CACAO_STORAGE, L_MILK_STORAGE, M_COFFEE_STORAGE, STATISTICS_WAIT_IN_MS,
-        X_PERCENTAGE_OF_CAPACITY,
-    },
-    container::Container,
+    clock::Clock,
+    constants::{STATISTICS_WAIT_IN_MS, X_PERCENTAGE_OF_CAPACITY},
+    container::{Container, Levels},
+    counters::OrderCounters,
+    epoch::TransferEpoch,
     errors::CoffeeMakerError,
-    order::Ingredient,
+    latency::{LatencyHistograms, LatencySummary},
+    order::{Ingredient, ALL_INGREDIENTS},
+    orders_queue::OrdersQueue,
+    poison::LockOrRecover,
+    scheduling::SchedulingPolicy,
+    statistics_sink::StatisticsSink,
+    supervisor::Incident,
 };
 
-mod sync {
-    use std::thread;
-    use std::time::Duration;
-
-    #[cfg(not(test))]
-    pub(crate) fn sleep(d: Duration) {
-        thread::sleep(d);
-    }
+/// Estado de un contenedor al momento de recolectar las estadisticas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ContainerStatistics {
+    pub ingredient: Ingredient,
+    pub remaining: u64,
+    pub consumed: u64,
+    pub capacity: u64,
+}
 
-    #[cfg(test)]
-    pub(crate) fn sleep(_: Duration) {
-        thread::yield_now();
-    }
+/// Estadisticas de la cafetera en un momento dado. Todos los sinks reciben la misma en cada ronda
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatisticsSnapshot {
+    /// Numero de ronda de recoleccion, empieza en 0
+    pub tick: u64,
+    /// Tiempo transcurrido segun el reloj de la cafetera
+    pub elapsed_ms: u64,
+    pub orders_processed: u64,
+    /// Contenedores en el orden de `ALL_INGREDIENTS`
+    pub containers: Vec<ContainerStatistics>,
+    pub paused_dispensers: Vec<usize>,
+    pub latencies: LatencySummary,
+    /// Politica con la que se toman los pedidos de la cola, para poder comparar las latencias entre ejecuciones
+    pub scheduling: SchedulingPolicy,
+    /// Caidas de los dispensers y reponedores hasta el momento
+    pub incidents: Vec<Incident>,
 }
 
-/// Estructura que se va a encargar de imprimir periodicamente las estadisticas de la cafetera
+/// Estructura que se va a encargar de recolectar periodicamente las estadisticas de la cafetera
+/// y entregarselas a cada uno de sus sinks
 pub struct StatisticsPrinter {
-    pub processed: Arc<RwLock<u64>>,
+    pub counters: Arc<OrderCounters>,
     pub resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+    levels: Arc<Levels>,
+    pub latencies: Arc<Mutex<LatencyHistograms>>,
+    orders_queue: Arc<Mutex<OrdersQueue>>,
+    total_dispensers: usize,
+    pub epoch: Arc<TransferEpoch>,
+    pub incidents: Arc<Mutex<Vec<Incident>>>,
+    sinks: Vec<Arc<dyn StatisticsSink>>,
+    clock: Arc<dyn Clock>,
     finish: Arc<Mutex<bool>>,
 }
 
 impl StatisticsPrinter {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        processed: Arc<RwLock<u64>>,
+        counters: Arc<OrderCounters>,
         resources: Arc<HashMap<Ingredient, Arc<Mutex<Container>>>>,
+        levels: Arc<Levels>,
+        latencies: Arc<Mutex<LatencyHistograms>>,
+        orders_queue: Arc<Mutex<OrdersQueue>>,
+        total_dispensers: usize,
+        epoch: Arc<TransferEpoch>,
+        sinks: Vec<Arc<dyn StatisticsSink>>,
+        clock: Arc<dyn Clock>,
     ) -> StatisticsPrinter {
         StatisticsPrinter {
-            processed,
+            counters,
             resources,
+            levels,
+            latencies,
+            orders_queue,
+            total_dispensers,
+            epoch,
+            incidents: Arc::new(Mutex::new(Vec::new())),
+            sinks,
+            clock,
             finish: Arc::new(Mutex::new(false)),
         }
     }
@@ -59,70 +104,104 @@ impl StatisticsPrinter {
         error!("Error setting statistics thread to finish");
     }
 
+    /// Recolecta y reporta las estadisticas hasta que se le indica que finalice.
+    /// La ultima recoleccion se reporta como final
     pub fn process_statistics(&self) -> Result<(), CoffeeMakerError> {
+        let mut tick = 0;
         loop {
-            if *self.finish.lock()? {
-                self.print_statistics()?;
+            let finished = *self.finish.lock()?;
+            let snapshot = self.take_snapshot(tick)?;
+            self.report(&snapshot, finished);
+            if finished {
                 return Ok(());
             }
-
-            self.print_statistics()?;
-
-            sync::sleep(Duration::from_millis(STATISTICS_WAIT_IN_MS));
+            tick += 1;
+            self.clock
+                .wait(Duration::from_millis(STATISTICS_WAIT_IN_MS));
         }
     }
 
-    fn print_statistics(&self) -> Result<(), CoffeeMakerError> {
-        let orders_processed = self.get_orders_processed()?;
-        let mut statistics = format!(
-            "[STATISTICS] Orders processed={} | Ingredient=(remaining, consumed) |",
-            orders_processed
-        );
-        self.add_resources_to_statistics_string(&mut statistics)?;
-        println!("{}", statistics);
-        Ok(())
+    /// Devuelve los percentiles de las latencias medidas hasta el momento
+    pub fn latency_summary(&self) -> Result<LatencySummary, CoffeeMakerError> {
+        Ok(self.latencies.lock()?.summary())
     }
 
-    fn add_resources_to_statistics_string(
-        &self,
-        statistics: &mut String,
-    ) -> Result<(), CoffeeMakerError> {
-        for (ingredient, container_lock) in self.resources.iter() {
-            let container = container_lock
-                .lock()
-                .map_err(|_| CoffeeMakerError::LockError)?;
-            statistics.push_str(&format!(
-                " {:?}=({},{}) ",
-                ingredient, container.remaining, container.consumed
-            ));
-            print_warning_if_below_x_level(ingredient, container.remaining);
-        }
-        Ok(())
+    /// Recolecta las estadisticas de la cafetera en este momento. Los contenedores y los pedidos procesados
+    /// se leen sin ninguna recarga a medias, asi lo que sale de un contenedor fuente siempre aparece en su destino.
+    /// No se toma el lock de ningun contenedor, los niveles y los contadores son atomicos
+    pub fn take_snapshot(&self, tick: u64) -> Result<StatisticsSnapshot, CoffeeMakerError> {
+        let (containers, orders_processed) = self.epoch.read(|| {
+            let mut containers = Vec::with_capacity(ALL_INGREDIENTS.len());
+            for ingredient in ALL_INGREDIENTS {
+                if let Some(level) = self.levels.get(&ingredient) {
+                    let (remaining, consumed) = level.load();
+                    containers.push(ContainerStatistics {
+                        ingredient,
+                        remaining,
+                        consumed,
+                        capacity: level.capacity(),
+                    });
+                }
+            }
+            Ok((containers, self.counters.processed()))
+        })?;
+        let (paused_dispensers, scheduling) = {
+            let queue = self.orders_queue.lock_or_recover()?;
+            (
+                queue.paused_dispensers(self.total_dispensers),
+                queue.scheduling_policy(),
+            )
+        };
+
+        Ok(StatisticsSnapshot {
+            tick,
+            elapsed_ms: self.clock.now().as_millis() as u64,
+            orders_processed,
+            containers,
+            paused_dispensers,
+            latencies: self.latency_summary()?,
+            scheduling,
+            incidents: self.incidents.lock()?.clone(),
+        })
     }
 
-    fn get_orders_processed(&self) -> Result<u64, CoffeeMakerError> {
-        let processed = *self
-            .processed
-            .read()
-            .map_err(|_| CoffeeMakerError::LockError)?;
-        Ok(processed)
+    /// Entrega la misma foto a todos los sinks. Si uno falla se sigue con el resto
+    pub(crate) fn report(&self, snapshot: &StatisticsSnapshot, last: bool) {
+        for sink in &self.sinks {
+            let result = if last {
+                sink.report_final(snapshot)
+            } else {
+                sink.report(snapshot)
+            };
+            if let Err(err) = result {
+                error!("[STATISTICS] Error reporting to {}: {:?}", sink.name(), err);
+            }
+        }
     }
 }
 
-fn print_warning_if_below_x_level(ingredient: &Ingredient, remaining: u64) {
+/// Indica si el contenedor del ingrediente se encuentra por debajo del nivel de alerta,
+/// calculado sobre la capacidad configurada. Solo se alerta de los contenedores de granos,
+/// leche y cacao.
+pub fn is_below_warning_level(ingredient: &Ingredient, remaining: u64, capacity: u64) -> bool {
     match ingredient {
-        Ingredient::Cacao => handle_warning_level(ingredient, remaining, C_CACAO_STORAGE),
-        Ingredient::ColdMilk => handle_warning_level(ingredient, remaining, L_MILK_STORAGE),
-        Ingredient::GrainsToGrind => handle_warning_level(ingredient, remaining, M_COFFEE_STORAGE),
-        _ => {}
+        Ingredient::Cacao | Ingredient::ColdMilk | Ingredient::GrainsToGrind => {
+            remaining < (capacity * X_PERCENTAGE_OF_CAPACITY) / 100
+        }
+        _ => false,
     }
 }
 
-fn handle_warning_level(ingredient: &Ingredient, remaining: u64, initial_level: u64) {
-    if remaining < (initial_level * X_PERCENTAGE_OF_CAPACITY) / 100 {
-        println!(
-            "[WARNING] {:?} container below {}% capacity at {}",
-            ingredient, X_PERCENTAGE_OF_CAPACITY, remaining
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_the_warning_level_over_the_configured_capacity() {
+        assert!(is_below_warning_level(&Ingredient::Cacao, 150, 1000));
+        assert!(!is_below_warning_level(&Ingredient::Cacao, 250, 1000));
+        assert!(!is_below_warning_level(&Ingredient::ColdMilk, 2500, 10000));
+        assert!(is_below_warning_level(&Ingredient::ColdMilk, 2500, 20000));
+        assert!(!is_below_warning_level(&Ingredient::HotWater, 0, 1000));
     }
 }
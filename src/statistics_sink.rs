@@ -0,0 +1,461 @@
+//! Destinos de las estadisticas de la cafetera. Cada sink recibe en cada ronda la misma foto
+//! de las estadisticas y decide como mostrarla o guardarla.
+use std::{
+    fs::File,
+    io::{self, LineWriter, Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use log::error;
+
+use crate::{
+    constants::X_PERCENTAGE_OF_CAPACITY,
+    errors::CoffeeMakerError,
+    latency::Percentiles,
+    statistics::{is_below_warning_level, ContainerStatistics, StatisticsSnapshot},
+    supervisor::Incident,
+};
+
+/// Destino de las estadisticas. `report` se llama en cada ronda de recoleccion y `report_final`
+/// una unica vez al finalizar la cafetera, en lugar de `report`.
+pub trait StatisticsSink: Send + Sync {
+    /// Nombre con el que se identifica al sink en los mensajes
+    fn name(&self) -> String;
+
+    fn report(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError>;
+
+    fn report_final(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        self.report(snapshot)
+    }
+}
+
+/// Imprime las estadisticas por pantalla con lineas `[STATISTICS]`, las alertas de bajo nivel con lineas `[WARNING]`
+/// y al finalizar los percentiles de las latencias con lineas `[LATENCY]` y las caidas de los hilos con lineas `[INCIDENT]`
+pub struct StdoutSink {
+    periodic: Mutex<bool>,
+}
+
+impl StdoutSink {
+    pub fn new() -> StdoutSink {
+        StdoutSink {
+            periodic: Mutex::new(true),
+        }
+    }
+
+    /// Indica si se imprimen las estadisticas en cada ronda o solo las finales. Se desactiva cuando el panel ocupa la terminal
+    pub fn set_periodic(&self, periodic: bool) -> Result<(), CoffeeMakerError> {
+        *self.periodic.lock()? = periodic;
+        Ok(())
+    }
+
+    fn print_statistics(&self, snapshot: &StatisticsSnapshot) {
+        let mut statistics = format!(
+            "[STATISTICS] Orders processed={} | Ingredient=(remaining, consumed) |",
+            snapshot.orders_processed
+        );
+        for container in &snapshot.containers {
+            statistics.push_str(&format!(
+                " {:?}=({},{}) ",
+                container.ingredient, container.remaining, container.consumed
+            ));
+            if is_below_warning_level(
+                &container.ingredient,
+                container.remaining,
+                container.capacity,
+            ) {
+                println!(
+                    "[WARNING] {:?} container below {}% capacity at {}",
+                    container.ingredient, X_PERCENTAGE_OF_CAPACITY, container.remaining
+                )
+            }
+        }
+        if !snapshot.paused_dispensers.is_empty() {
+            statistics.push_str(&format!(
+                "| Paused dispensers={:?}",
+                snapshot.paused_dispensers
+            ));
+        }
+        println!("{}", statistics);
+    }
+}
+
+impl Default for StdoutSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatisticsSink for StdoutSink {
+    fn name(&self) -> String {
+        String::from("stdout")
+    }
+
+    fn report(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        if *self.periodic.lock()? {
+            self.print_statistics(snapshot);
+        }
+        Ok(())
+    }
+
+    fn report_final(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        self.print_statistics(snapshot);
+        println!("[LATENCY] Scheduling policy={}", snapshot.scheduling.name());
+        print_percentiles("Queue wait", &snapshot.latencies.queue_wait);
+        print_percentiles("Preparation", &snapshot.latencies.preparation);
+        print_percentiles("Replenishment wait", &snapshot.latencies.replenishment_wait);
+        for incident in &snapshot.incidents {
+            print_incident(incident);
+        }
+        Ok(())
+    }
+}
+
+fn print_percentiles(name: &str, percentiles: &Percentiles) {
+    println!(
+        "[LATENCY] {} (us) | count={} p50={} p90={} p99={} max={}",
+        name, percentiles.count, percentiles.p50, percentiles.p90, percentiles.p99, percentiles.max
+    );
+}
+
+fn print_incident(incident: &Incident) {
+    let mut line = format!(
+        "[INCIDENT] {} crashed at {}ms: {}",
+        incident.worker, incident.elapsed_ms, incident.cause
+    );
+    if let Some(order) = incident.requeued_order {
+        line.push_str(&format!(" | order {} returned to the queue", order));
+    }
+    line.push_str(if incident.restarted {
+        " | restarted"
+    } else {
+        " | not restarted, out of restarts"
+    });
+    println!("{}", line);
+}
+
+/// Escribe cada foto de las estadisticas como una linea JSON del archivo. El archivo se trunca al crear el sink
+pub struct JsonFileSink {
+    name: String,
+    writer: Mutex<LineWriter<File>>,
+}
+
+impl JsonFileSink {
+    pub fn create(path: &Path) -> Result<JsonFileSink, CoffeeMakerError> {
+        let file = File::create(path).map_err(|err| {
+            error!("[STATISTICS] Error creating {:?}: {:?}", path, err);
+            CoffeeMakerError::FileReaderError {
+                path: path.display().to_string(),
+                reason: err.to_string(),
+            }
+        })?;
+        Ok(JsonFileSink {
+            name: path.display().to_string(),
+            writer: Mutex::new(LineWriter::new(file)),
+        })
+    }
+}
+
+impl StatisticsSink for JsonFileSink {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn report(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        let write_error = |reason: String| CoffeeMakerError::FileReaderError {
+            path: self.name.clone(),
+            reason,
+        };
+        let json = serde_json::to_string(snapshot).map_err(|err| write_error(err.to_string()))?;
+        writeln!(self.writer.lock()?, "{}", json).map_err(|err| write_error(err.to_string()))
+    }
+}
+
+/// Guarda la ultima foto de las estadisticas para exponerla en el formato de texto de Prometheus.
+/// Con `serve` se atiende por HTTP en un hilo propio hasta que termina el proceso
+pub struct MetricsSink {
+    latest: Mutex<Option<StatisticsSnapshot>>,
+}
+
+impl MetricsSink {
+    pub fn new() -> MetricsSink {
+        MetricsSink {
+            latest: Mutex::new(None),
+        }
+    }
+
+    /// Metricas de la ultima foto recibida. Vacio si todavia no se recibio ninguna
+    pub fn render(&self) -> Result<String, CoffeeMakerError> {
+        let Some(snapshot) = self.latest.lock()?.clone() else {
+            return Ok(String::new());
+        };
+        let mut metrics = String::new();
+        metrics.push_str("# TYPE coffee_maker_orders_processed counter\n");
+        metrics.push_str(&format!(
+            "coffee_maker_orders_processed {}\n",
+            snapshot.orders_processed
+        ));
+        metrics.push_str("# TYPE coffee_maker_paused_dispensers gauge\n");
+        metrics.push_str(&format!(
+            "coffee_maker_paused_dispensers {}\n",
+            snapshot.paused_dispensers.len()
+        ));
+        metrics.push_str("# TYPE coffee_maker_worker_incidents counter\n");
+        metrics.push_str(&format!(
+            "coffee_maker_worker_incidents {}\n",
+            snapshot.incidents.len()
+        ));
+        push_container_metric(&mut metrics, "remaining", "gauge", &snapshot, |container| {
+            container.remaining
+        });
+        push_container_metric(
+            &mut metrics,
+            "consumed",
+            "counter",
+            &snapshot,
+            |container| container.consumed,
+        );
+        push_container_metric(&mut metrics, "capacity", "gauge", &snapshot, |container| {
+            container.capacity
+        });
+        metrics.push_str("# TYPE coffee_maker_latency_us summary\n");
+        for (kind, percentiles) in [
+            ("queue_wait", &snapshot.latencies.queue_wait),
+            ("preparation", &snapshot.latencies.preparation),
+            ("replenishment_wait", &snapshot.latencies.replenishment_wait),
+        ] {
+            for (quantile, value) in [
+                ("0.5", percentiles.p50),
+                ("0.9", percentiles.p90),
+                ("0.99", percentiles.p99),
+            ] {
+                metrics.push_str(&format!(
+                    "coffee_maker_latency_us{{kind=\"{}\",policy=\"{}\",quantile=\"{}\"}} {}\n",
+                    kind,
+                    snapshot.scheduling.name(),
+                    quantile,
+                    value
+                ));
+            }
+            metrics.push_str(&format!(
+                "coffee_maker_latency_us_count{{kind=\"{}\",policy=\"{}\"}} {}\n",
+                kind,
+                snapshot.scheduling.name(),
+                percentiles.count
+            ));
+        }
+        Ok(metrics)
+    }
+
+    /// Atiende cualquier pedido HTTP en `address` respondiendo con las metricas. Devuelve la direccion
+    /// en la que quedo escuchando (util si se usa el puerto 0)
+    pub fn serve(self: &Arc<Self>, address: &str) -> Result<SocketAddr, CoffeeMakerError> {
+        let listener = TcpListener::bind(address).map_err(|err| {
+            error!("[METRICS] Error binding {}: {:?}", address, err);
+            socket_error(address, err)
+        })?;
+        let local_address = listener
+            .local_addr()
+            .map_err(|err| socket_error(address, err))?;
+        let sink = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut request = [0; 1024];
+                if stream.read(&mut request).is_err() {
+                    continue;
+                }
+                let body = sink.render().unwrap_or_default();
+                let response = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                if let Err(err) = stream.write_all(response.as_bytes()) {
+                    error!("[METRICS] Error answering a request: {:?}", err);
+                }
+            }
+        });
+        Ok(local_address)
+    }
+}
+
+fn socket_error(address: &str, err: io::Error) -> CoffeeMakerError {
+    CoffeeMakerError::SocketError {
+        path: address.to_string(),
+        reason: err.to_string(),
+    }
+}
+
+fn push_container_metric(
+    metrics: &mut String,
+    metric: &str,
+    kind: &str,
+    snapshot: &StatisticsSnapshot,
+    value_of: fn(&ContainerStatistics) -> u64,
+) {
+    metrics.push_str(&format!(
+        "# TYPE coffee_maker_container_{} {}\n",
+        metric, kind
+    ));
+    for container in &snapshot.containers {
+        metrics.push_str(&format!(
+            "coffee_maker_container_{}{{ingredient=\"{}\"}} {}\n",
+            metric,
+            container.ingredient.name(),
+            value_of(container)
+        ));
+    }
+}
+
+impl Default for MetricsSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatisticsSink for MetricsSink {
+    fn name(&self) -> String {
+        String::from("metrics")
+    }
+
+    fn report(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        *self.latest.lock()? = Some(snapshot.clone());
+        Ok(())
+    }
+}
+
+/// Guarda en memoria todas las fotos recibidas, util para tests o para inspeccionarlas luego de ejecutar
+pub struct MemorySink {
+    snapshots: Mutex<Vec<StatisticsSnapshot>>,
+    finished: Mutex<bool>,
+}
+
+impl MemorySink {
+    pub fn new() -> MemorySink {
+        MemorySink {
+            snapshots: Mutex::new(Vec::new()),
+            finished: Mutex::new(false),
+        }
+    }
+
+    pub fn snapshots(&self) -> Result<Vec<StatisticsSnapshot>, CoffeeMakerError> {
+        Ok(self.snapshots.lock()?.clone())
+    }
+
+    /// Indica si ya se recibio la foto final
+    pub fn is_finished(&self) -> Result<bool, CoffeeMakerError> {
+        Ok(*self.finished.lock()?)
+    }
+}
+
+impl Default for MemorySink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatisticsSink for MemorySink {
+    fn name(&self) -> String {
+        String::from("memory")
+    }
+
+    fn report(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        self.snapshots.lock()?.push(snapshot.clone());
+        Ok(())
+    }
+
+    fn report_final(&self, snapshot: &StatisticsSnapshot) -> Result<(), CoffeeMakerError> {
+        self.report(snapshot)?;
+        *self.finished.lock()? = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::BufRead, io::BufReader, net::TcpStream, process};
+
+    use crate::{
+        latency::LatencyHistograms, order::Ingredient, scheduling::SchedulingPolicy,
+        statistics::StatisticsSnapshot,
+    };
+
+    use super::*;
+
+    fn snapshot(tick: u64) -> StatisticsSnapshot {
+        StatisticsSnapshot {
+            tick,
+            elapsed_ms: tick * 50,
+            orders_processed: tick,
+            containers: vec![ContainerStatistics {
+                ingredient: Ingredient::Cacao,
+                remaining: 90,
+                consumed: 10,
+                capacity: 100,
+            }],
+            paused_dispensers: Vec::new(),
+            latencies: LatencyHistograms::new().summary(),
+            scheduling: SchedulingPolicy::ShortestJobFirst,
+            incidents: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn should_write_a_json_line_per_snapshot() {
+        let path = env::temp_dir().join(format!("coffee-maker-statistics-{}.jsonl", process::id()));
+        let sink = JsonFileSink::create(&path).expect("Error in test");
+        sink.report(&snapshot(0)).expect("Error in test");
+        sink.report_final(&snapshot(1)).expect("Error in test");
+        drop(sink);
+
+        let lines: Vec<serde_json::Value> =
+            BufReader::new(File::open(&path).expect("Error in test"))
+                .lines()
+                .map(|line| {
+                    serde_json::from_str(&line.expect("Error in test")).expect("Error in test")
+                })
+                .collect();
+        _ = fs::remove_file(&path);
+        assert_eq!(2, lines.len());
+        assert_eq!(1, lines[1]["orders_processed"]);
+        assert_eq!("cacao", lines[1]["containers"][0]["ingredient"]);
+        assert_eq!("sjf", lines[1]["scheduling"]);
+    }
+
+    #[test]
+    fn should_serve_the_latest_snapshot_as_metrics() {
+        let sink = Arc::new(MetricsSink::new());
+        sink.report(&snapshot(3)).expect("Error in test");
+        let address = sink.serve("127.0.0.1:0").expect("Error in test");
+
+        let mut stream = TcpStream::connect(address).expect("Error in test");
+        stream
+            .write_all(b"GET /metrics HTTP/1.0\r\n\r\n")
+            .expect("Error in test");
+        let mut response = String::new();
+        stream.read_to_string(&mut response).expect("Error in test");
+        assert!(response.starts_with("HTTP/1.0 200 OK"));
+        assert!(response.contains("coffee_maker_orders_processed 3\n"));
+        assert!(response.contains("coffee_maker_container_remaining{ingredient=\"cacao\"} 90\n"));
+        assert!(response
+            .contains("coffee_maker_latency_us_count{kind=\"queue_wait\",policy=\"sjf\"} 0\n"));
+    }
+
+    #[test]
+    fn should_keep_every_snapshot_in_memory() {
+        let sink = MemorySink::new();
+        sink.report(&snapshot(0)).expect("Error in test");
+        assert!(!sink.is_finished().expect("Error in test"));
+        sink.report_final(&snapshot(1)).expect("Error in test");
+        assert!(sink.is_finished().expect("Error in test"));
+        assert_eq!(
+            vec![snapshot(0), snapshot(1)],
+            sink.snapshots().expect("Error in test")
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Supervisor de los hilos de la cafetera. Detecta cuando un dispenser o un reponedor se cae y lo reinicia
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::{error, warn};
+use serde::Serialize;
+
+use crate::{clock::Clock, errors::CoffeeMakerError, order::Ingredient};
+
+/// Trabajo que corre en un hilo supervisado
+pub trait Worker: Send + Sync {
+    /// Trabaja hasta que finaliza la cafetera
+    fn work(&self) -> Result<(), CoffeeMakerError>;
+
+    /// Deja el estado compartido listo para volver a empezar despues de una caida.
+    /// Devuelve el pedido que se devolvio a la cola, si habia uno a medio preparar
+    fn recover(&self) -> Result<Option<usize>, CoffeeMakerError> {
+        Ok(None)
+    }
+
+    /// Se llama cuando se agoto el presupuesto de reinicios y el hilo no se vuelve a iniciar
+    fn give_up(&self) {}
+}
+
+/// Identifica a un hilo supervisado
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerId {
+    Dispenser(usize),
+    /// Reponedor del contenedor indicado a partir de otro contenedor
+    ContainerReplenisher(Ingredient),
+    /// Reponedor del contenedor indicado a partir de una fuente externa
+    ExternalReplenisher(Ingredient),
+}
+
+/// Tambien es el nombre del hilo
+impl fmt::Display for WorkerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerId::Dispenser(id) => write!(f, "dispenser {}", id),
+            WorkerId::ContainerReplenisher(ingredient) => {
+                write!(f, "replenisher {:?}", ingredient)
+            }
+            WorkerId::ExternalReplenisher(ingredient) => {
+                write!(f, "external replenisher {:?}", ingredient)
+            }
+        }
+    }
+}
+
+/// Caida de un hilo supervisado
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Incident {
+    pub worker: WorkerId,
+    /// Error que devolvio el hilo o mensaje del panic
+    pub cause: String,
+    /// Momento de la caida segun el reloj de la cafetera
+    pub elapsed_ms: u64,
+    /// Pedido que se estaba preparando y se devolvio a la cola
+    pub requeued_order: Option<usize>,
+    /// Falso si se agoto el presupuesto de reinicios y el hilo no se volvio a iniciar
+    pub restarted: bool,
+}
+
+type Exit = thread::Result<Result<(), CoffeeMakerError>>;
+
+/// Inicia los hilos de los dispensers y reponedores y los reinicia si se caen, ya sea porque devolvieron un error
+/// o por un panic. Cada hilo se reinicia como mucho `restart_budget` veces. Las caidas quedan registradas en `incidents`.
+/// Cada hilo avisa por un canal cuando termina, asi el supervisor no depende del orden en el que se esperan
+pub struct Supervisor {
+    restart_budget: usize,
+    workers: HashMap<WorkerId, Arc<dyn Worker>>,
+    handles: HashMap<WorkerId, JoinHandle<()>>,
+    running: HashSet<WorkerId>,
+    restarts: HashMap<WorkerId, usize>,
+    exits_sender: Sender<(WorkerId, Exit)>,
+    exits: Receiver<(WorkerId, Exit)>,
+    incidents: Arc<Mutex<Vec<Incident>>>,
+    first_failure: Option<CoffeeMakerError>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Supervisor {
+    pub fn new(
+        restart_budget: usize,
+        incidents: Arc<Mutex<Vec<Incident>>>,
+        clock: Arc<dyn Clock>,
+    ) -> Supervisor {
+        let (exits_sender, exits) = mpsc::channel();
+        Supervisor {
+            restart_budget,
+            workers: HashMap::new(),
+            handles: HashMap::new(),
+            running: HashSet::new(),
+            restarts: HashMap::new(),
+            exits_sender,
+            exits,
+            incidents,
+            first_failure: None,
+            clock,
+        }
+    }
+
+    /// Inicia el hilo de `worker` y lo supervisa a partir de ahora
+    pub fn spawn(&mut self, id: WorkerId, worker: Arc<dyn Worker>) {
+        self.workers.insert(id, worker);
+        self.start(id);
+    }
+
+    fn start(&mut self, id: WorkerId) {
+        let Some(worker) = self.workers.get(&id).cloned() else {
+            return;
+        };
+        let exits = self.exits_sender.clone();
+        let spawned = thread::Builder::new().name(id.to_string()).spawn(move || {
+            let exit = panic::catch_unwind(AssertUnwindSafe(|| worker.work()));
+            if exits.send((id, exit)).is_err() {
+                error!("[SUPERVISOR] {} could not report that it finished", id);
+            }
+        });
+        match spawned {
+            Ok(handle) => {
+                self.handles.insert(id, handle);
+                self.running.insert(id);
+            }
+            Err(err) => error!("[SUPERVISOR] Error starting {}: {:?}", id, err),
+        }
+    }
+
+    /// Espera a que finalicen los hilos que cumplen `group`. Mientras tanto reinicia a cualquier hilo
+    /// supervisado que se caiga, sea o no del grupo
+    pub fn wait_for(&mut self, group: impl Fn(&WorkerId) -> bool) {
+        while self.running.iter().any(&group) {
+            let Ok((id, exit)) = self.exits.recv() else {
+                return;
+            };
+            self.running.remove(&id);
+            if let Some(handle) = self.handles.remove(&id) {
+                if handle.join().is_err() {
+                    error!("[SUPERVISOR] Error joining {}", id);
+                }
+            }
+            let cause = match exit {
+                Ok(Ok(())) => continue,
+                Ok(Err(err)) => err.to_string(),
+                Err(panic) => panic_message(panic.as_ref()),
+            };
+            self.handle_crash(id, cause);
+        }
+    }
+
+    /// Espera a todos los hilos supervisados
+    pub fn wait_for_all(&mut self) {
+        self.wait_for(|_| true);
+    }
+
+    /// Devuelve el primer hilo que se quedo sin reinicios, con el motivo de su ultima caida
+    pub fn first_failure(&mut self) -> Option<CoffeeMakerError> {
+        self.first_failure.take()
+    }
+
+    fn handle_crash(&mut self, id: WorkerId, cause: String) {
+        let Some(worker) = self.workers.get(&id).cloned() else {
+            return;
+        };
+        let requeued_order = worker.recover().unwrap_or_else(|err| {
+            error!("[SUPERVISOR] Error recovering {}: {}", id, err);
+            None
+        });
+        let restarts = self.restarts.entry(id).or_insert(0);
+        let restarted = *restarts < self.restart_budget;
+        if restarted {
+            *restarts += 1;
+            warn!(
+                "[SUPERVISOR] {} crashed ({}), restarting it ({} of {})",
+                id, cause, restarts, self.restart_budget
+            );
+        } else {
+            error!(
+                "[SUPERVISOR] {} crashed ({}) and ran out of restarts",
+                id, cause
+            );
+            worker.give_up();
+            self.first_failure
+                .get_or_insert_with(|| CoffeeMakerError::WorkerGaveUp {
+                    worker: id.to_string(),
+                    cause: cause.clone(),
+                });
+        }
+        let incident = Incident {
+            worker: id,
+            cause,
+            elapsed_ms: self.clock.now().as_millis() as u64,
+            requeued_order,
+            restarted,
+        };
+        match self.incidents.lock() {
+            Ok(mut incidents) => incidents.push(incident),
+            Err(_) => error!("[SUPERVISOR] Error recording the incident of {}", id),
+        }
+        if restarted {
+            self.start(id);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return format!("panic: {}", message);
+    }
+    if let Some(message) = panic.downcast_ref::<String>() {
+        return format!("panic: {}", message);
+    }
+    String::from("panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::clock::VirtualClock;
+
+    use super::*;
+
+    /// Se cae las primeras `crashes` veces que trabaja
+    struct FlakyWorker {
+        crashes: usize,
+        runs: AtomicUsize,
+        recoveries: AtomicUsize,
+        gave_up: Mutex<bool>,
+    }
+
+    impl FlakyWorker {
+        fn new(crashes: usize) -> FlakyWorker {
+            FlakyWorker {
+                crashes,
+                runs: AtomicUsize::new(0),
+                recoveries: AtomicUsize::new(0),
+                gave_up: Mutex::new(false),
+            }
+        }
+    }
+
+    impl Worker for FlakyWorker {
+        fn work(&self) -> Result<(), CoffeeMakerError> {
+            let run = self.runs.fetch_add(1, Ordering::SeqCst);
+            if run >= self.crashes {
+                return Ok(());
+            }
+            if run.is_multiple_of(2) {
+                panic!("crash {}", run);
+            }
+            Err(CoffeeMakerError::LockError)
+        }
+
+        fn recover(&self) -> Result<Option<usize>, CoffeeMakerError> {
+            Ok(Some(self.recoveries.fetch_add(1, Ordering::SeqCst)))
+        }
+
+        fn give_up(&self) {
+            *self.gave_up.lock().expect("Error in test") = true;
+        }
+    }
+
+    fn supervisor(restart_budget: usize) -> (Supervisor, Arc<Mutex<Vec<Incident>>>) {
+        let incidents = Arc::new(Mutex::new(Vec::new()));
+        let supervisor = Supervisor::new(
+            restart_budget,
+            incidents.clone(),
+            Arc::new(VirtualClock::new()),
+        );
+        (supervisor, incidents)
+    }
+
+    #[test]
+    fn should_restart_a_worker_until_it_finishes() {
+        let (mut supervisor, incidents) = supervisor(3);
+        let worker = Arc::new(FlakyWorker::new(2));
+        supervisor.spawn(WorkerId::Dispenser(0), worker.clone());
+        supervisor.wait_for_all();
+
+        assert_eq!(3, worker.runs.load(Ordering::SeqCst));
+        assert!(!*worker.gave_up.lock().expect("Error in test"));
+        let incidents = incidents.lock().expect("Error in test");
+        assert_eq!(2, incidents.len());
+        assert_eq!("panic: crash 0", incidents[0].cause);
+        assert_eq!("a lock was poisoned", incidents[1].cause);
+        assert_eq!(Some(1), incidents[1].requeued_order);
+        assert!(incidents.iter().all(|incident| incident.restarted));
+    }
+
+    #[test]
+    fn should_give_up_after_the_restart_budget() {
+        let (mut supervisor, incidents) = supervisor(1);
+        let worker = Arc::new(FlakyWorker::new(5));
+        supervisor.spawn(
+            WorkerId::ExternalReplenisher(Ingredient::HotWater),
+            worker.clone(),
+        );
+        supervisor.wait_for_all();
+
+        assert_eq!(2, worker.runs.load(Ordering::SeqCst));
+        assert!(*worker.gave_up.lock().expect("Error in test"));
+        assert_eq!(
+            Some(CoffeeMakerError::WorkerGaveUp {
+                worker: String::from("external replenisher HotWater"),
+                cause: String::from("a lock was poisoned"),
+            }),
+            supervisor.first_failure()
+        );
+        let incidents = incidents.lock().expect("Error in test");
+        assert_eq!(
+            vec![true, false],
+            incidents
+                .iter()
+                .map(|incident| incident.restarted)
+                .collect::<Vec<bool>>()
+        );
+    }
+
+    #[test]
+    fn should_keep_restarting_other_workers_while_waiting_for_a_group() {
+        let (mut supervisor, incidents) = supervisor(3);
+        let replenisher = Arc::new(FlakyWorker::new(1));
+        let dispenser = Arc::new(FlakyWorker::new(0));
+        supervisor.spawn(
+            WorkerId::ContainerReplenisher(Ingredient::GroundCoffee),
+            replenisher.clone(),
+        );
+        supervisor.spawn(WorkerId::Dispenser(0), dispenser);
+        supervisor.wait_for(|worker| matches!(worker, WorkerId::ContainerReplenisher(_)));
+
+        assert_eq!(2, replenisher.runs.load(Ordering::SeqCst));
+        assert_eq!(1, incidents.lock().expect("Error in test").len());
+        supervisor.wait_for_all();
+    }
+}